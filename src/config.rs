@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::str::FromStr;
@@ -11,7 +12,7 @@ pub enum HttpMethod {
     Post,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum RecordType {
     A,
@@ -55,6 +56,12 @@ impl FromStr for RecordType {
 pub enum ConfigDnsProvider {
     #[serde(rename = "cloudflare")]
     Cloudflare,
+    #[serde(rename = "route53")]
+    Route53,
+    #[serde(rename = "gcloud")]
+    GCloud,
+    #[serde(rename = "ovh")]
+    Ovh,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,6 +71,121 @@ pub struct DnsProviderConfig {
     pub project_id: Option<String>,
     pub rate_limit: Option<RateLimit>,
     pub retry_config: Option<RetryConfig>,
+    pub route53: Option<Route53>,
+    pub gcloud: Option<GCloud>,
+    pub ovh: Option<Ovh>,
+}
+
+/// AWS Route 53 settings. Without keys, credentials are read from the
+/// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment variables, then
+/// from the shared credentials file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route53 {
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    /// Profile of the shared credentials file, defaults to `AWS_PROFILE` or
+    /// `default`
+    pub profile: Option<String>,
+    /// Hosted zone ID, looked up from the domain name when not set
+    pub hosted_zone_id: Option<String>,
+    /// API endpoint, e.g. a local mock for testing
+    #[serde(default = "default_route53_endpoint")]
+    pub endpoint: String,
+    /// Region requests are signed for
+    #[serde(default = "default_route53_region")]
+    pub region: String,
+    /// How long to wait for a change to reach all Route 53 servers, changes
+    /// are not waited for when zero
+    #[serde(default = "default_route53_change_timeout", with = "duration_serde")]
+    pub change_timeout_in_seconds: Duration,
+}
+
+impl Default for Route53 {
+    fn default() -> Self {
+        Self {
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            profile: None,
+            hosted_zone_id: None,
+            endpoint: default_route53_endpoint(),
+            region: default_route53_region(),
+            change_timeout_in_seconds: default_route53_change_timeout(),
+        }
+    }
+}
+
+fn default_route53_endpoint() -> String {
+    "https://route53.amazonaws.com".to_string()
+}
+
+fn default_route53_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_route53_change_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// Google Cloud DNS settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GCloud {
+    /// Service account JSON key, defaults to `GOOGLE_APPLICATION_CREDENTIALS`
+    pub credentials_file: Option<String>,
+    /// Managed zone name, looked up from the domain name when not set
+    pub managed_zone: Option<String>,
+    /// Cloud DNS API endpoint, e.g. a local stand-in for testing
+    #[serde(default = "default_gcloud_endpoint")]
+    pub endpoint: String,
+    /// OAuth2 token endpoint, defaults to the `token_uri` of the key
+    pub token_url: Option<String>,
+}
+
+impl Default for GCloud {
+    fn default() -> Self {
+        Self {
+            credentials_file: None,
+            managed_zone: None,
+            endpoint: default_gcloud_endpoint(),
+            token_url: None,
+        }
+    }
+}
+
+fn default_gcloud_endpoint() -> String {
+    "https://dns.googleapis.com".to_string()
+}
+
+/// OVHcloud settings. Without keys, credentials are read from the
+/// `OVH_APPLICATION_KEY`, `OVH_APPLICATION_SECRET` and `OVH_CONSUMER_KEY`
+/// environment variables
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ovh {
+    pub application_key: Option<String>,
+    pub application_secret: Option<String>,
+    pub consumer_key: Option<String>,
+    /// API region (`ovh-eu`, `ovh-ca` or `ovh-us`) or endpoint URL
+    #[serde(default = "default_ovh_endpoint")]
+    pub endpoint: String,
+}
+
+impl Default for Ovh {
+    fn default() -> Self {
+        Self {
+            application_key: None,
+            application_secret: None,
+            consumer_key: None,
+            endpoint: default_ovh_endpoint(),
+        }
+    }
+}
+
+fn default_ovh_endpoint() -> String {
+    "ovh-eu".to_string()
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -84,6 +206,7 @@ pub struct RetryConfig {
 pub enum ConfigError {
     IoError(std::io::Error),
     ParseError(serde_yaml::Error),
+    ValidationError(String),
 }
 
 impl fmt::Display for ConfigError {
@@ -91,6 +214,7 @@ impl fmt::Display for ConfigError {
         match self {
             ConfigError::IoError(e) => write!(f, "IO error: {}", e),
             ConfigError::ParseError(e) => write!(f, "Parse error: {}", e),
+            ConfigError::ValidationError(e) => write!(f, "Validation error: {}", e),
         }
     }
 }
@@ -115,7 +239,64 @@ impl From<serde_yaml::Error> for ConfigError {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default)]
     pub dns_records: Vec<DnsRecordConfig>,
+    /// Freebox LAN hosts published as `<host>.<domain>` records
+    #[serde(default)]
+    pub lan_hosts: Vec<LanHostsConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LanHostsConfig {
+    pub name: String,
+    pub freebox: Freebox,
+    /// MAC addresses of the hosts to publish
+    #[serde(default)]
+    pub macs: Vec<String>,
+    /// Regex matched against host names, hosts to publish
+    pub name_pattern: Option<String>,
+    /// Publish A records with the host IPv4 address
+    #[serde(default = "default_true")]
+    pub ipv4: bool,
+    /// Publish AAAA records with the host global IPv6 address
+    #[serde(default)]
+    pub ipv6: bool,
+    /// Delete the records of hosts which are no longer active on the LAN
+    #[serde(default)]
+    pub remove_absent: bool,
+    #[serde(with = "duration_serde")]
+    pub check_interval_in_seconds: Duration,
+    pub domain: LanHostsDomain,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LanHostsDomain {
+    pub provider: ConfigDnsProvider,
+    pub domain_name: String,
+    pub record_ttl: u32,
+    pub api_key: Option<String>,
+    /// Google Cloud project, defaults to the one of the service account key
+    pub project_id: Option<String>,
+    pub route53: Option<Route53>,
+    pub gcloud: Option<GCloud>,
+    pub ovh: Option<Ovh>,
+}
+
+impl LanHostsDomain {
+    pub fn provider_config(&self) -> DnsProviderConfig {
+        DnsProviderConfig {
+            provider: self.provider.clone(),
+            api_key: self.api_key.clone(),
+            project_id: self.project_id.clone(),
+            rate_limit: None,
+            retry_config: None,
+            route53: self.route53.clone(),
+            gcloud: self.gcloud.clone(),
+            ovh: self.ovh.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -124,28 +305,627 @@ pub struct DnsRecordConfig {
     pub name: String,
     pub source: Source,
     pub domain: Domain,
+    /// Publish the source IPv6 prefix followed by this host part instead of
+    /// the source address, for hosts behind the router
+    pub ipv6_host: Option<Ipv6Host>,
+    /// What to do while the source is on a backup connection (e.g. Freebox
+    /// 4G backup), the address is published as usual when not set
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    pub backup: Option<BackupPolicy>,
+    /// SRV record published with the record, e.g. to advertise a port inside
+    /// the range of a shared IPv4 address
+    pub srv: Option<SrvRecord>,
+}
+
+impl DnsRecordConfig {
+    /// Record name and TTL to publish on the main or backup connection,
+    /// `None` while the record is left untouched
+    pub fn published_record(&self, on_backup: bool) -> Option<(&str, u32)> {
+        match self.backup.as_ref().filter(|_| on_backup) {
+            Some(BackupPolicy::Skip) => None,
+            Some(BackupPolicy::AlternateRecord(record_name)) => {
+                Some((record_name, self.domain.record_ttl))
+            }
+            Some(BackupPolicy::LowerTtl(ttl)) => Some((&self.domain.record_name, *ttl)),
+            None => Some((&self.domain.record_name, self.domain.record_ttl)),
+        }
+    }
+}
+
+/// SRV record published next to an A or AAAA record, pointing to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SrvRecord {
+    /// Service and protocol labels, e.g. `_minecraft._tcp`
+    pub service: String,
+    /// Must be inside the port range of a shared IPv4 address
+    pub port: u16,
+    #[serde(default)]
+    pub priority: u16,
+    #[serde(default)]
+    pub weight: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupPolicy {
+    /// Keep the record as it is
+    Skip,
+    /// Publish the address to this record name instead
+    AlternateRecord(String),
+    /// Publish the address with this TTL
+    LowerTtl(u32),
+}
+
+/// Static host part of an address built from a delegated IPv6 prefix, with
+/// either `interface_id` or `mac`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ipv6Host {
+    /// Interface identifier, e.g. `::1234:5678:9abc:def0`
+    pub interface_id: Option<String>,
+    /// MAC address the modified EUI-64 interface identifier is derived from
+    pub mac: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Source {
     pub freebox: Option<Freebox>,
+    pub http: Option<Http>,
+    pub interface: Option<Interface>,
+    pub gateway: Option<Gateway>,
+    pub stun: Option<Stun>,
+    pub dns: Option<DnsQuery>,
+    pub exec: Option<Exec>,
+    pub livebox: Option<Livebox>,
+    pub bbox: Option<Bbox>,
+    pub sfrbox: Option<SfrBox>,
+    pub fritzbox: Option<FritzBox>,
+    pub opnsense: Option<OpnSense>,
+    pub pfsense: Option<PfSense>,
+    pub openwrt: Option<OpenWrt>,
+    pub routeros: Option<RouterOs>,
+    pub unifi: Option<Unifi>,
+    /// Additional sources, combined with the ones above using `strategy`
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    pub sources: Option<Vec<SourceProvider>>,
+    #[serde(default)]
+    pub strategy: SourceStrategy,
+    /// Minimum number of sources agreeing on an address with the `quorum`
+    /// strategy, defaults to a majority
+    pub quorum: Option<usize>,
     #[serde(with = "duration_serde")]
     pub check_interval_in_seconds: Duration,
 }
 
+impl Source {
+    /// All configured sources: the ones set directly, in the order of the
+    /// fields above, then `sources` in list order
+    pub fn providers(&self) -> Vec<SourceProvider> {
+        let mut providers = Vec::new();
+        if let Some(freebox) = &self.freebox {
+            providers.push(SourceProvider::Freebox(freebox.clone()));
+        }
+        if let Some(http) = &self.http {
+            providers.push(SourceProvider::Http(http.clone()));
+        }
+        if let Some(interface) = &self.interface {
+            providers.push(SourceProvider::Interface(interface.clone()));
+        }
+        if let Some(gateway) = &self.gateway {
+            providers.push(SourceProvider::Gateway(gateway.clone()));
+        }
+        if let Some(stun) = &self.stun {
+            providers.push(SourceProvider::Stun(stun.clone()));
+        }
+        if let Some(dns) = &self.dns {
+            providers.push(SourceProvider::Dns(dns.clone()));
+        }
+        if let Some(exec) = &self.exec {
+            providers.push(SourceProvider::Exec(exec.clone()));
+        }
+        if let Some(livebox) = &self.livebox {
+            providers.push(SourceProvider::Livebox(livebox.clone()));
+        }
+        if let Some(bbox) = &self.bbox {
+            providers.push(SourceProvider::Bbox(bbox.clone()));
+        }
+        if let Some(sfrbox) = &self.sfrbox {
+            providers.push(SourceProvider::SfrBox(sfrbox.clone()));
+        }
+        if let Some(fritzbox) = &self.fritzbox {
+            providers.push(SourceProvider::FritzBox(fritzbox.clone()));
+        }
+        if let Some(opnsense) = &self.opnsense {
+            providers.push(SourceProvider::OpnSense(opnsense.clone()));
+        }
+        if let Some(pfsense) = &self.pfsense {
+            providers.push(SourceProvider::PfSense(pfsense.clone()));
+        }
+        if let Some(openwrt) = &self.openwrt {
+            providers.push(SourceProvider::OpenWrt(openwrt.clone()));
+        }
+        if let Some(routeros) = &self.routeros {
+            providers.push(SourceProvider::RouterOs(routeros.clone()));
+        }
+        if let Some(unifi) = &self.unifi {
+            providers.push(SourceProvider::Unifi(unifi.clone()));
+        }
+        providers.extend(self.sources.iter().flatten().cloned());
+        providers
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceProvider {
+    Freebox(Freebox),
+    Http(Http),
+    Interface(Interface),
+    Gateway(Gateway),
+    Stun(Stun),
+    Dns(DnsQuery),
+    Exec(Exec),
+    Livebox(Livebox),
+    Bbox(Bbox),
+    SfrBox(SfrBox),
+    FritzBox(FritzBox),
+    OpnSense(OpnSense),
+    PfSense(PfSense),
+    OpenWrt(OpenWrt),
+    RouterOs(RouterOs),
+    Unifi(Unifi),
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStrategy {
+    /// Use the first source returning an address, in `providers` order
+    #[default]
+    FirstSuccess,
+    /// Query all sources concurrently and require `quorum` of them to agree
+    Quorum,
+}
+
+impl fmt::Display for SourceStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceStrategy::FirstSuccess => write!(f, "first_success"),
+            SourceStrategy::Quorum => write!(f, "quorum"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Freebox {
     #[serde(default = "default_freebox_url")]
     pub url: Option<String>,
     pub token: String,
+    /// Application id the token was granted to
+    #[serde(default = "default_freebox_app_id")]
+    pub app_id: String,
+    /// Find the Freebox on the local network with mDNS instead of using `url`
+    #[serde(default)]
+    pub mdns: bool,
+    /// Talk to the Freebox over HTTPS on its `api_domain` and `https_port`
+    #[serde(default)]
+    pub https: bool,
+    /// PEM file with the only CAs trusted for HTTPS, instead of the bundled
+    /// Freebox root CA
+    pub ca_file: Option<String>,
+    /// Port forwards kept in sync on the Freebox on each check
+    #[serde(default)]
+    pub port_forwards: Vec<FreeboxPortForward>,
+    /// What to do with a shared IPv4 address, only reachable on a port range
+    #[serde(default)]
+    pub shared_ipv4: SharedIpv4Policy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SharedIpv4Policy {
+    /// Fail the lookup
+    #[default]
+    Refuse,
+    /// Publish the address with a warning
+    Warn,
+    /// Publish the address
+    Allow,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FreeboxPortForward {
+    pub protocol: PortForwardProtocol,
+    pub wan_port_start: u16,
+    /// Last port of the WAN range, defaults to `wan_port_start`
+    pub wan_port_end: Option<u16>,
+    pub lan_ip: String,
+    /// LAN port of `wan_port_start`, the range keeps the same offset
+    pub lan_port: u16,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortForwardProtocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for PortForwardProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortForwardProtocol::Tcp => write!(f, "tcp"),
+            PortForwardProtocol::Udp => write!(f, "udp"),
+        }
+    }
 }
 
 fn default_freebox_url() -> Option<String> {
     Some("http://mafreebox.freebox.fr".to_string())
 }
 
+pub fn default_freebox_app_id() -> String {
+    "fr.freebox.cddns".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Http {
+    pub url: String,
+    #[serde(default = "default_http_method")]
+    pub method: HttpMethod,
+    pub body: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+    /// JSON pointer (RFC 6901) to the address in a JSON response, e.g. `/ip`
+    pub json_pointer: Option<String>,
+    /// Regex matching the address; the first capture group is used if any
+    pub regex: Option<String>,
+    #[serde(default = "default_http_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Interface {
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub skip_link_local: bool,
+    #[serde(default = "default_true")]
+    pub skip_ula: bool,
+    #[serde(default = "default_true")]
+    pub skip_deprecated: bool,
+    /// Skip RFC 4941 privacy (temporary) IPv6 addresses
+    #[serde(default = "default_true")]
+    pub skip_temporary: bool,
+    /// Prefer a stable SLAAC address built from the EUI-64 of the interface
+    #[serde(default)]
+    pub prefer_eui64: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GatewayProtocol {
+    Upnp,
+    NatPmp,
+    Pcp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Gateway {
+    pub protocol: GatewayProtocol,
+    /// UPnP IGD device description URL, discovered with SSDP when not set
+    pub description_url: Option<String>,
+    /// NAT-PMP/PCP gateway address (`ip` or `ip:port`), defaults to the default gateway
+    pub address: Option<String>,
+    #[serde(default = "default_gateway_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+}
+
+fn default_gateway_timeout() -> Duration {
+    Duration::from_secs(3)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stun {
+    /// STUN servers as `host:port`, tried in order
+    #[serde(default = "default_stun_servers")]
+    pub servers: Vec<String>,
+    #[serde(default = "default_stun_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+}
+
+fn default_stun_servers() -> Vec<String> {
+    vec![
+        "stun.cloudflare.com:3478".to_string(),
+        "stun.l.google.com:19302".to_string(),
+    ]
+}
+
+fn default_stun_timeout() -> Duration {
+    Duration::from_secs(3)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsQuery {
+    /// Resolver to query as `host` or `host:port`, e.g. `resolver1.opendns.com`
+    pub resolver: String,
+    /// Name returning the client address, e.g. `myip.opendns.com`
+    pub query_name: String,
+    /// `A`, `AAAA` or `TXT`, defaults to `A` or `AAAA` depending on the record type
+    pub query_type: Option<RecordType>,
+    #[serde(default = "default_dns_query_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+}
+
+fn default_dns_query_timeout() -> Duration {
+    Duration::from_secs(3)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Exec {
+    /// Program and its arguments, not run through a shell
+    pub command: Vec<String>,
+    pub env: Option<HashMap<String, String>>,
+    pub working_dir: Option<String>,
+    #[serde(default = "default_exec_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+}
+
+fn default_exec_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Livebox {
+    #[serde(default = "default_livebox_url")]
+    pub url: String,
+    #[serde(default = "default_livebox_username")]
+    pub username: String,
+    pub password: String,
+    /// PEM file with the only CAs trusted for HTTPS
+    pub ca_file: Option<String>,
+    #[serde(default = "default_box_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+    /// Retries of a request failing on the network or timing out
+    #[serde(default = "default_router_retries")]
+    pub retries: u32,
+}
+
+fn default_livebox_url() -> String {
+    "http://192.168.1.1".to_string()
+}
+
+fn default_livebox_username() -> String {
+    "admin".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bbox {
+    #[serde(default = "default_bbox_url")]
+    pub url: String,
+    /// Administration password, only needed when the API requires a login
+    pub password: Option<String>,
+    /// PEM file with the only CAs trusted for HTTPS
+    pub ca_file: Option<String>,
+    #[serde(default = "default_box_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+    /// Retries of a request failing on the network or timing out
+    #[serde(default = "default_router_retries")]
+    pub retries: u32,
+}
+
+fn default_bbox_url() -> String {
+    "https://mabbox.bytel.fr".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SfrBox {
+    #[serde(default = "default_sfrbox_url")]
+    pub url: String,
+    /// PEM file with the only CAs trusted for HTTPS
+    pub ca_file: Option<String>,
+    #[serde(default = "default_box_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+    /// Retries of a request failing on the network or timing out
+    #[serde(default = "default_router_retries")]
+    pub retries: u32,
+}
+
+fn default_sfrbox_url() -> String {
+    "http://192.168.1.1".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FritzBox {
+    /// TR-064 endpoint
+    #[serde(default = "default_fritzbox_url")]
+    pub url: String,
+    /// Fritz!Box user, sent with HTTP digest authentication when the box asks for it
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// PEM file with the only CAs trusted for HTTPS
+    pub ca_file: Option<String>,
+    #[serde(default = "default_box_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+    /// Retries of a request failing on the network or timing out
+    #[serde(default = "default_router_retries")]
+    pub retries: u32,
+}
+
+fn default_fritzbox_url() -> String {
+    "http://fritz.box:49000".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OpnSense {
+    #[serde(default = "default_router_https_url")]
+    pub url: String,
+    pub api_key: String,
+    pub api_secret: String,
+    /// WAN interface, as its device name (`igb0`) or description (`WAN`)
+    #[serde(default = "default_wan_interface")]
+    pub interface: String,
+    /// PEM file with the only CAs trusted for HTTPS
+    pub ca_file: Option<String>,
+    #[serde(default = "default_box_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+    /// Retries of a request failing on the network or timing out
+    #[serde(default = "default_router_retries")]
+    pub retries: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PfSense {
+    #[serde(default = "default_router_https_url")]
+    pub url: String,
+    /// REST API package key
+    pub api_key: String,
+    /// WAN interface, as its name (`wan`), description (`WAN`) or device (`igb0`)
+    #[serde(default = "default_wan_interface")]
+    pub interface: String,
+    /// PEM file with the only CAs trusted for HTTPS
+    pub ca_file: Option<String>,
+    #[serde(default = "default_box_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+    /// Retries of a request failing on the network or timing out
+    #[serde(default = "default_router_retries")]
+    pub retries: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OpenWrt {
+    #[serde(default = "default_openwrt_url")]
+    pub url: String,
+    #[serde(default = "default_openwrt_username")]
+    pub username: String,
+    pub password: String,
+    /// Logical interface holding the IPv4 address
+    #[serde(default = "default_wan_interface")]
+    pub interface: String,
+    /// Logical interface holding the IPv6 address and delegated prefix
+    #[serde(default = "default_openwrt_ipv6_interface")]
+    pub ipv6_interface: String,
+    /// PEM file with the only CAs trusted for HTTPS
+    pub ca_file: Option<String>,
+    #[serde(default = "default_box_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+    /// Retries of a request failing on the network or timing out
+    #[serde(default = "default_router_retries")]
+    pub retries: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouterOs {
+    #[serde(default = "default_routeros_url")]
+    pub url: String,
+    #[serde(default = "default_routeros_username")]
+    pub username: String,
+    pub password: String,
+    /// WAN interface, e.g. `ether1` or `pppoe-out1`
+    #[serde(default = "default_routeros_interface")]
+    pub interface: String,
+    /// PEM file with the only CAs trusted for HTTPS
+    pub ca_file: Option<String>,
+    #[serde(default = "default_box_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+    /// Retries of a request failing on the network or timing out
+    #[serde(default = "default_router_retries")]
+    pub retries: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Unifi {
+    /// Controller URL, e.g. `https://unifi:8443` or the console URL
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    #[serde(default = "default_unifi_site")]
+    pub site: String,
+    /// Controller running on UniFi OS (Dream Machine, Cloud Key Gen2+...)
+    #[serde(default)]
+    pub unifi_os: bool,
+    /// PEM file with the only CAs trusted for HTTPS
+    pub ca_file: Option<String>,
+    #[serde(default = "default_box_timeout", with = "duration_serde")]
+    pub timeout_in_seconds: Duration,
+    /// Retries of a request failing on the network or timing out
+    #[serde(default = "default_router_retries")]
+    pub retries: u32,
+}
+
+fn default_routeros_url() -> String {
+    "https://192.168.88.1".to_string()
+}
+
+fn default_routeros_username() -> String {
+    "admin".to_string()
+}
+
+fn default_routeros_interface() -> String {
+    "ether1".to_string()
+}
+
+fn default_unifi_site() -> String {
+    "default".to_string()
+}
+
+fn default_router_https_url() -> String {
+    "https://192.168.1.1".to_string()
+}
+
+fn default_wan_interface() -> String {
+    "wan".to_string()
+}
+
+fn default_openwrt_url() -> String {
+    "http://192.168.1.1".to_string()
+}
+
+fn default_openwrt_username() -> String {
+    "root".to_string()
+}
+
+fn default_openwrt_ipv6_interface() -> String {
+    "wan6".to_string()
+}
+
+fn default_router_retries() -> u32 {
+    2
+}
+
+fn default_box_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_http_method() -> HttpMethod {
+    HttpMethod::Get
+}
+
+fn default_http_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Domain {
@@ -157,6 +937,26 @@ pub struct Domain {
     pub api_key: Option<String>,
     pub email: Option<String>,
     pub zone_id: Option<String>,
+    /// Google Cloud project, defaults to the one of the service account key
+    pub project_id: Option<String>,
+    pub route53: Option<Route53>,
+    pub gcloud: Option<GCloud>,
+    pub ovh: Option<Ovh>,
+}
+
+impl Domain {
+    pub fn provider_config(&self) -> DnsProviderConfig {
+        DnsProviderConfig {
+            provider: self.provider.clone(),
+            api_key: self.api_key.clone(),
+            project_id: self.project_id.clone(),
+            rate_limit: None,
+            retry_config: None,
+            route53: self.route53.clone(),
+            gcloud: self.gcloud.clone(),
+            ovh: self.ovh.clone(),
+        }
+    }
 }
 
 // Duration serialization/deserialization helpers
@@ -185,6 +985,116 @@ impl Config {
     pub fn from_file(path: &str) -> Result<Self, ConfigError> {
         let contents = std::fs::read_to_string(path)?;
         let config: Config = serde_yaml::from_str(&contents)?;
+        config.validate()?;
         Ok(config)
     }
+
+    /// Checks serde can't express, across records
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for record in &self.dns_records {
+            if let Some(BackupPolicy::LowerTtl(ttl)) = record.backup
+                && !(60..=86400).contains(&ttl)
+            {
+                return Err(ConfigError::ValidationError(format!(
+                    "`lower_ttl` of {} must be between 60 and 86400 seconds",
+                    record.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the token of every `freebox` block using `app_id` in a configuration
+    /// file, returning the number of updated blocks. Comments are not preserved.
+    pub fn update_freebox_token(
+        path: &str,
+        app_id: &str,
+        token: &str,
+    ) -> Result<usize, ConfigError> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut document: serde_yaml::Value = serde_yaml::from_str(&contents)?;
+
+        let updated = set_freebox_token(&mut document, app_id, token);
+        if updated > 0 {
+            std::fs::write(path, serde_yaml::to_string(&document)?)?;
+        }
+
+        Ok(updated)
+    }
+}
+
+fn set_freebox_token(value: &mut serde_yaml::Value, app_id: &str, token: &str) -> usize {
+    match value {
+        serde_yaml::Value::Mapping(mapping) => mapping
+            .iter_mut()
+            .map(|(key, value)| match (key.as_str(), value) {
+                (Some("freebox"), serde_yaml::Value::Mapping(freebox)) => {
+                    let block_app_id = freebox
+                        .get("app_id")
+                        .and_then(|id| id.as_str())
+                        .map(|id| id.to_string())
+                        .unwrap_or_else(default_freebox_app_id);
+                    if block_app_id == app_id {
+                        freebox.insert("token".into(), token.into());
+                        1
+                    } else {
+                        0
+                    }
+                }
+                (_, value) => set_freebox_token(value, app_id, token),
+            })
+            .sum(),
+        serde_yaml::Value::Sequence(sequence) => sequence
+            .iter_mut()
+            .map(|value| set_freebox_token(value, app_id, token))
+            .sum(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(backup: &str) -> DnsRecordConfig {
+        serde_yaml::from_str(&format!(
+            r#"
+name: home
+source: {{check_interval_in_seconds: 60}}
+domain:
+  provider: cloudflare
+  domain_name: example.com
+  record_name: home
+  record_type: A
+  record_ttl: 3600
+{}
+"#,
+            backup
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn record_is_published_as_usual_without_policy() {
+        let record = record("");
+
+        assert_eq!(record.published_record(false), Some(("home", 3600)));
+        assert_eq!(record.published_record(true), Some(("home", 3600)));
+    }
+
+    #[test]
+    fn backup_policy_applies_only_on_backup() {
+        let skip = record("backup: skip");
+        assert_eq!(skip.published_record(false), Some(("home", 3600)));
+        assert_eq!(skip.published_record(true), None);
+
+        let alternate = record("backup:\n  alternate_record: home-4g");
+        assert_eq!(alternate.published_record(false), Some(("home", 3600)));
+        assert_eq!(alternate.published_record(true), Some(("home-4g", 3600)));
+
+        let lower_ttl = record("backup:\n  lower_ttl: 60");
+        assert_eq!(lower_ttl.published_record(false), Some(("home", 3600)));
+        assert_eq!(lower_ttl.published_record(true), Some(("home", 60)));
+    }
 }
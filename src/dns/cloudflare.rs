@@ -1,17 +1,21 @@
 use super::{
-    DnsError, DnsProvider, DnsRecordCloudflare, validate_record_data, validate_record_name,
+    DnsError, DnsProvider, DnsRecord, RecordData, validate_record_data, validate_record_name,
     validate_ttl,
 };
 use crate::config::RecordType;
 use async_trait::async_trait;
 use cloudflare::endpoints::dns::dns::{
-    CreateDnsRecord, ListDnsRecords, ListDnsRecordsParams, UpdateDnsRecord,
+    CreateDnsRecord, DeleteDnsRecord, ListDnsRecords, ListDnsRecordsParams, UpdateDnsRecord,
 };
 use cloudflare::endpoints::dns::dns::{CreateDnsRecordParams, DnsContent, UpdateDnsRecordParams};
 use cloudflare::endpoints::zones::zone::ListZones;
 use cloudflare::framework::{auth::Credentials, client::async_api::Client};
+use std::collections::HashMap;
 use tracing;
 
+// extension holding whether a record is proxied by Cloudflare
+const PROXIED_EXTENSION: &str = "proxied";
+
 pub struct CloudflareDns {
     client: Client,
 }
@@ -85,87 +89,103 @@ impl CloudflareDns {
         )))
     }
 
-    /// Check if a specific record exists with the correct content by filtering the API query
-    async fn find_matching_record(
+    /// Records of a name and type, the listing is filtered by name on the API side
+    async fn list_records(
         &self,
         zone_id: &str,
         record_name: &str,
         record_type: &RecordType,
-    ) -> Result<Option<String>, DnsError> {
-        tracing::debug!(
-            "Searching for existing record: {} ({})",
-            record_name,
-            Self::record_type_to_string(record_type)
-        );
+    ) -> Result<Vec<cloudflare::endpoints::dns::dns::DnsRecord>, DnsError> {
+        let normalized_name = Self::ensure_trailing_dot(record_name);
+        let target_type = Self::record_type_to_string(record_type);
 
-        // Create parameters that filter by record name and type to avoid TLSA records
+        // the type filter of the API also matches the content, so the type is
+        // checked here
         let params = ListDnsRecordsParams {
-            per_page: Some(20),
+            name: Some(normalized_name.trim_end_matches('.').to_string()),
+            per_page: Some(100),
             ..Default::default()
         };
-
-        // Try to make a targeted request
-        match self
+        let records = self
             .client
             .request(&ListDnsRecords {
                 zone_identifier: zone_id,
                 params,
             })
             .await
+            .map_err(|e| {
+                DnsError::ApiError(format!(
+                    "Failed to list DNS records of {}: {}",
+                    record_name, e
+                ))
+            })?;
+
+        Ok(records
+            .result
+            .into_iter()
+            .filter(|cf_record| {
+                Self::ensure_trailing_dot(&cf_record.name) == normalized_name
+                    && Self::get_record_type_from_content(&cf_record.content) == target_type
+            })
+            .collect())
+    }
+
+    /// ID of the existing record of a name and type
+    async fn find_matching_record(
+        &self,
+        zone_id: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<Option<String>, DnsError> {
+        tracing::debug!(
+            "Searching for existing record: {} ({})",
+            record_name,
+            Self::record_type_to_string(record_type)
+        );
+
+        match self
+            .list_records(zone_id, record_name, record_type)
+            .await?
+            .into_iter()
+            .next()
         {
-            Ok(records) => {
-                let normalized_name = Self::ensure_trailing_dot(record_name);
-                let target_type = Self::record_type_to_string(record_type);
-
-                for cf_record in records.result {
-                    if Self::ensure_trailing_dot(&cf_record.name) == normalized_name {
-                        let cf_record_type = Self::get_record_type_from_content(&cf_record.content);
-                        if cf_record_type == target_type {
-                            tracing::debug!("Found matching record with ID: {}", cf_record.id);
-                            return Ok(Some(cf_record.id));
-                        }
-                    }
-                }
-                tracing::debug!("No matching record found");
-                Ok(None)
+            Some(cf_record) => {
+                tracing::debug!("Found matching record with ID: {}", cf_record.id);
+                Ok(Some(cf_record.id))
             }
-            Err(e) => {
-                tracing::warn!(
-                    "Failed to search for existing record due to API error: {:?}",
-                    e
-                );
-                // If we can't list records due to TLSA issues, return None (assume record doesn't exist)
+            None => {
+                tracing::debug!("No matching record found");
                 Ok(None)
             }
         }
     }
 
     /// Update record with proper existence checking
-    async fn update_record_smart(
-        &self,
-        zone: &str,
-        record: &DnsRecordCloudflare,
-    ) -> Result<(), DnsError> {
+    async fn update_record_smart(&self, zone: &str, record: &DnsRecord) -> Result<(), DnsError> {
         self.validate_record(record)?;
 
         let zone_id = self.get_zone_id(zone).await?;
+        let proxied = record
+            .extensions
+            .get(PROXIED_EXTENSION)
+            .and_then(serde_json::Value::as_bool);
 
         // Check if record already exists with same name and type
         match self
-            .find_matching_record(&zone_id, &record.name, &record.record_type)
+            .find_matching_record(&zone_id, &record.name, &record.record_type())
             .await?
         {
             Some(record_id) => {
                 // Record exists, update it
                 tracing::info!("Updating existing DNS record: {}", record.name);
 
-                let content = self.create_dns_content(record)?;
+                let content = Self::dns_content(&record.data);
 
                 let params = UpdateDnsRecordParams {
                     name: &Self::ensure_trailing_dot(&record.name),
                     content,
                     ttl: Some(record.ttl),
-                    proxied: None,
+                    proxied,
                 };
 
                 self.client
@@ -183,14 +203,14 @@ impl CloudflareDns {
                 // Record doesn't exist, create it
                 tracing::info!("Creating new DNS record: {}", record.name);
 
-                let content = self.create_dns_content(record)?;
+                let content = Self::dns_content(&record.data);
 
                 let params = CreateDnsRecordParams {
                     name: &Self::ensure_trailing_dot(&record.name),
                     content,
                     ttl: Some(record.ttl),
                     priority: None,
-                    proxied: None,
+                    proxied,
                 };
 
                 self.client
@@ -208,140 +228,138 @@ impl CloudflareDns {
         Ok(())
     }
 
-    /// Helper to create DNS content from our record
-    fn create_dns_content(&self, record: &DnsRecordCloudflare) -> Result<DnsContent, DnsError> {
-        match &record.record_type {
-            RecordType::A => {
-                let ip: std::net::Ipv4Addr = record
-                    .content
-                    .parse()
-                    .map_err(|_| DnsError::ValidationError("Invalid IPv4 address".to_string()))?;
-                Ok(DnsContent::A { content: ip })
-            }
-            RecordType::Aaaa => {
-                let ip: std::net::Ipv6Addr = record
-                    .content
-                    .parse()
-                    .map_err(|_| DnsError::ValidationError("Invalid IPv6 address".to_string()))?;
-                Ok(DnsContent::AAAA { content: ip })
-            }
-            RecordType::Cname => Ok(DnsContent::CNAME {
-                content: Self::ensure_trailing_dot(&record.content),
-            }),
-            RecordType::Mx => {
-                let parts: Vec<&str> = record.content.split_whitespace().collect();
-                if parts.len() != 2 {
-                    return Err(DnsError::ValidationError(
-                        "MX record must have format: priority target".to_string(),
-                    ));
+    /// Cloudflare content of the record data
+    fn dns_content(data: &RecordData) -> DnsContent {
+        match data {
+            RecordData::A { address } => DnsContent::A { content: *address },
+            RecordData::Aaaa { address } => DnsContent::AAAA { content: *address },
+            RecordData::Cname { target } => DnsContent::CNAME {
+                content: target.clone(),
+            },
+            RecordData::Mx { priority, exchange } => DnsContent::MX {
+                content: exchange.clone(),
+                priority: *priority,
+            },
+            // a single string is sent as is, several as quoted strings
+            RecordData::Txt { strings } => DnsContent::TXT {
+                content: match strings.as_slice() {
+                    [string] => string.clone(),
+                    _ => data.to_string(),
+                },
+            },
+            RecordData::Srv { .. } => DnsContent::SRV {
+                content: data.to_string(),
+            },
+        }
+    }
+
+    /// Record data of a Cloudflare content
+    fn record_data(content: &DnsContent) -> Result<RecordData, DnsError> {
+        Ok(match content {
+            DnsContent::A { content } => RecordData::A { address: *content },
+            DnsContent::AAAA { content } => RecordData::Aaaa { address: *content },
+            DnsContent::CNAME { content } => RecordData::Cname {
+                target: Self::ensure_trailing_dot(content),
+            },
+            DnsContent::MX { content, priority } => RecordData::Mx {
+                priority: *priority,
+                exchange: Self::ensure_trailing_dot(content),
+            },
+            DnsContent::TXT { content } => RecordData::parse(&RecordType::Txt, content)?,
+            DnsContent::SRV { content } => {
+                let mut data = RecordData::parse(&RecordType::Srv, content)?;
+                if let RecordData::Srv { target, .. } = &mut data {
+                    *target = Self::ensure_trailing_dot(target);
                 }
-                let priority = parts[0]
-                    .parse::<u16>()
-                    .map_err(|_| DnsError::ValidationError("Invalid MX priority".to_string()))?;
-                Ok(DnsContent::MX {
-                    content: Self::ensure_trailing_dot(parts[1]),
-                    priority,
-                })
+                data
             }
-            RecordType::Txt => Ok(DnsContent::TXT {
-                content: record.content.clone(),
-            }),
-            RecordType::Srv => Ok(DnsContent::SRV {
-                content: Self::ensure_trailing_dot(&record.content),
-            }),
-        }
+            _ => {
+                return Err(DnsError::ApiError("Unsupported record type".to_string()));
+            }
+        })
     }
 
-    /// Get the current content of a DNS record
-    async fn get_record_content_impl(
+    /// Get the current DNS record
+    async fn get_record_impl(
         &self,
         zone_id: &str,
         record_name: &str,
         record_type: &RecordType,
-    ) -> Result<Option<String>, DnsError> {
+    ) -> Result<Option<DnsRecord>, DnsError> {
         tracing::debug!(
-            "Getting content for record: {} ({})",
+            "Getting record: {} ({})",
             record_name,
             Self::record_type_to_string(record_type)
         );
 
-        let params = ListDnsRecordsParams {
-            per_page: Some(20),
-            ..Default::default()
+        let Some(cf_record) = self
+            .list_records(zone_id, record_name, record_type)
+            .await?
+            .into_iter()
+            .next()
+        else {
+            tracing::debug!("No matching record found");
+            return Ok(None);
         };
 
-        match self
-            .client
-            .request(&ListDnsRecords {
-                zone_identifier: zone_id,
-                params,
-            })
-            .await
-        {
-            Ok(records) => {
-                let normalized_name = Self::ensure_trailing_dot(record_name);
-                let target_type = Self::record_type_to_string(record_type);
-
-                for cf_record in records.result {
-                    if Self::ensure_trailing_dot(&cf_record.name) == normalized_name {
-                        let cf_record_type = Self::get_record_type_from_content(&cf_record.content);
-                        if cf_record_type == target_type {
-                            let content = match &cf_record.content {
-                                DnsContent::A { content } => content.to_string(),
-                                DnsContent::AAAA { content } => content.to_string(),
-                                DnsContent::CNAME { content } => content.clone(),
-                                DnsContent::MX { content, priority } => {
-                                    format!("{} {}", priority, content)
-                                }
-                                DnsContent::TXT { content } => content.clone(),
-                                DnsContent::SRV { content } => content.clone(),
-                                _ => {
-                                    return Err(DnsError::ApiError(
-                                        "Unsupported record type".to_string(),
-                                    ));
-                                }
-                            };
-                            tracing::debug!("Found record content: {}", content);
-                            return Ok(Some(content));
-                        }
-                    }
-                }
-                tracing::debug!("No matching record found");
-                Ok(None)
-            }
-            Err(e) => {
-                tracing::warn!("Failed to get record content due to API error: {:?}", e);
-                Ok(None)
-            }
-        }
+        let data = Self::record_data(&cf_record.content)?;
+        tracing::debug!("Found record content: {}", data);
+        Ok(Some(DnsRecord {
+            id: Some(cf_record.id),
+            name: Self::ensure_trailing_dot(record_name),
+            ttl: cf_record.ttl,
+            data,
+            extensions: HashMap::from([(PROXIED_EXTENSION.to_string(), cf_record.proxied.into())]),
+        }))
     }
 }
 
 #[async_trait]
 impl DnsProvider for CloudflareDns {
-    async fn update_record(
+    async fn update_record(&self, zone: &str, record: &DnsRecord) -> Result<(), DnsError> {
+        self.update_record_smart(zone, record).await
+    }
+
+    async fn get_record(
         &self,
         zone: &str,
-        record: &DnsRecordCloudflare,
-    ) -> Result<(), DnsError> {
-        // Use the smart update approach that handles TLSA parsing issues
-        self.update_record_smart(zone, record).await
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<Option<DnsRecord>, DnsError> {
+        let zone_id = self.get_zone_id(zone).await?;
+        self.get_record_impl(&zone_id, record_name, record_type)
+            .await
     }
 
-    async fn get_record_content(
+    async fn delete_record(
         &self,
         zone: &str,
         record_name: &str,
         record_type: &RecordType,
-    ) -> Result<Option<String>, DnsError> {
+    ) -> Result<bool, DnsError> {
         let zone_id = self.get_zone_id(zone).await?;
-        self.get_record_content_impl(&zone_id, record_name, record_type)
+        let Some(record_id) = self
+            .find_matching_record(&zone_id, record_name, record_type)
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        tracing::info!("Deleting DNS record: {}", record_name);
+        self.client
+            .request(&DeleteDnsRecord {
+                zone_identifier: &zone_id,
+                identifier: &record_id,
+            })
             .await
+            .map_err(|e| DnsError::ApiError(format!("Failed to delete DNS record: {}", e)))?;
+
+        Ok(true)
     }
 
-    fn validate_record(&self, record: &DnsRecordCloudflare) -> Result<(), DnsError> {
+    fn validate_record(&self, record: &DnsRecord) -> Result<(), DnsError> {
         validate_record_name(&record.name)?;
-        validate_record_data(&record.content, &record.record_type)?;
+        validate_record_data(&record.data)?;
         validate_ttl(record.ttl)?;
         Ok(())
     }
@@ -0,0 +1,880 @@
+use super::{
+    DnsError, DnsProvider, DnsRecord, RecordData, validate_record_data, validate_record_name,
+    validate_ttl,
+};
+use crate::config::{GCloud, RecordType};
+use async_trait::async_trait;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing;
+
+const GCLOUD_SCOPE: &str = "https://www.googleapis.com/auth/ndev.clouddns.readwrite";
+const GCLOUD_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// lifetime requested for the signed JWT, the maximum allowed by Google
+const GCLOUD_JWT_LIFETIME: Duration = Duration::from_secs(3600);
+// tokens are renewed this long before they expire
+const GCLOUD_TOKEN_MARGIN: Duration = Duration::from_secs(60);
+// a change conflicting with a concurrent one is retried on a fresh read
+const GCLOUD_CHANGE_ATTEMPTS: usize = 2;
+
+/// Service account JSON key
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    #[serde(rename = "type")]
+    key_type: String,
+    project_id: Option<String>,
+    private_key_id: Option<String>,
+    private_key: String,
+    client_email: String,
+    token_uri: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JwtClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManagedZone {
+    name: String,
+    dns_name: String,
+    visibility: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManagedZonesResponse {
+    #[serde(default)]
+    managed_zones: Vec<ManagedZone>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ResourceRecordSet {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    ttl: u32,
+    /// Record data in presentation format
+    rrdatas: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ResourceRecordSetsResponse {
+    #[serde(default)]
+    rrsets: Vec<ResourceRecordSet>,
+}
+
+#[derive(Deserialize)]
+struct Change {
+    id: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetails {
+    message: String,
+}
+
+pub struct GCloudDns {
+    client: Client,
+    encoding_key: EncodingKey,
+    key_id: Option<String>,
+    client_email: String,
+    token_url: String,
+    endpoint: String,
+    project_id: String,
+    managed_zone: Option<String>,
+    // access token and its expiry
+    token: Mutex<Option<(String, Instant)>>,
+    // managed zone names by domain name
+    zones: Mutex<HashMap<String, String>>,
+}
+
+impl GCloudDns {
+    pub fn new(config: &GCloud, project_id: Option<&str>) -> Result<Self, DnsError> {
+        let path = config
+            .credentials_file
+            .clone()
+            .or_else(|| std::env::var("GOOGLE_APPLICATION_CREDENTIALS").ok())
+            .ok_or_else(|| {
+                DnsError::ValidationError(
+                    "A service account key is required for Google Cloud DNS".to_string(),
+                )
+            })?;
+        let key: ServiceAccountKey = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                DnsError::ValidationError(format!("Invalid service account key {}: {}", path, e))
+            })?;
+        if key.key_type != "service_account" {
+            return Err(DnsError::ValidationError(format!(
+                "{} is not a service account key",
+                path
+            )));
+        }
+
+        let encoding_key = EncodingKey::from_rsa_pem(key.private_key.as_bytes()).map_err(|e| {
+            DnsError::ValidationError(format!("Invalid private key in {}: {}", path, e))
+        })?;
+        let project_id = project_id
+            .map(str::to_string)
+            .or(key.project_id)
+            .ok_or_else(|| {
+                DnsError::ValidationError("project_id is required for Google Cloud DNS".to_string())
+            })?;
+        let token_url = config
+            .token_url
+            .clone()
+            .or(key.token_uri)
+            .unwrap_or_else(|| "https://oauth2.googleapis.com/token".to_string());
+        let client = Client::builder()
+            .timeout(GCLOUD_REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| {
+                DnsError::ApiError(format!("Failed to create Google Cloud DNS client: {}", e))
+            })?;
+
+        Ok(Self {
+            client,
+            encoding_key,
+            key_id: key.private_key_id,
+            client_email: key.client_email,
+            token_url,
+            endpoint: config.endpoint.trim_end_matches('/').to_string(),
+            project_id,
+            managed_zone: config.managed_zone.clone(),
+            token: Mutex::new(None),
+            zones: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// OAuth2 access token, exchanged for a JWT signed with the service
+    /// account key and cached until it expires
+    async fn access_token(&self) -> Result<String, DnsError> {
+        if let Some((token, expiry)) = self
+            .token
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .as_ref()
+            && Instant::now() < *expiry
+        {
+            return Ok(token.clone());
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = self.key_id.clone();
+        let assertion = jsonwebtoken::encode(
+            &header,
+            &JwtClaims {
+                iss: &self.client_email,
+                scope: GCLOUD_SCOPE,
+                aud: &self.token_url,
+                iat: now,
+                exp: now + GCLOUD_JWT_LIFETIME.as_secs(),
+            },
+            &self.encoding_key,
+        )
+        .map_err(|e| DnsError::ApiError(format!("Failed to sign Google JWT: {}", e)))?;
+
+        tracing::debug!("Requesting a Google OAuth2 token for {}", self.client_email);
+        let response = self
+            .client
+            .post(&self.token_url)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", &assertion),
+            ])
+            .send()
+            .await
+            .map_err(|e| DnsError::ApiError(format!("Google OAuth2 request failed: {}", e)))?;
+        let status = response.status();
+        if !status.is_success() {
+            // {"error":"invalid_grant","error_description":"Invalid JWT Signature."}
+            return Err(DnsError::ApiError(format!(
+                "Google OAuth2 token request failed ({}): {}",
+                status,
+                response.text().await.unwrap_or_default()
+            )));
+        }
+        let token: TokenResponse = response
+            .json()
+            .await
+            .map_err(|e| DnsError::ApiError(format!("Invalid Google OAuth2 response: {}", e)))?;
+
+        let expiry = Instant::now()
+            + Duration::from_secs(token.expires_in).saturating_sub(GCLOUD_TOKEN_MARGIN);
+        *self
+            .token
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+            Some((token.access_token.clone(), expiry));
+        Ok(token.access_token)
+    }
+
+    /// Send an authenticated request, `path` being relative to the project
+    async fn request(
+        &self,
+        request: impl FnOnce(&Client, String) -> RequestBuilder,
+        path: &str,
+    ) -> Result<Response, DnsError> {
+        let url = format!(
+            "{}/dns/v1/projects/{}{}",
+            self.endpoint, self.project_id, path
+        );
+        request(&self.client, url)
+            .bearer_auth(self.access_token().await?)
+            .send()
+            .await
+            .map_err(|e| DnsError::ApiError(format!("Google Cloud DNS request failed: {}", e)))
+    }
+
+    /// Send an authenticated request and decode its successful response
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: impl FnOnce(&Client, String) -> RequestBuilder,
+        path: &str,
+    ) -> Result<T, DnsError> {
+        let response = self.request(request, path).await?;
+        if !response.status().is_success() {
+            return Err(api_error(response).await);
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| DnsError::ApiError(format!("Invalid Google Cloud DNS response: {}", e)))
+    }
+
+    async fn get_managed_zone(&self, zone: &str) -> Result<String, DnsError> {
+        if let Some(managed_zone) = &self.managed_zone {
+            return Ok(managed_zone.clone());
+        }
+        if let Some(managed_zone) = self
+            .zones
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(zone)
+        {
+            return Ok(managed_zone.clone());
+        }
+
+        let dns_name = format!("{}.", zone.trim_end_matches('.'));
+        let response: ManagedZonesResponse = self
+            .send(
+                |client, url| client.get(url).query(&[("dnsName", &dns_name)]),
+                "/managedZones",
+            )
+            .await?;
+        let managed_zone = response
+            .managed_zones
+            .into_iter()
+            .find(|managed_zone| {
+                managed_zone.dns_name.eq_ignore_ascii_case(&dns_name)
+                    && managed_zone.visibility.as_deref() != Some("private")
+            })
+            .map(|managed_zone| managed_zone.name)
+            .ok_or_else(|| {
+                DnsError::ZoneNotFound(format!("Managed zone for '{}' not found", zone))
+            })?;
+
+        tracing::debug!(
+            "Found Google Cloud DNS managed zone {} for {}",
+            managed_zone,
+            zone
+        );
+        self.zones
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(zone.to_string(), managed_zone.clone());
+        Ok(managed_zone)
+    }
+
+    async fn find_record_set(
+        &self,
+        managed_zone: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<Option<ResourceRecordSet>, DnsError> {
+        let record_type = record_type.to_string();
+        let response: ResourceRecordSetsResponse = self
+            .send(
+                |client, url| {
+                    client
+                        .get(url)
+                        .query(&[("name", record_name), ("type", &record_type)])
+                },
+                &format!("/managedZones/{}/rrsets", managed_zone),
+            )
+            .await?;
+
+        Ok(response
+            .rrsets
+            .into_iter()
+            .find(|rrset| rrset.record_type == record_type))
+    }
+
+    /// Apply the deletions and additions in one atomic change, `false` if
+    /// the record sets changed since they were read
+    async fn change(
+        &self,
+        managed_zone: &str,
+        deletions: Vec<ResourceRecordSet>,
+        additions: Vec<ResourceRecordSet>,
+    ) -> Result<bool, DnsError> {
+        let response = self
+            .request(
+                |client, url| {
+                    client.post(url).json(&json!({
+                        "deletions": deletions,
+                        "additions": additions,
+                    }))
+                },
+                &format!("/managedZones/{}/changes", managed_zone),
+            )
+            .await?;
+        let status = response.status();
+
+        // 409 when an added set already exists, 412 when a deleted one differs
+        if status == StatusCode::CONFLICT || status == StatusCode::PRECONDITION_FAILED {
+            tracing::debug!("{}", api_error(response).await);
+            return Ok(false);
+        }
+        if !status.is_success() {
+            return Err(api_error(response).await);
+        }
+        let change: Change = response
+            .json()
+            .await
+            .map_err(|e| DnsError::ApiError(format!("Invalid Google Cloud DNS response: {}", e)))?;
+
+        tracing::debug!("Google Cloud DNS change {} is {}", change.id, change.status);
+        Ok(true)
+    }
+}
+
+/// Error for a failed Google Cloud DNS response
+async fn api_error(response: Response) -> DnsError {
+    let status = response.status();
+    // {"error":{"code":404,"message":"The 'parameters.managedZone' resource named 'x' does not exist."}}
+    let error: Option<ErrorResponse> = response.json().await.ok();
+    DnsError::ApiError(format!(
+        "Google Cloud DNS error ({}): {}",
+        status,
+        error.map(|error| error.error.message).unwrap_or_default()
+    ))
+}
+
+#[async_trait]
+impl DnsProvider for GCloudDns {
+    async fn update_record(&self, zone: &str, record: &DnsRecord) -> Result<(), DnsError> {
+        self.validate_record(record)?;
+
+        let managed_zone = self.get_managed_zone(zone).await?;
+        let record_type = record.record_type();
+        for _ in 0..GCLOUD_CHANGE_ATTEMPTS {
+            // a record set can't be added over an existing one, it is replaced
+            let existing = self
+                .find_record_set(&managed_zone, &record.name, &record_type)
+                .await?;
+
+            if existing.is_some() {
+                tracing::info!("Updating existing DNS record: {}", record.name);
+            } else {
+                tracing::info!("Creating new DNS record: {}", record.name);
+            }
+            let applied = self
+                .change(
+                    &managed_zone,
+                    existing.into_iter().collect(),
+                    vec![ResourceRecordSet {
+                        name: record.name.clone(),
+                        record_type: record_type.to_string(),
+                        ttl: record.ttl,
+                        rrdatas: vec![record.data.to_string()],
+                    }],
+                )
+                .await?;
+            if applied {
+                return Ok(());
+            }
+            tracing::warn!(
+                "DNS record {} changed meanwhile, reading it again",
+                record.name
+            );
+        }
+
+        Err(DnsError::ApiError(format!(
+            "DNS record {} kept changing during its update",
+            record.name
+        )))
+    }
+
+    async fn get_record(
+        &self,
+        zone: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<Option<DnsRecord>, DnsError> {
+        let managed_zone = self.get_managed_zone(zone).await?;
+        let Some(rrset) = self
+            .find_record_set(&managed_zone, record_name, record_type)
+            .await?
+        else {
+            tracing::debug!("No matching record found");
+            return Ok(None);
+        };
+        // a set of several values is reported by its first value
+        let Some(rrdata) = rrset.rrdatas.first() else {
+            return Ok(None);
+        };
+
+        Ok(Some(DnsRecord::new(
+            rrset.name.clone(),
+            rrset.ttl,
+            RecordData::parse(record_type, rrdata)?,
+        )))
+    }
+
+    async fn delete_record(
+        &self,
+        zone: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<bool, DnsError> {
+        let managed_zone = self.get_managed_zone(zone).await?;
+        // a deletion must match the current record set exactly
+        let Some(rrset) = self
+            .find_record_set(&managed_zone, record_name, record_type)
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        tracing::info!("Deleting DNS record: {}", record_name);
+        if !self.change(&managed_zone, vec![rrset], Vec::new()).await? {
+            return Err(DnsError::ApiError(format!(
+                "DNS record {} changed while being deleted",
+                record_name
+            )));
+        }
+        Ok(true)
+    }
+
+    fn validate_record(&self, record: &DnsRecord) -> Result<(), DnsError> {
+        validate_record_name(&record.name)?;
+        validate_record_data(&record.data)?;
+        validate_ttl(record.ttl)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Form, Json, Router,
+        extract::{Path, Query, State},
+        http::{HeaderMap, StatusCode},
+        response::{IntoResponse, Response},
+        routing::{get, post},
+    };
+    use jsonwebtoken::{DecodingKey, Validation};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // test-only key pair, not known to Google
+    const PRIVATE_KEY: &str = include_str!("testdata/gcloud_key.pem");
+    const PUBLIC_KEY: &str = include_str!("testdata/gcloud_key.pub.pem");
+    const CLIENT_EMAIL: &str = "ddns@my-project.iam.gserviceaccount.com";
+
+    #[derive(Deserialize)]
+    struct Claims {
+        iss: String,
+        scope: String,
+        aud: String,
+        iat: u64,
+        exp: u64,
+    }
+
+    /// OAuth2 token endpoint and Cloud DNS API
+    struct MockGoogle {
+        url: String,
+        expires_in: u64,
+        tokens_issued: AtomicUsize,
+        zone_lookups: AtomicUsize,
+        /// Record set of home.example.com.
+        rrset: Mutex<Option<Value>>,
+        /// Changes rejected after a concurrent update of the record set
+        conflicts: AtomicUsize,
+        /// Bodies of the changes requested
+        changes: Mutex<Vec<Value>>,
+    }
+
+    fn authorized(mock: &MockGoogle, headers: &HeaderMap) -> bool {
+        let expected = format!("Bearer token-{}", mock.tokens_issued.load(Ordering::SeqCst));
+        headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            == Some(expected.as_str())
+    }
+
+    fn unauthorized() -> Response {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": {"code": 401, "message": "Invalid Credentials"}})),
+        )
+            .into_response()
+    }
+
+    async fn token(
+        State(mock): State<Arc<MockGoogle>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Response {
+        assert_eq!(
+            form["grant_type"],
+            "urn:ietf:params:oauth:grant-type:jwt-bearer"
+        );
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[format!("{}/token", mock.url)]);
+        let Ok(jwt) = jsonwebtoken::decode::<Claims>(
+            &form["assertion"],
+            &DecodingKey::from_rsa_pem(PUBLIC_KEY.as_bytes()).unwrap(),
+            &validation,
+        ) else {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "invalid_grant"})),
+            )
+                .into_response();
+        };
+
+        assert_eq!(jwt.header.kid.as_deref(), Some("key-id"));
+        assert_eq!(jwt.claims.iss, CLIENT_EMAIL);
+        assert_eq!(jwt.claims.scope, GCLOUD_SCOPE);
+        assert_eq!(jwt.claims.aud, format!("{}/token", mock.url));
+        assert_eq!(jwt.claims.exp - jwt.claims.iat, 3600);
+
+        let issued = mock.tokens_issued.fetch_add(1, Ordering::SeqCst) + 1;
+        Json(json!({
+            "access_token": format!("token-{}", issued),
+            "expires_in": mock.expires_in,
+            "token_type": "Bearer",
+        }))
+        .into_response()
+    }
+
+    async fn managed_zones(
+        State(mock): State<Arc<MockGoogle>>,
+        headers: HeaderMap,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Response {
+        if !authorized(&mock, &headers) {
+            return unauthorized();
+        }
+
+        assert_eq!(query["dnsName"], "example.com.");
+        mock.zone_lookups.fetch_add(1, Ordering::SeqCst);
+        Json(json!({
+            "managedZones": [
+                {"name": "internal-zone", "dnsName": "example.com.", "visibility": "private"},
+                {"name": "example-zone", "dnsName": "Example.com.", "visibility": "public"},
+            ],
+        }))
+        .into_response()
+    }
+
+    async fn rrsets(
+        State(mock): State<Arc<MockGoogle>>,
+        Path(zone): Path<String>,
+        headers: HeaderMap,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Response {
+        if !authorized(&mock, &headers) {
+            return unauthorized();
+        }
+
+        assert_eq!(zone, "example-zone");
+        assert_eq!(query["name"], "home.example.com.");
+        assert_eq!(query["type"], "A");
+        let rrset = mock.rrset.lock().unwrap().clone();
+        Json(json!({"rrsets": Vec::from_iter(rrset)})).into_response()
+    }
+
+    async fn changes(
+        State(mock): State<Arc<MockGoogle>>,
+        Path(zone): Path<String>,
+        headers: HeaderMap,
+        Json(change): Json<Value>,
+    ) -> Response {
+        if !authorized(&mock, &headers) {
+            return unauthorized();
+        }
+
+        assert_eq!(zone, "example-zone");
+        mock.changes.lock().unwrap().push(change.clone());
+        let mut rrset = mock.rrset.lock().unwrap();
+        // a concurrent update, with different data each time
+        if let Ok(conflicts) =
+            mock.conflicts
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        {
+            *rrset = Some(json!({
+                "name": "home.example.com.",
+                "type": "A",
+                "ttl": 300,
+                "rrdatas": [format!("198.51.100.{}", 100 + conflicts)],
+            }));
+        }
+        let deletions = change["deletions"].as_array().unwrap();
+        if deletions.first() != rrset.as_ref() {
+            return (
+                StatusCode::PRECONDITION_FAILED,
+                Json(json!({"error": {"code": 412, "message": "Precondition not met for 'entity.change.deletions[0]'"}})),
+            )
+                .into_response();
+        }
+
+        *rrset = change["additions"][0]
+            .as_object()
+            .cloned()
+            .map(Value::Object);
+        Json(json!({"id": "1", "status": "pending"})).into_response()
+    }
+
+    async fn start_mock(
+        expires_in: u64,
+        managed_zone: Option<&str>,
+    ) -> (Arc<MockGoogle>, GCloudDns) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let url = format!("http://{}", address);
+        let mock = Arc::new(MockGoogle {
+            url: url.clone(),
+            expires_in,
+            tokens_issued: AtomicUsize::new(0),
+            zone_lookups: AtomicUsize::new(0),
+            rrset: Mutex::new(Some(json!({
+                "name": "home.example.com.",
+                "type": "A",
+                "ttl": 300,
+                "rrdatas": ["203.0.113.9"],
+            }))),
+            conflicts: AtomicUsize::new(0),
+            changes: Mutex::new(Vec::new()),
+        });
+        let app = Router::new()
+            .route("/token", post(token))
+            .route(
+                "/dns/v1/projects/my-project/managedZones",
+                get(managed_zones),
+            )
+            .route(
+                "/dns/v1/projects/my-project/managedZones/{zone}/rrsets",
+                get(rrsets),
+            )
+            .route(
+                "/dns/v1/projects/my-project/managedZones/{zone}/changes",
+                post(changes),
+            )
+            .with_state(mock.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let key_file =
+            std::env::temp_dir().join(format!("custom-ddns-gcloud-{}.json", address.port()));
+        std::fs::write(
+            &key_file,
+            json!({
+                "type": "service_account",
+                "project_id": "my-project",
+                "private_key_id": "key-id",
+                "private_key": PRIVATE_KEY,
+                "client_email": CLIENT_EMAIL,
+                "token_uri": format!("{}/token", url),
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let gcloud = GCloudDns::new(
+            &GCloud {
+                credentials_file: Some(key_file.to_string_lossy().to_string()),
+                managed_zone: managed_zone.map(str::to_string),
+                endpoint: url,
+                token_url: None,
+            },
+            None,
+        )
+        .unwrap();
+        std::fs::remove_file(key_file).unwrap();
+        (mock, gcloud)
+    }
+
+    #[tokio::test]
+    async fn token_is_cached_until_it_expires() {
+        let (mock, gcloud) = start_mock(3600, Some("example-zone")).await;
+
+        assert_eq!(gcloud.access_token().await.unwrap(), "token-1");
+        assert_eq!(gcloud.access_token().await.unwrap(), "token-1");
+        assert_eq!(mock.tokens_issued.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn token_is_renewed_before_it_expires() {
+        // within the renewal margin, so never reused
+        let (mock, gcloud) = start_mock(GCLOUD_TOKEN_MARGIN.as_secs(), Some("example-zone")).await;
+
+        assert_eq!(gcloud.access_token().await.unwrap(), "token-1");
+        assert_eq!(gcloud.access_token().await.unwrap(), "token-2");
+        assert_eq!(mock.tokens_issued.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn record_is_read_with_the_token() {
+        let (mock, gcloud) = start_mock(3600, Some("example-zone")).await;
+
+        let record = gcloud
+            .get_record("example.com", "home.example.com.", &RecordType::A)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            record.data,
+            RecordData::parse(&RecordType::A, "203.0.113.9").unwrap()
+        );
+        assert_eq!(record.ttl, 300);
+        assert_eq!(mock.tokens_issued.load(Ordering::SeqCst), 1);
+    }
+
+    fn home(address: &str) -> DnsRecord {
+        DnsRecord::new(
+            "home.example.com.".to_string(),
+            60,
+            RecordData::parse(&RecordType::A, address).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn managed_zone_is_found_by_dns_name() {
+        let (mock, gcloud) = start_mock(3600, None).await;
+
+        for _ in 0..2 {
+            let record = gcloud
+                .get_record("example.com", "home.example.com.", &RecordType::A)
+                .await
+                .unwrap();
+            assert!(record.is_some());
+        }
+        // the private zone of the same name is skipped, the public one cached
+        assert_eq!(mock.zone_lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn existing_set_is_replaced_in_one_change() {
+        let (mock, gcloud) = start_mock(3600, None).await;
+
+        gcloud
+            .update_record("example.com", &home("198.51.100.1"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *mock.changes.lock().unwrap(),
+            [json!({
+                "deletions": [{
+                    "name": "home.example.com.",
+                    "type": "A",
+                    "ttl": 300,
+                    "rrdatas": ["203.0.113.9"],
+                }],
+                "additions": [{
+                    "name": "home.example.com.",
+                    "type": "A",
+                    "ttl": 60,
+                    "rrdatas": ["198.51.100.1"],
+                }],
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_set_is_only_added() {
+        let (mock, gcloud) = start_mock(3600, Some("example-zone")).await;
+        *mock.rrset.lock().unwrap() = None;
+
+        gcloud
+            .update_record("example.com", &home("198.51.100.1"))
+            .await
+            .unwrap();
+
+        let changes = mock.changes.lock().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0]["deletions"], json!([]));
+        assert_eq!(
+            changes[0]["additions"][0]["rrdatas"],
+            json!(["198.51.100.1"])
+        );
+    }
+
+    #[tokio::test]
+    async fn conflicting_change_is_retried_on_the_new_set() {
+        let (mock, gcloud) = start_mock(3600, Some("example-zone")).await;
+        mock.conflicts.store(1, Ordering::SeqCst);
+
+        gcloud
+            .update_record("example.com", &home("198.51.100.1"))
+            .await
+            .unwrap();
+
+        let changes = mock.changes.lock().unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[1]["deletions"][0]["rrdatas"],
+            json!(["198.51.100.101"])
+        );
+        assert_eq!(
+            mock.rrset.lock().unwrap().as_ref().unwrap()["rrdatas"],
+            json!(["198.51.100.1"])
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_conflicts_are_an_error() {
+        let (mock, gcloud) = start_mock(3600, Some("example-zone")).await;
+        mock.conflicts
+            .store(GCLOUD_CHANGE_ATTEMPTS, Ordering::SeqCst);
+
+        let result = gcloud
+            .update_record("example.com", &home("198.51.100.1"))
+            .await;
+
+        assert!(matches!(result, Err(DnsError::ApiError(_))));
+        assert_eq!(mock.changes.lock().unwrap().len(), GCLOUD_CHANGE_ATTEMPTS);
+    }
+}
@@ -1,12 +1,18 @@
 use crate::{
-    config::{ConfigDnsProvider, RecordType},
-    dns::cloudflare::CloudflareDns,
+    config::{ConfigDnsProvider, DnsProviderConfig, RecordType},
+    dns::{cloudflare::CloudflareDns, gcloud::GCloudDns, ovh::OvhDns, route53::Route53Dns},
 };
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use thiserror::Error;
 
 pub mod cloudflare;
+pub mod gcloud;
+pub mod ovh;
+pub mod route53;
 
 #[derive(Debug, Error)]
 pub enum DnsError {
@@ -18,37 +24,218 @@ pub enum DnsError {
     ZoneNotFound(String),
 }
 
-pub enum DnsClient {
-    Cloudflare(CloudflareDns),
+/// DNS provider of a domain
+pub fn build_provider(config: &DnsProviderConfig) -> Result<Box<dyn DnsProvider>, DnsError> {
+    match config.provider {
+        ConfigDnsProvider::Cloudflare => {
+            let api_key = config.api_key.clone().ok_or_else(|| {
+                DnsError::ValidationError("API key is required for Cloudflare provider".to_string())
+            })?;
+            Ok(Box::new(CloudflareDns::new(api_key)?))
+        }
+        ConfigDnsProvider::Route53 => Ok(Box::new(Route53Dns::new(
+            &config.route53.clone().unwrap_or_default(),
+        )?)),
+        ConfigDnsProvider::GCloud => Ok(Box::new(GCloudDns::new(
+            &config.gcloud.clone().unwrap_or_default(),
+            config.project_id.as_deref(),
+        )?)),
+        ConfigDnsProvider::Ovh => Ok(Box::new(OvhDns::new(
+            &config.ovh.clone().unwrap_or_default(),
+        )?)),
+    }
+}
+
+/// Record data, typed per record type. Names are fully qualified, with a
+/// trailing dot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "UPPERCASE")]
+pub enum RecordData {
+    A {
+        address: Ipv4Addr,
+    },
+    Aaaa {
+        address: Ipv6Addr,
+    },
+    Cname {
+        target: String,
+    },
+    Mx {
+        priority: u16,
+        exchange: String,
+    },
+    /// Character strings of up to 255 bytes each, concatenated by resolvers
+    Txt {
+        strings: Vec<String>,
+    },
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+}
+
+impl RecordData {
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            RecordData::A { .. } => RecordType::A,
+            RecordData::Aaaa { .. } => RecordType::Aaaa,
+            RecordData::Cname { .. } => RecordType::Cname,
+            RecordData::Mx { .. } => RecordType::Mx,
+            RecordData::Txt { .. } => RecordType::Txt,
+            RecordData::Srv { .. } => RecordType::Srv,
+        }
+    }
+
+    /// Parse the presentation format of a record, e.g. `10 mail.example.com.`
+    /// for an MX record
+    pub fn parse(record_type: &RecordType, content: &str) -> Result<Self, DnsError> {
+        let invalid =
+            || DnsError::ValidationError(format!("Invalid {} record: {}", record_type, content));
+        let fields: Vec<&str> = content.split_whitespace().collect();
+        let number = |field: &str| field.parse::<u16>().map_err(|_| invalid());
+
+        Ok(match (record_type, fields.as_slice()) {
+            (RecordType::A, [address]) => RecordData::A {
+                address: address.parse().map_err(|_| invalid())?,
+            },
+            (RecordType::Aaaa, [address]) => RecordData::Aaaa {
+                address: address.parse().map_err(|_| invalid())?,
+            },
+            (RecordType::Cname, [target]) => RecordData::Cname {
+                target: target.to_string(),
+            },
+            (RecordType::Mx, [priority, exchange]) => RecordData::Mx {
+                priority: number(priority)?,
+                exchange: exchange.to_string(),
+            },
+            (RecordType::Txt, _) => RecordData::Txt {
+                strings: parse_txt_strings(content).ok_or_else(invalid)?,
+            },
+            (RecordType::Srv, [priority, weight, port, target]) => RecordData::Srv {
+                priority: number(priority)?,
+                weight: number(weight)?,
+                port: number(port)?,
+                target: target.to_string(),
+            },
+            _ => return Err(invalid()),
+        })
+    }
 }
 
+/// Presentation format of the record data, TXT strings are quoted
+impl fmt::Display for RecordData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordData::A { address } => write!(f, "{}", address),
+            RecordData::Aaaa { address } => write!(f, "{}", address),
+            RecordData::Cname { target } => write!(f, "{}", target),
+            RecordData::Mx { priority, exchange } => write!(f, "{} {}", priority, exchange),
+            RecordData::Txt { strings } => {
+                let strings: Vec<String> = strings
+                    .iter()
+                    .map(|string| {
+                        format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+                    })
+                    .collect();
+                write!(f, "{}", strings.join(" "))
+            }
+            RecordData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, target),
+        }
+    }
+}
+
+/// Strings of a TXT record content, either quoted strings (`"a" "b"`) or a
+/// single unquoted string
+fn parse_txt_strings(content: &str) -> Option<Vec<String>> {
+    let content = content.trim();
+    if !content.starts_with('"') {
+        return Some(vec![content.to_string()]);
+    }
+
+    let mut strings = Vec::new();
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => string.push(chars.next()?),
+                        c => string.push(c),
+                    }
+                }
+                strings.push(string);
+            }
+            c if c.is_whitespace() => {}
+            _ => return None,
+        }
+    }
+    Some(strings)
+}
+
+/// DNS record, independent of the provider
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DnsRecordCloudflare {
+pub struct DnsRecord {
+    /// Identifier of the record at the provider, when it has one
     pub id: Option<String>,
     pub name: String,
-    pub content: String,
-    pub record_type: RecordType,
     pub ttl: u32,
+    #[serde(flatten)]
+    pub data: RecordData,
+    /// Provider-specific settings, e.g. `proxied` for Cloudflare
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub extensions: HashMap<String, serde_json::Value>,
+}
+
+impl DnsRecord {
+    pub fn new(name: String, ttl: u32, data: RecordData) -> Self {
+        Self {
+            id: None,
+            name,
+            ttl,
+            data,
+            extensions: HashMap::new(),
+        }
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        self.data.record_type()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DnsZone {
     pub name: String,
     pub provider: ConfigDnsProvider,
-    pub records: Vec<DnsRecordCloudflare>,
+    pub records: Vec<DnsRecord>,
 }
 
 #[async_trait]
 pub trait DnsProvider: Send + Sync {
-    async fn update_record(&self, zone: &str, record: &DnsRecordCloudflare)
-    -> Result<(), DnsError>;
-    async fn get_record_content(
+    async fn update_record(&self, zone: &str, record: &DnsRecord) -> Result<(), DnsError>;
+    /// Get the record with this name and type, if any
+    async fn get_record(
+        &self,
+        zone: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<Option<DnsRecord>, DnsError>;
+    /// Delete a record, returning whether it existed
+    async fn delete_record(
         &self,
         zone: &str,
         record_name: &str,
         record_type: &RecordType,
-    ) -> Result<Option<String>, DnsError>;
-    fn validate_record(&self, record: &DnsRecordCloudflare) -> Result<(), DnsError>;
+    ) -> Result<bool, DnsError>;
+    fn validate_record(&self, record: &DnsRecord) -> Result<(), DnsError>;
 }
 
 // Validation functions
@@ -66,68 +253,43 @@ pub fn validate_record_name(name: &str) -> Result<(), DnsError> {
     Ok(())
 }
 
-pub fn validate_record_data(content: &str, record_type: &RecordType) -> Result<(), DnsError> {
-    if content.is_empty() {
-        return Err(DnsError::ValidationError(
-            "Record content cannot be empty".to_string(),
-        ));
-    }
-
-    match record_type {
-        RecordType::A => {
-            if !content.split('.').all(|octet| octet.parse::<u8>().is_ok()) {
-                return Err(DnsError::ValidationError(
-                    "Invalid IPv4 address format".to_string(),
-                ));
-            }
-        }
-        RecordType::Aaaa => {
-            if !content.split(':').all(|segment| {
-                segment.is_empty()
-                    || segment.len() <= 4 && segment.chars().all(|c| c.is_ascii_hexdigit())
-            }) {
+pub fn validate_record_data(data: &RecordData) -> Result<(), DnsError> {
+    match data {
+        // addresses are valid by construction
+        RecordData::A { .. } | RecordData::Aaaa { .. } => {}
+        RecordData::Cname { target: name }
+        | RecordData::Mx { exchange: name, .. }
+        | RecordData::Srv { target: name, .. } => {
+            if name.is_empty() {
                 return Err(DnsError::ValidationError(
-                    "Invalid IPv6 address format".to_string(),
+                    "Record target cannot be empty".to_string(),
                 ));
             }
-        }
-        RecordType::Cname | RecordType::Mx => {
-            if !content.ends_with('.') {
+            if !name.ends_with('.') {
                 return Err(DnsError::ValidationError(
-                    "CNAME and MX records must end with a dot".to_string(),
+                    "CNAME, MX and SRV record targets must end with a dot".to_string(),
                 ));
             }
         }
-        RecordType::Txt => {
-            // TXT records can contain any printable ASCII characters
-            if !content
-                .chars()
-                .all(|c| c.is_ascii() && !c.is_ascii_control())
-            {
+        RecordData::Txt { strings } => {
+            if strings.is_empty() {
                 return Err(DnsError::ValidationError(
-                    "TXT record contains invalid characters".to_string(),
+                    "TXT record needs at least one string".to_string(),
                 ));
             }
-        }
-        RecordType::Srv => {
-            // SRV record format: priority weight port target
-            let parts: Vec<&str> = content.split_whitespace().collect();
-            if parts.len() != 4 {
+            if strings.iter().any(|string| string.len() > 255) {
                 return Err(DnsError::ValidationError(
-                    "SRV record must have format: priority weight port target".to_string(),
+                    "TXT record strings are limited to 255 bytes".to_string(),
                 ));
             }
-            if parts[0].parse::<u16>().is_err()
-                || parts[1].parse::<u16>().is_err()
-                || parts[2].parse::<u16>().is_err()
+            // TXT records can contain any printable ASCII characters
+            if !strings
+                .iter()
+                .flat_map(|string| string.chars())
+                .all(|c| c.is_ascii() && !c.is_ascii_control())
             {
                 return Err(DnsError::ValidationError(
-                    "SRV record priority, weight, and port must be numbers".to_string(),
-                ));
-            }
-            if !parts[3].ends_with('.') {
-                return Err(DnsError::ValidationError(
-                    "SRV record target must end with a dot".to_string(),
+                    "TXT record contains invalid characters".to_string(),
                 ));
             }
         }
@@ -144,3 +306,139 @@ pub fn validate_ttl(ttl: u32) -> Result<(), DnsError> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `content`, which must be displayed back unchanged
+    fn round_trip(record_type: &RecordType, content: &str) -> RecordData {
+        let data = RecordData::parse(record_type, content).unwrap();
+        assert_eq!(data.record_type(), *record_type);
+        assert_eq!(data.to_string(), content);
+        data
+    }
+
+    fn is_invalid(record_type: &RecordType, content: &str) -> bool {
+        matches!(
+            RecordData::parse(record_type, content),
+            Err(DnsError::ValidationError(_))
+        )
+    }
+
+    #[test]
+    fn addresses_round_trip() {
+        assert_eq!(
+            round_trip(&RecordType::A, "203.0.113.7"),
+            RecordData::A {
+                address: Ipv4Addr::new(203, 0, 113, 7)
+            }
+        );
+        round_trip(&RecordType::Aaaa, "2001:db8::1");
+        // displayed in canonical form
+        let data = RecordData::parse(&RecordType::Aaaa, "2001:0DB8:0:0::1").unwrap();
+        assert_eq!(data.to_string(), "2001:db8::1");
+
+        assert!(is_invalid(&RecordType::A, "2001:db8::1"));
+        assert!(is_invalid(&RecordType::A, "203.0.113.7 203.0.113.8"));
+        assert!(is_invalid(&RecordType::Aaaa, "203.0.113.7"));
+    }
+
+    #[test]
+    fn cname_keeps_its_trailing_dot() {
+        let data = round_trip(&RecordType::Cname, "target.example.com.");
+
+        assert_eq!(
+            data,
+            RecordData::Cname {
+                target: "target.example.com.".to_string()
+            }
+        );
+        assert!(validate_record_data(&data).is_ok());
+        let relative = RecordData::parse(&RecordType::Cname, "target").unwrap();
+        assert!(validate_record_data(&relative).is_err());
+    }
+
+    #[test]
+    fn quoted_txt_round_trips() {
+        assert_eq!(
+            round_trip(
+                &RecordType::Txt,
+                r#""v=spf1 include:_spf.example.com ~all""#
+            ),
+            RecordData::Txt {
+                strings: vec!["v=spf1 include:_spf.example.com ~all".to_string()]
+            }
+        );
+        assert_eq!(
+            round_trip(&RecordType::Txt, r#""say \"hi\"" "back\\slash""#),
+            RecordData::Txt {
+                strings: vec![r#"say "hi""#.to_string(), r"back\slash".to_string()]
+            }
+        );
+        round_trip(&RecordType::Txt, r#""""#);
+    }
+
+    #[test]
+    fn multi_chunk_txt_round_trips() {
+        let chunks = ["a".repeat(255), "b".repeat(45)];
+        let content = format!("\"{}\" \"{}\"", chunks[0], chunks[1]);
+
+        let data = round_trip(&RecordType::Txt, &content);
+
+        assert_eq!(
+            data,
+            RecordData::Txt {
+                strings: chunks.to_vec()
+            }
+        );
+        assert!(validate_record_data(&data).is_ok());
+        let too_long = RecordData::Txt {
+            strings: vec!["a".repeat(256)],
+        };
+        assert!(validate_record_data(&too_long).is_err());
+    }
+
+    #[test]
+    fn unquoted_txt_is_a_single_string() {
+        let data = RecordData::parse(&RecordType::Txt, "hello world").unwrap();
+
+        assert_eq!(
+            data,
+            RecordData::Txt {
+                strings: vec!["hello world".to_string()]
+            }
+        );
+        assert_eq!(data.to_string(), r#""hello world""#);
+        assert_eq!(
+            RecordData::parse(&RecordType::Txt, &data.to_string()).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn malformed_txt_is_rejected() {
+        assert!(is_invalid(&RecordType::Txt, r#""unterminated"#));
+        assert!(is_invalid(&RecordType::Txt, r#""a"b"#));
+        assert!(is_invalid(&RecordType::Txt, r#""a" b"#));
+        assert!(is_invalid(&RecordType::Txt, r#""trailing\"#));
+    }
+
+    #[test]
+    fn srv_round_trips() {
+        assert_eq!(
+            round_trip(&RecordType::Srv, "10 5 25565 mc.example.com."),
+            RecordData::Srv {
+                priority: 10,
+                weight: 5,
+                port: 25565,
+                target: "mc.example.com.".to_string(),
+            }
+        );
+        round_trip(&RecordType::Mx, "10 mail.example.com.");
+
+        assert!(is_invalid(&RecordType::Srv, "10 5 mc.example.com."));
+        assert!(is_invalid(&RecordType::Srv, "10 5 65536 mc.example.com."));
+        assert!(is_invalid(&RecordType::Srv, "-1 5 25565 mc.example.com."));
+    }
+}
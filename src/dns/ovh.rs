@@ -0,0 +1,793 @@
+use super::{
+    DnsError, DnsProvider, DnsRecord, RecordData, validate_record_data, validate_record_name,
+    validate_ttl,
+};
+use crate::config::{Ovh, RecordType};
+use async_trait::async_trait;
+use reqwest::{Client, Method, StatusCode, Url};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing;
+
+const OVH_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OvhRecord {
+    id: u64,
+    field_type: String,
+    sub_domain: String,
+    target: String,
+    ttl: u32,
+}
+
+#[derive(Deserialize)]
+struct OvhError {
+    message: String,
+}
+
+pub struct OvhDns {
+    client: Client,
+    endpoint: String,
+    application_key: String,
+    application_secret: String,
+    consumer_key: String,
+    // difference between the API server clock and the local one, in seconds
+    time_delta: Mutex<Option<i64>>,
+}
+
+impl OvhDns {
+    pub fn new(config: &Ovh) -> Result<Self, DnsError> {
+        let endpoint = match config.endpoint.as_str() {
+            "ovh-eu" => "https://eu.api.ovh.com/1.0",
+            "ovh-ca" => "https://ca.api.ovh.com/1.0",
+            "ovh-us" => "https://api.us.ovhcloud.com/1.0",
+            url if url.starts_with("https://") || url.starts_with("http://") => {
+                url.trim_end_matches('/')
+            }
+            endpoint => {
+                return Err(DnsError::ValidationError(format!(
+                    "Unknown OVH endpoint {}, expected ovh-eu, ovh-ca, ovh-us or a URL",
+                    endpoint
+                )));
+            }
+        };
+        let credential = |value: &Option<String>, variable: &str| {
+            value
+                .clone()
+                .or_else(|| std::env::var(variable).ok())
+                .filter(|value| !value.is_empty())
+        };
+        let (Some(application_key), Some(application_secret), Some(consumer_key)) = (
+            credential(&config.application_key, "OVH_APPLICATION_KEY"),
+            credential(&config.application_secret, "OVH_APPLICATION_SECRET"),
+            credential(&config.consumer_key, "OVH_CONSUMER_KEY"),
+        ) else {
+            return Err(DnsError::ValidationError(
+                "application_key, application_secret and consumer_key are required for OVH"
+                    .to_string(),
+            ));
+        };
+        let client = Client::builder()
+            .timeout(OVH_REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| DnsError::ApiError(format!("Failed to create OVH client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            endpoint: endpoint.to_string(),
+            application_key,
+            application_secret,
+            consumer_key,
+            time_delta: Mutex::new(None),
+        })
+    }
+
+    fn local_time() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
+    }
+
+    /// Timestamp of the API server, signatures are rejected when the local
+    /// clock drifts
+    async fn timestamp(&self) -> Result<i64, DnsError> {
+        if let Some(delta) = *self
+            .time_delta
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+        {
+            return Ok(Self::local_time() + delta);
+        }
+
+        let response = self
+            .client
+            .get(format!("{}/auth/time", self.endpoint))
+            .send()
+            .await
+            .map_err(|e| DnsError::ApiError(format!("OVH time request failed: {}", e)))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(DnsError::ApiError(format!(
+                "OVH time request failed ({})",
+                status
+            )));
+        }
+        let server_time: i64 = response
+            .json()
+            .await
+            .map_err(|e| DnsError::ApiError(format!("Invalid OVH time response: {}", e)))?;
+
+        let delta = server_time - Self::local_time();
+        tracing::debug!("OVH API clock is {}s away from the local one", delta);
+        *self
+            .time_delta
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(delta);
+        Ok(server_time)
+    }
+
+    /// `$1$` followed by the SHA-1 of the secrets and the request
+    fn sign(&self, method: &Method, url: &str, body: &str, timestamp: i64) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(format!(
+            "{}+{}+{}+{}+{}+{}",
+            self.application_secret, self.consumer_key, method, url, body, timestamp
+        ));
+        format!("$1${}", hex::encode(hasher.finalize()))
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<serde_json::Value>,
+    ) -> Result<T, DnsError> {
+        // the URL is signed as sent, query included
+        let url = format!("{}{}", self.endpoint, path);
+        let url = Url::parse_with_params(&url, query)
+            .map_err(|e| DnsError::ValidationError(format!("Invalid OVH URL {}: {}", url, e)))?;
+        let url = url.as_str().trim_end_matches('?').to_string();
+
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let mut resynced = false;
+        let text = loop {
+            let timestamp = self.timestamp().await?;
+            let mut request = self
+                .client
+                .request(method.clone(), &url)
+                .header("X-Ovh-Application", &self.application_key)
+                .header("X-Ovh-Consumer", &self.consumer_key)
+                .header("X-Ovh-Timestamp", timestamp.to_string())
+                .header(
+                    "X-Ovh-Signature",
+                    self.sign(&method, &url, &body, timestamp),
+                );
+            if !body.is_empty() {
+                request = request
+                    .header("Content-Type", "application/json")
+                    .body(body.clone());
+            }
+
+            let response = request
+                .send()
+                .await
+                .map_err(|e| DnsError::ApiError(format!("OVH request failed: {}", e)))?;
+            let status = response.status();
+            let text = response
+                .text()
+                .await
+                .map_err(|e| DnsError::ApiError(format!("OVH request failed: {}", e)))?;
+
+            if status.is_success() {
+                break text;
+            }
+            // {"class":"Client::Forbidden","message":"This call has not been granted"}
+            let message = serde_json::from_str::<OvhError>(&text)
+                .map(|error| error.message)
+                .unwrap_or(text);
+
+            // the clock of either side may have moved since the offset was
+            // measured, measure it again
+            if !resynced
+                && matches!(status, StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED)
+                && message.to_lowercase().contains("timestamp")
+            {
+                tracing::debug!(
+                    "OVH rejected the timestamp, syncing the clock again: {}",
+                    message
+                );
+                *self
+                    .time_delta
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+                resynced = true;
+                continue;
+            }
+            return Err(DnsError::ApiError(format!(
+                "OVH error ({}): {}",
+                status, message
+            )));
+        };
+        // calls without a result answer `null`, or nothing at all
+        serde_json::from_str(if text.is_empty() { "null" } else { &text })
+            .map_err(|e| DnsError::ApiError(format!("Invalid OVH response: {}", e)))
+    }
+
+    /// Record name relative to the zone, empty for the apex
+    fn sub_domain(zone: &str, record_name: &str) -> Result<String, DnsError> {
+        let zone = zone.trim_end_matches('.').to_lowercase();
+        let name = record_name.trim_end_matches('.').to_lowercase();
+
+        if name == zone {
+            return Ok(String::new());
+        }
+        name.strip_suffix(&format!(".{}", zone))
+            .map(str::to_string)
+            .ok_or_else(|| {
+                DnsError::ValidationError(format!(
+                    "Record {} is not in the zone {}",
+                    record_name, zone
+                ))
+            })
+    }
+
+    /// IDs of the records of a name and type
+    async fn find_records(
+        &self,
+        zone: &str,
+        sub_domain: &str,
+        record_type: &RecordType,
+    ) -> Result<Vec<u64>, DnsError> {
+        self.request(
+            Method::GET,
+            &format!("/domain/zone/{}/record", zone),
+            &[
+                ("fieldType", &record_type.to_string()),
+                ("subDomain", sub_domain),
+            ],
+            None,
+        )
+        .await
+    }
+
+    /// Apply the pending changes of the zone, OVH doesn't serve them before
+    async fn refresh(&self, zone: &str) -> Result<(), DnsError> {
+        tracing::debug!("Refreshing OVH zone {}", zone);
+        self.request(
+            Method::POST,
+            &format!("/domain/zone/{}/refresh", zone),
+            &[],
+            None,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl DnsProvider for OvhDns {
+    async fn update_record(&self, zone: &str, record: &DnsRecord) -> Result<(), DnsError> {
+        self.validate_record(record)?;
+
+        let zone = zone.trim_end_matches('.');
+        let sub_domain = Self::sub_domain(zone, &record.name)?;
+        let record_type = record.record_type();
+        let target = record.data.to_string();
+
+        // several records of a name and type are replaced by the first one
+        match self
+            .find_records(zone, &sub_domain, &record_type)
+            .await?
+            .split_first()
+        {
+            Some((id, duplicates)) => {
+                tracing::info!("Updating existing DNS record: {}", record.name);
+                self.request::<()>(
+                    Method::PUT,
+                    &format!("/domain/zone/{}/record/{}", zone, id),
+                    &[],
+                    Some(json!({
+                        "subDomain": sub_domain,
+                        "target": target,
+                        "ttl": record.ttl,
+                    })),
+                )
+                .await?;
+                for duplicate in duplicates {
+                    tracing::info!("Deleting duplicate DNS record: {}", record.name);
+                    self.request::<()>(
+                        Method::DELETE,
+                        &format!("/domain/zone/{}/record/{}", zone, duplicate),
+                        &[],
+                        None,
+                    )
+                    .await?;
+                }
+            }
+            None => {
+                tracing::info!("Creating new DNS record: {}", record.name);
+                let created: OvhRecord = self
+                    .request(
+                        Method::POST,
+                        &format!("/domain/zone/{}/record", zone),
+                        &[],
+                        Some(json!({
+                            "fieldType": record_type.to_string(),
+                            "subDomain": sub_domain,
+                            "target": target,
+                            "ttl": record.ttl,
+                        })),
+                    )
+                    .await?;
+                tracing::debug!("Created OVH record {}", created.id);
+            }
+        }
+
+        self.refresh(zone).await
+    }
+
+    async fn get_record(
+        &self,
+        zone: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<Option<DnsRecord>, DnsError> {
+        let zone = zone.trim_end_matches('.');
+        let sub_domain = Self::sub_domain(zone, record_name)?;
+        let Some(id) = self
+            .find_records(zone, &sub_domain, record_type)
+            .await?
+            .first()
+            .copied()
+        else {
+            tracing::debug!("No matching record found");
+            return Ok(None);
+        };
+
+        let record: OvhRecord = self
+            .request(
+                Method::GET,
+                &format!("/domain/zone/{}/record/{}", zone, id),
+                &[],
+                None,
+            )
+            .await?;
+        if record.field_type != record_type.to_string() {
+            return Ok(None);
+        }
+
+        let mut dns_record = DnsRecord::new(
+            format!("{}.", record_name.trim_end_matches('.')),
+            record.ttl,
+            RecordData::parse(record_type, &record.target)?,
+        );
+        dns_record.id = Some(record.id.to_string());
+        tracing::debug!(
+            "Found OVH record {} for {} in {}",
+            record.id,
+            record.sub_domain,
+            zone
+        );
+        Ok(Some(dns_record))
+    }
+
+    async fn delete_record(
+        &self,
+        zone: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<bool, DnsError> {
+        let zone = zone.trim_end_matches('.');
+        let sub_domain = Self::sub_domain(zone, record_name)?;
+        let ids = self.find_records(zone, &sub_domain, record_type).await?;
+        if ids.is_empty() {
+            return Ok(false);
+        }
+
+        tracing::info!("Deleting DNS record: {}", record_name);
+        for id in ids {
+            self.request::<()>(
+                Method::DELETE,
+                &format!("/domain/zone/{}/record/{}", zone, id),
+                &[],
+                None,
+            )
+            .await?;
+        }
+        self.refresh(zone).await?;
+        Ok(true)
+    }
+
+    fn validate_record(&self, record: &DnsRecord) -> Result<(), DnsError> {
+        validate_record_name(&record.name)?;
+        validate_record_data(&record.data)?;
+        validate_ttl(record.ttl)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        extract::State,
+        http::{HeaderMap, Uri},
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering};
+
+    const APPLICATION_KEY: &str = "key";
+    const APPLICATION_SECRET: &str = "secret";
+    const CONSUMER_KEY: &str = "consumer";
+
+    /// OVH API checking signatures and timestamps, with the records of the
+    /// `example.com` zone
+    struct MockOvh {
+        url: String,
+        // seconds the API clock is ahead of the local one
+        clock_offset: AtomicI64,
+        reject_timestamps: AtomicBool,
+        time_requests: AtomicUsize,
+        records: std::sync::Mutex<BTreeMap<u64, Value>>,
+        // method, path and query of the signed requests
+        requests: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl MockOvh {
+        fn now(&self) -> i64 {
+            OvhDns::local_time() + self.clock_offset.load(Ordering::SeqCst)
+        }
+
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    async fn time(State(mock): State<Arc<MockOvh>>) -> Json<i64> {
+        mock.time_requests.fetch_add(1, Ordering::SeqCst);
+        Json(mock.now())
+    }
+
+    fn error(status: StatusCode, message: &str) -> Response {
+        (status, Json(json!({"message": message}))).into_response()
+    }
+
+    async fn api(
+        State(mock): State<Arc<MockOvh>>,
+        method: axum::http::Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: String,
+    ) -> Response {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let timestamp: i64 = header("x-ovh-timestamp").parse().unwrap();
+        if mock.reject_timestamps.load(Ordering::SeqCst) || (timestamp - mock.now()).abs() > 30 {
+            return error(StatusCode::BAD_REQUEST, "Invalid timestamp");
+        }
+        let url = format!("{}{}", mock.url, uri);
+        let signed = format!(
+            "{}+{}+{}+{}+{}+{}",
+            APPLICATION_SECRET, CONSUMER_KEY, method, url, body, timestamp
+        );
+        if header("x-ovh-application") != APPLICATION_KEY
+            || header("x-ovh-signature") != format!("$1${}", hex::encode(Sha1::digest(signed)))
+        {
+            return error(StatusCode::BAD_REQUEST, "Invalid signature");
+        }
+        mock.requests
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", method, uri));
+
+        let path: Vec<&str> = uri.path().split('/').collect();
+        let mut records = mock.records.lock().unwrap();
+        match (method.as_str(), &path[..]) {
+            ("POST", ["", "1.0", "domain", "zone", "example.com", "refresh"]) => {
+                Json(Value::Null).into_response()
+            }
+            ("GET", ["", "1.0", "domain", "zone", "example.com", "record"]) => {
+                let query: std::collections::HashMap<String, String> = Url::parse(&url)
+                    .unwrap()
+                    .query_pairs()
+                    .into_owned()
+                    .collect();
+                let ids: Vec<u64> = records
+                    .iter()
+                    .filter(|(_, record)| {
+                        record["fieldType"] == query["fieldType"]
+                            && record["subDomain"] == query["subDomain"]
+                    })
+                    .map(|(id, _)| *id)
+                    .collect();
+                Json(json!(ids)).into_response()
+            }
+            ("POST", ["", "1.0", "domain", "zone", "example.com", "record"]) => {
+                let id = records.keys().max().map_or(1, |id| id + 1);
+                let mut record: Value = serde_json::from_str(&body).unwrap();
+                record["id"] = json!(id);
+                records.insert(id, record.clone());
+                Json(record).into_response()
+            }
+            (method, ["", "1.0", "domain", "zone", "example.com", "record", id]) => {
+                let id: u64 = id.parse().unwrap();
+                let Some(record) = records.get_mut(&id) else {
+                    return error(StatusCode::NOT_FOUND, "This service does not exist");
+                };
+                match method {
+                    "GET" => Json(record.clone()).into_response(),
+                    "PUT" => {
+                        let update: Value = serde_json::from_str(&body).unwrap();
+                        for (field, value) in update.as_object().unwrap() {
+                            record[field] = value.clone();
+                        }
+                        StatusCode::OK.into_response()
+                    }
+                    "DELETE" => {
+                        records.remove(&id);
+                        Json(Value::Null).into_response()
+                    }
+                    _ => error(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+                }
+            }
+            _ => error(StatusCode::NOT_FOUND, "This service does not exist"),
+        }
+    }
+
+    async fn start_mock(records: &[(&str, &str, &str)]) -> (Arc<MockOvh>, OvhDns) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mock = Arc::new(MockOvh {
+            url: url.clone(),
+            clock_offset: AtomicI64::new(500),
+            reject_timestamps: AtomicBool::new(false),
+            time_requests: AtomicUsize::new(0),
+            records: std::sync::Mutex::new(
+                records
+                    .iter()
+                    .zip(1..)
+                    .map(|((field_type, sub_domain, target), id)| {
+                        (
+                            id,
+                            json!({
+                                "id": id,
+                                "fieldType": field_type,
+                                "subDomain": sub_domain,
+                                "target": target,
+                                "ttl": 300,
+                                "zone": "example.com",
+                            }),
+                        )
+                    })
+                    .collect(),
+            ),
+            requests: std::sync::Mutex::new(Vec::new()),
+        });
+        let app = Router::new()
+            .route("/1.0/auth/time", get(time))
+            .fallback(api)
+            .with_state(mock.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let ovh = OvhDns::new(&Ovh {
+            application_key: Some(APPLICATION_KEY.to_string()),
+            application_secret: Some(APPLICATION_SECRET.to_string()),
+            consumer_key: Some(CONSUMER_KEY.to_string()),
+            endpoint: format!("{}/1.0", url),
+        })
+        .unwrap();
+        (mock, ovh)
+    }
+
+    fn a_record(address: &str) -> DnsRecord {
+        DnsRecord::new(
+            "home.example.com.".to_string(),
+            300,
+            RecordData::parse(&RecordType::A, address).unwrap(),
+        )
+    }
+
+    #[test]
+    fn signature_covers_method_url_body_and_timestamp() {
+        let ovh = OvhDns::new(&Ovh {
+            application_key: Some(APPLICATION_KEY.to_string()),
+            application_secret: Some(APPLICATION_SECRET.to_string()),
+            consumer_key: Some(CONSUMER_KEY.to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            ovh.sign(
+                &Method::GET,
+                "https://eu.api.ovh.com/1.0/domain/zone/example.com/record?fieldType=A&subDomain=home",
+                "",
+                1700000000
+            ),
+            "$1$3fbde4fd256c9a29b6384c88fb34a89dd106f417"
+        );
+        assert_eq!(
+            ovh.sign(
+                &Method::PUT,
+                "https://eu.api.ovh.com/1.0/domain/zone/example.com/record/42",
+                r#"{"target":"203.0.113.1"}"#,
+                1700000000
+            ),
+            "$1$f5e0c26ef60b6d80e34dbd07cd32dbf29c209b7b"
+        );
+    }
+
+    #[test]
+    fn sub_domain_is_relative_to_the_zone() {
+        assert_eq!(
+            OvhDns::sub_domain("example.com", "example.com.").unwrap(),
+            ""
+        );
+        assert_eq!(
+            OvhDns::sub_domain("example.com.", "home.example.com.").unwrap(),
+            "home"
+        );
+        assert_eq!(
+            OvhDns::sub_domain("Example.com", "_mc._tcp.Home.EXAMPLE.com").unwrap(),
+            "_mc._tcp.home"
+        );
+        assert!(matches!(
+            OvhDns::sub_domain("example.com", "home.example.org."),
+            Err(DnsError::ValidationError(_))
+        ));
+        assert!(matches!(
+            OvhDns::sub_domain("example.com", "homeexample.com."),
+            Err(DnsError::ValidationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn timestamp_follows_the_api_clock() {
+        let (mock, ovh) = start_mock(&[]).await;
+
+        let first = ovh.timestamp().await.unwrap();
+        let second = ovh.timestamp().await.unwrap();
+
+        assert!((first - mock.now()).abs() <= 1);
+        assert!((second - mock.now()).abs() <= 1);
+        assert_eq!(mock.time_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_timestamp_resyncs_the_clock() {
+        let (mock, ovh) = start_mock(&[("A", "home", "203.0.113.1")]).await;
+        ovh.get_record("example.com", "home.example.com.", &RecordType::A)
+            .await
+            .unwrap();
+
+        mock.clock_offset.store(-900, Ordering::SeqCst);
+        let record = ovh
+            .get_record("example.com", "home.example.com.", &RecordType::A)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(record.data, a_record("203.0.113.1").data);
+        assert_eq!(mock.time_requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_timestamp_is_retried_once() {
+        let (mock, ovh) = start_mock(&[]).await;
+        mock.reject_timestamps.store(true, Ordering::SeqCst);
+
+        let result = ovh
+            .get_record("example.com", "home.example.com.", &RecordType::A)
+            .await;
+
+        assert!(
+            matches!(result, Err(DnsError::ApiError(message)) if message.contains("Invalid timestamp"))
+        );
+        assert_eq!(mock.time_requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn records_are_found_by_type_and_sub_domain() {
+        let (mock, ovh) = start_mock(&[
+            ("AAAA", "home", "2001:db8::1"),
+            ("A", "home", "203.0.113.1"),
+        ])
+        .await;
+
+        let ids = ovh
+            .find_records("example.com", "home", &RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(ids, vec![2]);
+        assert_eq!(
+            mock.requests(),
+            ["GET /1.0/domain/zone/example.com/record?fieldType=A&subDomain=home"]
+        );
+    }
+
+    #[tokio::test]
+    async fn existing_record_is_updated_and_duplicates_deleted() {
+        let (mock, ovh) =
+            start_mock(&[("A", "home", "203.0.113.1"), ("A", "home", "203.0.113.2")]).await;
+
+        ovh.update_record("example.com", &a_record("203.0.113.9"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mock.requests(),
+            [
+                "GET /1.0/domain/zone/example.com/record?fieldType=A&subDomain=home",
+                "PUT /1.0/domain/zone/example.com/record/1",
+                "DELETE /1.0/domain/zone/example.com/record/2",
+                "POST /1.0/domain/zone/example.com/refresh",
+            ]
+        );
+        let records = mock.records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[&1]["target"], "203.0.113.9");
+    }
+
+    #[tokio::test]
+    async fn missing_record_is_created() {
+        let (mock, ovh) = start_mock(&[("AAAA", "home", "2001:db8::1")]).await;
+
+        ovh.update_record("example.com", &a_record("203.0.113.9"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mock.requests(),
+            [
+                "GET /1.0/domain/zone/example.com/record?fieldType=A&subDomain=home",
+                "POST /1.0/domain/zone/example.com/record",
+                "POST /1.0/domain/zone/example.com/refresh",
+            ]
+        );
+        assert_eq!(mock.records.lock().unwrap()[&2]["fieldType"], "A");
+    }
+
+    #[tokio::test]
+    async fn every_record_is_deleted() {
+        let (mock, ovh) =
+            start_mock(&[("A", "home", "203.0.113.1"), ("A", "home", "203.0.113.2")]).await;
+
+        assert!(
+            ovh.delete_record("example.com", "home.example.com.", &RecordType::A)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !ovh.delete_record("example.com", "home.example.com.", &RecordType::A)
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(
+            mock.requests(),
+            [
+                "GET /1.0/domain/zone/example.com/record?fieldType=A&subDomain=home",
+                "DELETE /1.0/domain/zone/example.com/record/1",
+                "DELETE /1.0/domain/zone/example.com/record/2",
+                "POST /1.0/domain/zone/example.com/refresh",
+                "GET /1.0/domain/zone/example.com/record?fieldType=A&subDomain=home",
+            ]
+        );
+    }
+}
@@ -0,0 +1,932 @@
+use super::{
+    DnsError, DnsProvider, DnsRecord, RecordData, validate_record_data, validate_record_name,
+    validate_ttl,
+};
+use crate::config::{RecordType, Route53};
+use crate::utils::{xml_element_text, xml_escape};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, Url};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing;
+
+const ROUTE53_API_VERSION: &str = "2013-04-01";
+const ROUTE53_SERVICE: &str = "route53";
+const ROUTE53_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// delay between two GetChange calls while a change is PENDING
+const ROUTE53_CHANGE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+type HmacSha256 = Hmac<Sha256>;
+
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    /// Keys from the configuration, the environment or the shared credentials
+    /// file, in this order
+    fn resolve(config: &Route53) -> Result<Self, DnsError> {
+        match (&config.access_key_id, &config.secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => {
+                return Ok(Self {
+                    access_key_id: access_key_id.clone(),
+                    secret_access_key: secret_access_key.clone(),
+                    session_token: config.session_token.clone(),
+                });
+            }
+            (None, None) => {}
+            _ => {
+                return Err(DnsError::ValidationError(
+                    "Both access_key_id and secret_access_key are required for Route 53"
+                        .to_string(),
+                ));
+            }
+        }
+
+        if let (Ok(access_key_id), Ok(secret_access_key)) = (
+            std::env::var("AWS_ACCESS_KEY_ID"),
+            std::env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            return Ok(Self {
+                access_key_id,
+                secret_access_key,
+                session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        }
+
+        let profile = config
+            .profile
+            .clone()
+            .or_else(|| std::env::var("AWS_PROFILE").ok())
+            .unwrap_or_else(|| "default".to_string());
+        let path = std::env::var_os("AWS_SHARED_CREDENTIALS_FILE")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".aws").join("credentials"))
+            })
+            .ok_or_else(|| {
+                DnsError::ValidationError("No AWS credentials found for Route 53".to_string())
+            })?;
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            DnsError::ValidationError(format!(
+                "No AWS credentials found for Route 53 ({}: {})",
+                path.display(),
+                e
+            ))
+        })?;
+
+        Self::from_shared_file(&content, &profile).ok_or_else(|| {
+            DnsError::ValidationError(format!(
+                "No AWS credentials for profile {} in {}",
+                profile,
+                path.display()
+            ))
+        })
+    }
+
+    /// Keys of a profile of the INI shared credentials file
+    fn from_shared_file(content: &str, profile: &str) -> Option<Self> {
+        let mut section = None;
+        let mut keys = HashMap::new();
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = Some(name.trim().to_string());
+            } else if section.as_deref() == Some(profile)
+                && let Some((key, value)) = line.split_once('=')
+            {
+                keys.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+
+        Some(Self {
+            access_key_id: keys.remove("aws_access_key_id")?,
+            secret_access_key: keys.remove("aws_secret_access_key")?,
+            session_token: keys.remove("aws_session_token"),
+        })
+    }
+}
+
+/// Resource record set, with the values in presentation format
+struct ResourceRecordSet {
+    name: String,
+    ttl: u32,
+    values: Vec<String>,
+}
+
+pub struct Route53Dns {
+    client: Client,
+    credentials: Credentials,
+    endpoint: String,
+    region: String,
+    hosted_zone_id: Option<String>,
+    change_timeout: Duration,
+    change_poll_interval: Duration,
+    // hosted zone IDs by zone name
+    zone_ids: Mutex<HashMap<String, String>>,
+}
+
+impl Route53Dns {
+    pub fn new(config: &Route53) -> Result<Self, DnsError> {
+        let client = Client::builder()
+            .timeout(ROUTE53_REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| DnsError::ApiError(format!("Failed to create Route 53 client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            credentials: Credentials::resolve(config)?,
+            endpoint: config.endpoint.trim_end_matches('/').to_string(),
+            region: config.region.clone(),
+            hosted_zone_id: config.hosted_zone_id.clone(),
+            change_timeout: config.change_timeout_in_seconds,
+            change_poll_interval: ROUTE53_CHANGE_POLL_INTERVAL,
+            zone_ids: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// URI encoding of SigV4, only unreserved characters are kept
+    fn uri_encode(value: &str) -> String {
+        value
+            .bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            })
+            .collect()
+    }
+
+    fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Headers of a request signed with AWS Signature Version 4
+    fn sign(&self, method: &Method, url: &Url, payload: &str) -> Vec<(&'static str, String)> {
+        self.sign_at(method, url, payload, Utc::now(), ROUTE53_SERVICE)
+    }
+
+    fn sign_at(
+        &self,
+        method: &Method,
+        url: &Url,
+        payload: &str,
+        now: DateTime<Utc>,
+        service: &str,
+    ) -> Vec<(&'static str, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        // canonical headers, sorted by name
+        let mut headers = vec![("host", host), ("x-amz-date", amz_date.clone())];
+        if let Some(session_token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", session_token.clone()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request =
+            Self::canonical_request(method, url, &headers, &signed_headers, payload);
+
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = [self.region.as_str(), service, "aws4_request"].iter().fold(
+            Self::hmac(
+                format!("AWS4{}", self.credentials.secret_access_key).as_bytes(),
+                &date,
+            ),
+            |key, data| Self::hmac(&key, data),
+        );
+        let signature = hex::encode(Self::hmac(&key, &string_to_sign));
+
+        headers.retain(|(name, _)| *name != "host");
+        headers.push((
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.credentials.access_key_id, scope, signed_headers, signature
+            ),
+        ));
+        headers
+    }
+
+    /// Canonical request of SigV4, `headers` being sorted by name
+    fn canonical_request(
+        method: &Method,
+        url: &Url,
+        headers: &[(&str, String)],
+        signed_headers: &str,
+        payload: &str,
+    ) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            url.path(),
+            url.query().unwrap_or_default(),
+            headers
+                .iter()
+                .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+                .collect::<String>(),
+            signed_headers,
+            hex::encode(Sha256::digest(payload.as_bytes()))
+        )
+    }
+
+    /// Send a signed request to the API, returning the XML response
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<String>,
+    ) -> Result<String, DnsError> {
+        // the query is signed as sent, sorted and encoded
+        let mut query: Vec<String> = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", Self::uri_encode(name), Self::uri_encode(value)))
+            .collect();
+        query.sort();
+        let url = format!(
+            "{}/{}{}{}",
+            self.endpoint,
+            ROUTE53_API_VERSION,
+            path,
+            if query.is_empty() {
+                String::new()
+            } else {
+                format!("?{}", query.join("&"))
+            }
+        );
+        let url = Url::parse(&url).map_err(|e| {
+            DnsError::ValidationError(format!("Invalid Route 53 URL {}: {}", url, e))
+        })?;
+
+        let body = body.unwrap_or_default();
+        let mut request = self.client.request(method.clone(), url.clone());
+        for (name, value) in self.sign(&method, &url, &body) {
+            request = request.header(name, value);
+        }
+        if !body.is_empty() {
+            request = request.header("content-type", "text/xml").body(body);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| DnsError::ApiError(format!("Route 53 request failed: {}", e)))?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|e| DnsError::ApiError(format!("Route 53 request failed: {}", e)))?;
+
+        if !status.is_success() {
+            // <ErrorResponse><Error><Code>..</Code><Message>..</Message></Error></ErrorResponse>
+            return Err(DnsError::ApiError(format!(
+                "Route 53 error ({}): {} {}",
+                status,
+                xml_element_text(&text, "Code").unwrap_or_default(),
+                xml_element_text(&text, "Message").unwrap_or_default()
+            )));
+        }
+        Ok(text)
+    }
+
+    fn parse(xml: &str) -> Result<roxmltree::Document<'_>, DnsError> {
+        roxmltree::Document::parse(xml)
+            .map_err(|e| DnsError::ApiError(format!("Invalid Route 53 response: {}", e)))
+    }
+
+    fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+        node.children()
+            .find(|child| child.tag_name().name() == name)
+            .and_then(|child| child.text())
+    }
+
+    /// Names are returned lowercase with `*` escaped as `\052`
+    fn same_name(route53_name: &str, name: &str) -> bool {
+        route53_name
+            .replace("\\052", "*")
+            .trim_end_matches('.')
+            .eq_ignore_ascii_case(name.trim_end_matches('.'))
+    }
+
+    async fn get_zone_id(&self, zone: &str) -> Result<String, DnsError> {
+        if let Some(zone_id) = &self.hosted_zone_id {
+            return Ok(zone_id.clone());
+        }
+        if let Some(zone_id) = self
+            .zone_ids
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(zone)
+        {
+            return Ok(zone_id.clone());
+        }
+
+        // the longest hosted zone holding the name, from the name itself up
+        let labels: Vec<&str> = zone.trim_end_matches('.').split('.').collect();
+        for start in 0..labels.len() {
+            let zone_name = format!("{}.", labels[start..].join("."));
+            let xml = self
+                .request(
+                    Method::GET,
+                    "/hostedzonesbyname",
+                    &[("dnsname", &zone_name), ("maxitems", "10")],
+                    None,
+                )
+                .await?;
+            let document = Self::parse(&xml)?;
+
+            // zones are listed from the given name on, private ones are skipped
+            let Some(zone_id) = document
+                .descendants()
+                .filter(|node| node.tag_name().name() == "HostedZone")
+                .filter(|zone| {
+                    Self::child_text(*zone, "Name")
+                        .is_some_and(|name| Self::same_name(name, &zone_name))
+                })
+                .find(|zone| {
+                    zone.descendants()
+                        .find(|node| node.tag_name().name() == "PrivateZone")
+                        .and_then(|node| node.text())
+                        != Some("true")
+                })
+                .and_then(|zone| Self::child_text(zone, "Id"))
+                .map(|id| id.trim_start_matches("/hostedzone/").to_string())
+            else {
+                continue;
+            };
+
+            tracing::debug!(
+                "Found Route 53 hosted zone {} ({}) for {}",
+                zone_id,
+                zone_name,
+                zone
+            );
+            self.zone_ids
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .insert(zone.to_string(), zone_id.clone());
+            return Ok(zone_id);
+        }
+
+        Err(DnsError::ZoneNotFound(format!(
+            "Hosted zone '{}' not found",
+            zone
+        )))
+    }
+
+    async fn find_record_set(
+        &self,
+        zone_id: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<Option<ResourceRecordSet>, DnsError> {
+        let record_type = record_type.to_string();
+        let xml = self
+            .request(
+                Method::GET,
+                &format!("/hostedzone/{}/rrset", zone_id),
+                &[
+                    ("name", record_name),
+                    ("type", &record_type),
+                    ("maxitems", "1"),
+                ],
+                None,
+            )
+            .await?;
+        let document = Self::parse(&xml)?;
+
+        // sets are listed from the given name and type on
+        Ok(document
+            .descendants()
+            .filter(|node| node.tag_name().name() == "ResourceRecordSet")
+            .find(|set| {
+                Self::child_text(*set, "Name")
+                    .is_some_and(|name| Self::same_name(name, record_name))
+                    && Self::child_text(*set, "Type") == Some(record_type.as_str())
+            })
+            .map(|set| ResourceRecordSet {
+                name: Self::child_text(set, "Name")
+                    .unwrap_or(record_name)
+                    .to_string(),
+                ttl: Self::child_text(set, "TTL")
+                    .and_then(|ttl| ttl.parse().ok())
+                    .unwrap_or_default(),
+                // alias records have no values
+                values: set
+                    .descendants()
+                    .filter(|node| node.tag_name().name() == "Value")
+                    .filter_map(|node| node.text())
+                    .map(str::to_string)
+                    .collect(),
+            }))
+    }
+
+    /// Apply a change to a record set, waiting until it is `INSYNC` when a
+    /// change timeout is set
+    async fn change_record_set(
+        &self,
+        zone_id: &str,
+        action: &str,
+        record_type: &RecordType,
+        record_set: &ResourceRecordSet,
+    ) -> Result<(), DnsError> {
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<ChangeResourceRecordSetsRequest xmlns="https://route53.amazonaws.com/doc/{}/">"#,
+                "<ChangeBatch><Changes><Change><Action>{}</Action><ResourceRecordSet>",
+                "<Name>{}</Name><Type>{}</Type><TTL>{}</TTL><ResourceRecords>{}</ResourceRecords>",
+                "</ResourceRecordSet></Change></Changes></ChangeBatch>",
+                "</ChangeResourceRecordSetsRequest>"
+            ),
+            ROUTE53_API_VERSION,
+            action,
+            xml_escape(&record_set.name),
+            record_type,
+            record_set.ttl,
+            record_set
+                .values
+                .iter()
+                .map(|value| format!(
+                    "<ResourceRecord><Value>{}</Value></ResourceRecord>",
+                    xml_escape(value)
+                ))
+                .collect::<String>()
+        );
+
+        let xml = self
+            .request(
+                Method::POST,
+                &format!("/hostedzone/{}/rrset/", zone_id),
+                &[],
+                Some(body),
+            )
+            .await?;
+        let mut status = xml_element_text(&xml, "Status");
+        let change_id = xml_element_text(&xml, "Id")
+            .map(|id| id.trim_start_matches("/change/").to_string())
+            .ok_or_else(|| DnsError::ApiError("Route 53 change has no ID".to_string()))?;
+
+        if self.change_timeout.is_zero() {
+            tracing::debug!(
+                "Route 53 change {} is {}",
+                change_id,
+                status.as_deref().unwrap_or("unknown")
+            );
+            return Ok(());
+        }
+
+        // the change has been accepted and is applied anyway, the wait only
+        // reports when it is served
+        let deadline = Instant::now() + self.change_timeout;
+        while status.as_deref() != Some("INSYNC") {
+            if Instant::now() >= deadline {
+                tracing::warn!(
+                    "Route 53 change {} still {} after {:?}",
+                    change_id,
+                    status.as_deref().unwrap_or("unknown"),
+                    self.change_timeout
+                );
+                return Ok(());
+            }
+            tracing::debug!("Waiting for Route 53 change {} to be in sync", change_id);
+            tokio::time::sleep(self.change_poll_interval).await;
+
+            let xml = self
+                .request(Method::GET, &format!("/change/{}", change_id), &[], None)
+                .await?;
+            status = xml_element_text(&xml, "Status");
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Route53Dns {
+    async fn update_record(&self, zone: &str, record: &DnsRecord) -> Result<(), DnsError> {
+        self.validate_record(record)?;
+
+        let zone_id = self.get_zone_id(zone).await?;
+        tracing::info!("Upserting DNS record: {}", record.name);
+        self.change_record_set(
+            &zone_id,
+            "UPSERT",
+            &record.record_type(),
+            &ResourceRecordSet {
+                name: record.name.clone(),
+                ttl: record.ttl,
+                values: vec![record.data.to_string()],
+            },
+        )
+        .await
+    }
+
+    async fn get_record(
+        &self,
+        zone: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<Option<DnsRecord>, DnsError> {
+        let zone_id = self.get_zone_id(zone).await?;
+        let Some(record_set) = self
+            .find_record_set(&zone_id, record_name, record_type)
+            .await?
+        else {
+            tracing::debug!("No matching record found");
+            return Ok(None);
+        };
+        // an alias or a set of several values is reported by its first value
+        let Some(value) = record_set.values.first() else {
+            tracing::debug!("Record {} is an alias", record_name);
+            return Ok(None);
+        };
+
+        Ok(Some(DnsRecord::new(
+            record_set.name.replace("\\052", "*"),
+            record_set.ttl,
+            RecordData::parse(record_type, value)?,
+        )))
+    }
+
+    async fn delete_record(
+        &self,
+        zone: &str,
+        record_name: &str,
+        record_type: &RecordType,
+    ) -> Result<bool, DnsError> {
+        let zone_id = self.get_zone_id(zone).await?;
+        // a deletion must match the current record set exactly
+        let Some(record_set) = self
+            .find_record_set(&zone_id, record_name, record_type)
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        tracing::info!("Deleting DNS record: {}", record_name);
+        self.change_record_set(&zone_id, "DELETE", record_type, &record_set)
+            .await?;
+        Ok(true)
+    }
+
+    fn validate_record(&self, record: &DnsRecord) -> Result<(), DnsError> {
+        validate_record_name(&record.name)?;
+        validate_record_data(&record.data)?;
+        validate_ttl(record.ttl)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        extract::{Path, Query, State},
+        http::HeaderMap,
+        routing::{get, post},
+    };
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // `get-vanilla` case of the AWS SigV4 test suite
+    const VANILLA_CANONICAL_REQUEST: &str = "GET\n/\n\nhost:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\nhost;x-amz-date\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const VANILLA_AUTHORIZATION: &str = "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31";
+
+    fn route53(session_token: Option<&str>) -> Route53Dns {
+        Route53Dns::new(&Route53 {
+            access_key_id: Some("AKIDEXAMPLE".to_string()),
+            secret_access_key: Some("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string()),
+            session_token: session_token.map(str::to_string),
+            ..Route53::default()
+        })
+        .unwrap()
+    }
+
+    fn vanilla_time() -> DateTime<Utc> {
+        "2015-08-30T12:36:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn canonical_request_matches_test_suite() {
+        let url = Url::parse("https://example.amazonaws.com/").unwrap();
+        let headers = [
+            ("host", "example.amazonaws.com".to_string()),
+            ("x-amz-date", "20150830T123600Z".to_string()),
+        ];
+
+        assert_eq!(
+            Route53Dns::canonical_request(&Method::GET, &url, &headers, "host;x-amz-date", ""),
+            VANILLA_CANONICAL_REQUEST
+        );
+    }
+
+    #[test]
+    fn signature_matches_test_suite() {
+        let url = Url::parse("https://example.amazonaws.com/").unwrap();
+
+        let headers = route53(None).sign_at(&Method::GET, &url, "", vanilla_time(), "service");
+
+        assert_eq!(
+            headers,
+            vec![
+                ("x-amz-date", "20150830T123600Z".to_string()),
+                ("authorization", VANILLA_AUTHORIZATION.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn session_token_is_signed() {
+        let url = Url::parse("https://example.amazonaws.com/").unwrap();
+
+        let headers =
+            route53(Some("token")).sign_at(&Method::GET, &url, "", vanilla_time(), "service");
+
+        assert!(headers.contains(&("x-amz-security-token", "token".to_string())));
+        assert!(headers.iter().any(|(name, value)| *name == "authorization"
+            && value.contains("SignedHeaders=host;x-amz-date;x-amz-security-token")));
+    }
+
+    #[test]
+    fn query_is_encoded_as_signed() {
+        assert_eq!(
+            Route53Dns::uri_encode("_sip._tcp.home.example.com."),
+            "_sip._tcp.home.example.com."
+        );
+        assert_eq!(Route53Dns::uri_encode("a b/*"), "a%20b%2F%2A");
+    }
+
+    /// Route 53 API with a few hosted zones, holding changes `PENDING` for
+    /// `pending_polls` GetChange calls
+    struct MockRoute53 {
+        // ID, name and whether the zone is private
+        zones: Vec<(&'static str, &'static str, bool)>,
+        pending_polls: usize,
+        zone_lookups: std::sync::Mutex<Vec<String>>,
+        changes: std::sync::Mutex<Vec<String>>,
+        polls: AtomicUsize,
+    }
+
+    /// Order of hosted zones in ListHostedZonesByName, labels reversed
+    fn zone_order(name: &str) -> Vec<String> {
+        name.trim_end_matches('.')
+            .rsplit('.')
+            .map(str::to_string)
+            .collect()
+    }
+
+    async fn hosted_zones_by_name(
+        State(mock): State<Arc<MockRoute53>>,
+        headers: HeaderMap,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> String {
+        assert!(headers.contains_key("authorization"));
+        let dns_name = &query["dnsname"];
+        mock.zone_lookups.lock().unwrap().push(dns_name.clone());
+
+        let mut zones = mock.zones.clone();
+        zones.sort_by_key(|(_, name, _)| zone_order(name));
+        let zones: String = zones
+            .iter()
+            .filter(|(_, name, _)| zone_order(name) >= zone_order(dns_name))
+            .take(query["maxitems"].parse().unwrap())
+            .map(|(id, name, private)| {
+                format!(
+                    "<HostedZone><Id>/hostedzone/{}</Id><Name>{}</Name><Config><PrivateZone>{}</PrivateZone></Config></HostedZone>",
+                    id, name, private
+                )
+            })
+            .collect();
+        format!(
+            r#"<?xml version="1.0"?><ListHostedZonesByNameResponse xmlns="https://route53.amazonaws.com/doc/2013-04-01/"><HostedZones>{}</HostedZones><IsTruncated>false</IsTruncated></ListHostedZonesByNameResponse>"#,
+            zones
+        )
+    }
+
+    async fn change_record_sets(
+        State(mock): State<Arc<MockRoute53>>,
+        Path(zone_id): Path<String>,
+        body: String,
+    ) -> String {
+        assert_eq!(zone_id, "Z1");
+        let mut changes = mock.changes.lock().unwrap();
+        changes.push(body);
+        format!(
+            r#"<?xml version="1.0"?><ChangeResourceRecordSetsResponse xmlns="https://route53.amazonaws.com/doc/2013-04-01/"><ChangeInfo><Id>/change/C{}</Id><Status>PENDING</Status></ChangeInfo></ChangeResourceRecordSetsResponse>"#,
+            changes.len()
+        )
+    }
+
+    async fn get_change(
+        State(mock): State<Arc<MockRoute53>>,
+        Path(change_id): Path<String>,
+    ) -> String {
+        assert_eq!(change_id, "C1");
+        let polls = mock.polls.fetch_add(1, Ordering::SeqCst) + 1;
+        format!(
+            r#"<?xml version="1.0"?><GetChangeResponse xmlns="https://route53.amazonaws.com/doc/2013-04-01/"><ChangeInfo><Id>/change/C1</Id><Status>{}</Status></ChangeInfo></GetChangeResponse>"#,
+            if polls > mock.pending_polls {
+                "INSYNC"
+            } else {
+                "PENDING"
+            }
+        )
+    }
+
+    async fn start_mock(
+        zones: Vec<(&'static str, &'static str, bool)>,
+        pending_polls: usize,
+        change_timeout: Duration,
+    ) -> (Arc<MockRoute53>, Route53Dns) {
+        let mock = Arc::new(MockRoute53 {
+            zones,
+            pending_polls,
+            zone_lookups: std::sync::Mutex::new(Vec::new()),
+            changes: std::sync::Mutex::new(Vec::new()),
+            polls: AtomicUsize::new(0),
+        });
+        let app = Router::new()
+            .route("/2013-04-01/hostedzonesbyname", get(hosted_zones_by_name))
+            .route(
+                "/2013-04-01/hostedzone/{zone_id}/rrset/",
+                post(change_record_sets),
+            )
+            .route("/2013-04-01/change/{change_id}", get(get_change))
+            .with_state(mock.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut route53 = Route53Dns::new(&Route53 {
+            access_key_id: Some("AKIDEXAMPLE".to_string()),
+            secret_access_key: Some("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string()),
+            endpoint,
+            change_timeout_in_seconds: change_timeout,
+            ..Route53::default()
+        })
+        .unwrap();
+        route53.change_poll_interval = Duration::from_millis(10);
+        (mock, route53)
+    }
+
+    fn zones() -> Vec<(&'static str, &'static str, bool)> {
+        vec![
+            ("Z1", "example.com.", false),
+            ("Z2", "home.example.com.", true),
+            ("Z3", "lan.example.com.", false),
+            ("Z4", "example.org.", false),
+        ]
+    }
+
+    #[tokio::test]
+    async fn hosted_zone_is_found_by_name() {
+        let (mock, route53) = start_mock(zones(), 0, Duration::ZERO).await;
+
+        assert_eq!(route53.get_zone_id("example.com").await.unwrap(), "Z1");
+        assert_eq!(route53.get_zone_id("example.com").await.unwrap(), "Z1");
+        assert_eq!(route53.get_zone_id("lan.example.com.").await.unwrap(), "Z3");
+
+        assert_eq!(
+            *mock.zone_lookups.lock().unwrap(),
+            ["example.com.", "lan.example.com."]
+        );
+    }
+
+    #[tokio::test]
+    async fn longest_public_zone_is_picked() {
+        let (mock, route53) = start_mock(zones(), 0, Duration::ZERO).await;
+
+        // home.example.com is private, the name belongs to example.com
+        assert_eq!(
+            route53.get_zone_id("nas.home.example.com.").await.unwrap(),
+            "Z1"
+        );
+        assert_eq!(
+            route53.get_zone_id("nas.lan.example.com").await.unwrap(),
+            "Z3"
+        );
+        assert!(matches!(
+            route53.get_zone_id("example.net").await,
+            Err(DnsError::ZoneNotFound(_))
+        ));
+
+        assert_eq!(
+            *mock.zone_lookups.lock().unwrap(),
+            [
+                "nas.home.example.com.",
+                "home.example.com.",
+                "example.com.",
+                "nas.lan.example.com.",
+                "lan.example.com.",
+                "example.net.",
+                "net.",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn upsert_is_polled_until_in_sync() {
+        let (mock, route53) = start_mock(zones(), 2, Duration::from_secs(5)).await;
+
+        route53
+            .update_record(
+                "example.com",
+                &DnsRecord::new(
+                    "home.example.com.".to_string(),
+                    300,
+                    RecordData::parse(&RecordType::Txt, "a&b").unwrap(),
+                ),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *mock.changes.lock().unwrap(),
+            [concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<ChangeResourceRecordSetsRequest xmlns="https://route53.amazonaws.com/doc/2013-04-01/">"#,
+                "<ChangeBatch><Changes><Change><Action>UPSERT</Action><ResourceRecordSet>",
+                "<Name>home.example.com.</Name><Type>TXT</Type><TTL>300</TTL>",
+                "<ResourceRecords><ResourceRecord><Value>&quot;a&amp;b&quot;</Value></ResourceRecord></ResourceRecords>",
+                "</ResourceRecordSet></Change></Changes></ChangeBatch>",
+                "</ChangeResourceRecordSetsRequest>"
+            )]
+        );
+        assert_eq!(mock.polls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn pending_change_stops_being_polled_after_timeout() {
+        let (mock, route53) = start_mock(zones(), usize::MAX, Duration::from_millis(100)).await;
+
+        let started = Instant::now();
+        route53
+            .update_record(
+                "example.com",
+                &DnsRecord::new(
+                    "home.example.com.".to_string(),
+                    300,
+                    RecordData::parse(&RecordType::A, "203.0.113.9").unwrap(),
+                ),
+            )
+            .await
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let polls = mock.polls.load(Ordering::SeqCst);
+        assert!((1..=11).contains(&polls), "{} polls", polls);
+    }
+
+    #[tokio::test]
+    async fn change_is_not_polled_without_timeout() {
+        let (mock, route53) = start_mock(zones(), 0, Duration::ZERO).await;
+
+        route53
+            .update_record(
+                "example.com",
+                &DnsRecord::new(
+                    "home.example.com.".to_string(),
+                    300,
+                    RecordData::parse(&RecordType::A, "203.0.113.9").unwrap(),
+                ),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(mock.changes.lock().unwrap().len(), 1);
+        assert_eq!(mock.polls.load(Ordering::SeqCst), 0);
+    }
+}
@@ -1,12 +1,19 @@
-use clap::Parser;
-use custom_ddns::config::{Config, ConfigDnsProvider, DnsRecordConfig};
-use custom_ddns::dns::cloudflare::CloudflareDns;
-use custom_ddns::dns::{DnsClient, DnsProvider, DnsRecordCloudflare};
-use custom_ddns::router::start_health_server;
+use clap::{Parser, Subcommand};
+use custom_ddns::config::{
+    Config, DnsRecordConfig, LanHostsConfig, RecordType, default_freebox_app_id,
+};
+use custom_ddns::dns::{self, DnsRecord, RecordData};
+use custom_ddns::router::{HealthState, start_health_server};
+use custom_ddns::sources;
 use custom_ddns::sources::IpSource;
-use custom_ddns::sources::freebox::FreeboxSource;
-use custom_ddns::utils::get_ip_version;
-use tracing::{debug, error, info};
+use custom_ddns::sources::freebox::{
+    self, FreeboxApp, FreeboxAuthorizationStatus, FreeboxSource, LanHostSelection,
+};
+use custom_ddns::sources::{IpAddress, IpVersion};
+use custom_ddns::utils::{get_ip_version, ipv6_interface_id};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -17,6 +24,52 @@ struct Args {
     /// Port for the health check server
     #[arg(long, default_value = "8080")]
     health_port: u16,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Freebox helpers
+    Freebox {
+        #[command(subcommand)]
+        command: FreeboxCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum FreeboxCommand {
+    /// Request an application token, to be granted on the Freebox front panel
+    Authorize(FreeboxAuthorizeArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct FreeboxAuthorizeArgs {
+    /// Freebox URL
+    #[arg(long, default_value = "http://mafreebox.freebox.fr")]
+    url: String,
+    /// Find the Freebox on the local network with mDNS instead of using `--url`
+    #[arg(long)]
+    mdns: bool,
+    /// PEM file with the only CAs trusted when `--url` uses HTTPS, instead of the
+    /// bundled Freebox root CA
+    #[arg(long)]
+    ca_file: Option<String>,
+    /// Application id, must match `app_id` in the configuration
+    #[arg(long, default_value_t = default_freebox_app_id())]
+    app_id: String,
+    /// Application name displayed on the Freebox
+    #[arg(long, default_value = "Custom DDNS")]
+    app_name: String,
+    /// Device name displayed on the Freebox
+    #[arg(long, default_value = "custom-ddns")]
+    device_name: String,
+    /// Seconds to wait for the request to be granted
+    #[arg(long, default_value = "120")]
+    timeout: u64,
+    /// Write the token into the configuration file (comments are not preserved)
+    #[arg(long)]
+    write_config: bool,
 }
 
 #[tokio::main]
@@ -27,12 +80,21 @@ async fn main() -> Result<(), anyhow::Error> {
                 .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
         )
         .init();
+    let args = Args::parse();
+
+    if let Some(Command::Freebox {
+        command: FreeboxCommand::Authorize(authorize_args),
+    }) = &args.command
+    {
+        return freebox_authorize(authorize_args, &args.config).await;
+    }
+
     info!("Starting Custom DDNS");
 
     // Start health check server
-    let args = Args::parse();
     let mut handles = Vec::new();
-    let health_handle = tokio::spawn(start_health_server(args.health_port));
+    let health_state = HealthState::default();
+    let health_handle = tokio::spawn(start_health_server(args.health_port, health_state.clone()));
     handles.push(health_handle);
 
     debug!("Loading config from {}", args.config);
@@ -42,7 +104,12 @@ async fn main() -> Result<(), anyhow::Error> {
             info!("Configuration loaded successfully");
 
             for record in config.dns_records {
-                let handle = tokio::spawn(process_record(record));
+                let handle = tokio::spawn(process_record(record, health_state.clone()));
+                handles.push(handle);
+            }
+
+            for lan_hosts in config.lan_hosts {
+                let handle = tokio::spawn(process_lan_hosts(lan_hosts, health_state.clone()));
                 handles.push(handle);
             }
 
@@ -67,177 +134,534 @@ async fn main() -> Result<(), anyhow::Error> {
     }
 }
 
-async fn process_record(record: DnsRecordConfig) -> Result<(), anyhow::Error> {
+async fn freebox_authorize(args: &FreeboxAuthorizeArgs, config: &str) -> Result<(), anyhow::Error> {
+    let app = FreeboxApp {
+        app_id: args.app_id.clone(),
+        app_name: args.app_name.clone(),
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        device_name: args.device_name.clone(),
+    };
+
+    let url = match args.mdns {
+        true => freebox::discover_mdns(Duration::from_secs(3)).await?,
+        false => args.url.clone(),
+    };
+
+    info!(
+        "Requesting authorization for `{}` on {}, please grant it on the Freebox front panel",
+        app.app_id, url
+    );
+
+    let mut last_status = None;
+    let token = freebox::authorize(
+        &url,
+        &app,
+        args.ca_file.as_deref(),
+        Duration::from_secs(args.timeout),
+        |status| {
+            if last_status != Some(status) {
+                info!("Authorization status: {:?}", status);
+                last_status = Some(status);
+            } else if status == FreeboxAuthorizationStatus::Pending {
+                debug!("Still waiting for authorization");
+            }
+        },
+    )
+    .await?;
+
+    info!("Authorization granted");
+
+    if args.write_config {
+        let updated = Config::update_freebox_token(config, &args.app_id, &token)?;
+        if updated == 0 {
+            return Err(anyhow::anyhow!(
+                "No freebox source using app_id `{}` found in {}, token: {}",
+                args.app_id,
+                config,
+                token
+            ));
+        }
+        info!(
+            "Token written to {} freebox source(s) in {}",
+            updated, config
+        );
+    } else {
+        println!("{}", token);
+    }
+
+    Ok(())
+}
+
+async fn process_record(
+    record: DnsRecordConfig,
+    health_state: HealthState,
+) -> Result<(), anyhow::Error> {
     let mut interval = tokio::time::interval(record.source.check_interval_in_seconds);
 
     info!("Starting DNS record check for `{}`", &record.name);
     let mut previous_ip_address = None;
+    let mut previous_on_backup = false;
+    // name and content of the SRV record once published
+    let mut previous_srv = None;
+    // name of the address record once it holds the current address, the
+    // SRV record points to it
+    let mut published_target: Option<String> = None;
+
+    // the source lives as long as the record task, so sources keeping state
+    // between checks (e.g. Freebox sessions) can reuse it
+    let mut ip_source = match sources::from_config(&record.source) {
+        Ok(ip_source) => ip_source,
+        Err(e) => {
+            error!("Failed to create IP source for {}: {}", record.name, e);
+            return Err(anyhow::anyhow!("Invalid source for {}: {}", record.name, e));
+        }
+    };
+
+    let interface_id = match record.ipv6_host.as_ref().map(ipv6_interface_id).transpose() {
+        Ok(interface_id) => interface_id,
+        Err(e) => {
+            error!("Invalid ipv6_host for {}: {}", record.name, e);
+            return Err(anyhow::anyhow!(
+                "Invalid ipv6_host for {}: {}",
+                record.name,
+                e
+            ));
+        }
+    };
+    if interface_id.is_some()
+        && get_ip_version(&record.domain.record_type).ok() != Some(IpVersion::IPv6)
+    {
+        error!("ipv6_host requires an AAAA record for {}", record.name);
+        return Err(anyhow::anyhow!(
+            "ipv6_host requires an AAAA record for {}",
+            record.name
+        ));
+    }
+
+    let dns_provider = match dns::build_provider(&record.domain.provider_config()) {
+        Ok(dns_provider) => dns_provider,
+        Err(e) => {
+            error!("Invalid DNS provider for {}: {}", record.name, e);
+            std::process::exit(1);
+        }
+    };
 
     loop {
         interval.tick().await;
 
         debug!("Checking record: {}", &record.name);
 
-        if let Some(source) = &record.source.freebox {
-            let mut freebox_source = FreeboxSource::new(source.url.clone(), source.token.clone())?;
+        // Determine IP version from record type (IPv4 or IPv6)
+        let ip_version = match get_ip_version(&record.domain.record_type) {
+            Ok(ip_version) => ip_version,
+            Err(e) => {
+                error!(
+                    "Failed to determine IP kind for record {}: {}",
+                    record.name, e
+                );
+                continue;
+            }
+        };
 
-            // Determine IP version from record type (IPv4 or IPv6)
-            let ip_version = match get_ip_version(&record.domain.record_type) {
-                Ok(ip_version) => ip_version,
-                Err(e) => {
-                    error!(
-                        "Failed to determine IP kind for record {}: {}",
-                        record.name, e
-                    );
-                    continue;
+        // Get the current IP address, or build it from the delegated prefix
+        let ip_result = match interface_id {
+            Some(interface_id) => ip_source.get_ipv6_prefix().await.map(|prefix| {
+                debug!("Detected {} IPv6 prefix: {}", ip_source.name(), prefix);
+                IpAddress {
+                    version: ip_version,
+                    address: prefix.host_address(interface_id).to_string(),
+                    last_updated: Some(std::time::SystemTime::now()),
                 }
-            };
-
-            // perform DNS check and get client
-            let dns_client = match record.domain.provider {
-                ConfigDnsProvider::Cloudflare => {
-                    let api_key = match record.domain.api_key.as_ref() {
-                        Some(key) => key,
-                        None => {
-                            error!(
-                                "API key is required for Cloudflare provider for record: {}",
+            }),
+            None => ip_source.get_ip(ip_version).await,
+        };
+        health_state.update(&record.name, |status| {
+            status.source = Some(ip_source.name().to_string());
+            status.details = ip_source.details();
+            match &ip_result {
+                Ok(ip) => {
+                    status.ip_address = Some(ip.address.clone());
+                    status.last_error = None;
+                }
+                Err(e) => status.last_error = Some(e.to_string()),
+            }
+        });
+
+        let current_ip = match ip_result {
+            Ok(current_ip_address) => {
+                debug!(
+                    "Detected {} IP: {}",
+                    ip_source.name(),
+                    current_ip_address.address
+                );
+                current_ip_address
+            }
+            Err(e) => {
+                error!("Failed to get {} IP: {}", ip_source.name(), e);
+                continue;
+            }
+        };
+        let record_data = match RecordData::parse(&record.domain.record_type, &current_ip.address) {
+            Ok(record_data) => record_data,
+            Err(e) => {
+                error!("Invalid {} IP: {}", ip_source.name(), e);
+                continue;
+            }
+        };
+
+        let on_backup = ip_source.is_backup_connection();
+        if on_backup != previous_on_backup {
+            if on_backup {
+                warn!("{} is on a backup connection", ip_source.name());
+            } else {
+                info!("{} is back on its main connection", ip_source.name());
+            }
+            // the target record or TTL may change, check the record again
+            previous_ip_address = None;
+            previous_on_backup = on_backup;
+        }
+
+        let Some((record_name, record_ttl)) = record.published_record(on_backup) else {
+            debug!(
+                "Skipping update of {} while on a backup connection",
+                record.name
+            );
+            continue;
+        };
+
+        // compare the current ip address with the previous ip address
+        let update_record = match previous_ip_address {
+            None => {
+                // first run, set the previous ip address
+                previous_ip_address = Some(current_ip.clone());
+
+                // Check if the record already exists with DNS check
+                async {
+                    // Construct the full qualified domain name with trailing dot
+                    let full_record_name =
+                        format!("{}.{}.", record_name, record.domain.domain_name);
+
+                    match dns_provider
+                        .get_record(
+                            &record.domain.domain_name,
+                            &full_record_name,
+                            &record.domain.record_type,
+                        )
+                        .await
+                    {
+                        Ok(Some(existing_record)) => {
+                            if existing_record.data == record_data
+                                && existing_record.ttl == record_ttl
+                            {
+                                debug!(
+                                    "DNS record for {} already matches current IP: {}",
+                                    record.name, current_ip.address
+                                );
+                                published_target = Some(full_record_name);
+                                false
+                            } else if existing_record.data == record_data {
+                                info!(
+                                    "DNS record for {} has different TTL: {} -> {}",
+                                    record.name, existing_record.ttl, record_ttl
+                                );
+                                true
+                            } else {
+                                info!(
+                                    "DNS record for {} has different IP: {} -> {}",
+                                    record.name, existing_record.data, current_ip.address
+                                );
+                                true
+                            }
+                        }
+                        Ok(None) => {
+                            info!(
+                                "DNS record for {} does not exist, will create it",
                                 record.name
                             );
-                            std::process::exit(1);
+                            true
                         }
-                    };
-
-                    let client = match CloudflareDns::new(api_key.clone()) {
-                        Ok(dns) => dns,
                         Err(e) => {
-                            error!("{}", e);
-                            std::process::exit(1);
+                            error!("Failed to check DNS record for {}: {}", record.name, e);
+                            // check it again on the next tick
+                            previous_ip_address = None;
+                            false
                         }
-                    };
-                    DnsClient::Cloudflare(client)
+                    }
+                }
+                .await
+            }
+            Some(ref last_ip_address) => match last_ip_address.address == current_ip.address {
+                true => false,
+                false => {
+                    info!(
+                        "{} IP address has changed: {} -> {}",
+                        ip_source.name(),
+                        last_ip_address.address,
+                        &current_ip.address
+                    );
+                    true
                 }
-            };
+            },
+        };
 
-            // Get the current IP address
-            let current_ip = match freebox_source.get_ip(ip_version).await {
-                Ok(current_ip_address) => {
-                    debug!("Detected Freebox IP: {}", current_ip_address.address);
-                    current_ip_address
+        if update_record {
+            // Construct the full qualified domain name with trailing dot
+            let full_record_name = format!("{}.{}.", record_name, record.domain.domain_name);
+            published_target = None;
+
+            match dns_provider
+                .update_record(
+                    &record.domain.domain_name,
+                    &DnsRecord::new(full_record_name.clone(), record_ttl, record_data.clone()),
+                )
+                .await
+            {
+                Ok(_) => {
+                    info!(
+                        "Successfully updated DNS record for {}: {}",
+                        record.name, current_ip.address
+                    );
+                    published_target = Some(full_record_name);
                 }
                 Err(e) => {
-                    error!("Failed to get Freebox IP: {}", e);
-                    continue;
+                    error!("Failed to update DNS record for {}: {}", record.name, e);
+                    // try again on the next tick
+                    previous_ip_address = None;
                 }
-            };
-
-            // compare the current ip address with the previous ip address
-            let update_record = match previous_ip_address {
-                None => {
-                    // first run, set the previous ip address
-                    previous_ip_address = Some(current_ip.clone());
-
-                    // Check if the record already exists with DNS check
-                    async {
-                        match dns_client {
-                            DnsClient::Cloudflare(ref cloudflare_dns) => {
-                                // Construct the full qualified domain name with trailing dot
-                                let full_record_name = format!(
-                                    "{}.{}.",
-                                    record.domain.record_name, record.domain.domain_name
-                                );
+            }
+        };
 
-                                match cloudflare_dns
-                                    .get_record_content(
-                                        &record.domain.domain_name,
-                                        &full_record_name,
-                                        &record.domain.record_type,
-                                    )
-                                    .await
-                                {
-                                    Ok(Some(existing_content)) => {
-                                        if existing_content == current_ip.address {
-                                            debug!(
-                                                "DNS record for {} already matches current IP: {}",
-                                                record.name, current_ip.address
-                                            );
-                                            false
-                                        } else {
-                                            info!(
-                                                "DNS record for {} has different IP: {} -> {}",
-                                                record.name, existing_content, current_ip.address
-                                            );
-                                            true
-                                        }
-                                    }
-                                    Ok(None) => {
-                                        info!(
-                                            "DNS record for {} does not exist, will create it",
-                                            record.name
-                                        );
-                                        true
-                                    }
-                                    Err(e) => {
-                                        error!(
-                                            "Failed to check DNS record for {}: {}",
-                                            record.name, e
-                                        );
-                                        false
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    .await
+        if let Some(srv) = &record.srv {
+            let port_range = ip_source
+                .ipv4_port_range()
+                .filter(|_| ip_version == IpVersion::IPv4);
+            match port_range {
+                Some((start, end)) if !(start..=end).contains(&srv.port) => {
+                    error!(
+                        "SRV port {} of {} is outside the usable port range {}-{}",
+                        srv.port, record.name, start, end
+                    );
                 }
-                Some(ref last_ip_address) => match last_ip_address.address == current_ip.address {
-                    true => false,
-                    false => {
-                        info!(
-                            "Freebox IP address has changed: {} -> {}",
-                            last_ip_address.address, &current_ip.address
-                        );
-                        true
-                    }
-                },
-            };
-
-            if update_record {
-                match dns_client {
-                    DnsClient::Cloudflare(cloudflare_dns) => {
-                        // Construct the full qualified domain name with trailing dot
-                        let full_record_name = format!(
-                            "{}.{}.",
-                            record.domain.record_name, record.domain.domain_name
-                        );
-
-                        match cloudflare_dns
-                            .update_record(
-                                &record.domain.domain_name,
-                                &DnsRecordCloudflare {
-                                    id: None,
-                                    name: full_record_name,
-                                    content: current_ip.address.clone(),
-                                    record_type: record.domain.record_type.clone(),
-                                    ttl: record.domain.record_ttl,
-                                },
-                            )
+                _ if published_target.as_deref()
+                    != Some(&format!("{}.{}.", record_name, record.domain.domain_name)) =>
+                {
+                    warn!(
+                        "Not publishing the SRV record of {} until its target {} is published",
+                        record.name, record_name
+                    );
+                }
+                _ => {
+                    let srv_record = (
+                        format!(
+                            "{}.{}.{}.",
+                            srv.service, record_name, record.domain.domain_name
+                        ),
+                        RecordData::Srv {
+                            priority: srv.priority,
+                            weight: srv.weight,
+                            port: srv.port,
+                            target: format!("{}.{}.", record_name, record.domain.domain_name),
+                        },
+                    );
+                    if previous_srv.as_ref() != Some(&srv_record) {
+                        let (srv_name, srv_data) = &srv_record;
+                        let result = match dns_provider
+                            .get_record(&record.domain.domain_name, srv_name, &RecordType::Srv)
                             .await
                         {
-                            Ok(_) => {
-                                info!(
-                                    "Successfully updated DNS record for {}: {}",
-                                    record.name, current_ip.address
-                                );
+                            Ok(Some(existing_record)) if existing_record.data == *srv_data => {
+                                Ok(())
                             }
+                            Ok(_) => dns_provider
+                                .update_record(
+                                    &record.domain.domain_name,
+                                    &DnsRecord::new(srv_name.clone(), record_ttl, srv_data.clone()),
+                                )
+                                .await
+                                .inspect(|_| {
+                                    info!(
+                                        "Successfully updated SRV record {}: {}",
+                                        srv_name, srv_data
+                                    )
+                                }),
+                            Err(e) => Err(e),
+                        };
+                        match result {
+                            Ok(_) => previous_srv = Some(srv_record),
                             Err(e) => {
-                                error!("Failed to update DNS record for {}: {}", record.name, e);
+                                error!("Failed to update SRV record for {}: {}", record.name, e)
                             }
                         }
                     }
                 }
-            };
+            }
+        }
+
+        debug!("Record check completed for {}", record.name);
+    }
+}
+
+async fn process_lan_hosts(
+    lan_hosts: LanHostsConfig,
+    health_state: HealthState,
+) -> Result<(), anyhow::Error> {
+    let mut interval = tokio::time::interval(lan_hosts.check_interval_in_seconds);
 
-            debug!("Record check completed for {}", record.name);
+    info!("Starting LAN hosts publishing for `{}`", &lan_hosts.name);
+
+    let selection = match LanHostSelection::new(&lan_hosts) {
+        Ok(selection) => selection,
+        Err(e) => {
+            error!("Invalid LAN hosts selection for {}: {}", lan_hosts.name, e);
+            return Err(anyhow::anyhow!(
+                "Invalid LAN hosts selection for {}: {}",
+                lan_hosts.name,
+                e
+            ));
+        }
+    };
+
+    let mut freebox = match FreeboxSource::new(&lan_hosts.freebox) {
+        Ok(freebox) => freebox,
+        Err(e) => {
+            error!(
+                "Failed to create Freebox client for {}: {}",
+                lan_hosts.name, e
+            );
+            return Err(anyhow::anyhow!(
+                "Invalid freebox for {}: {}",
+                lan_hosts.name,
+                e
+            ));
         }
+    };
+
+    let dns_provider = dns::build_provider(&lan_hosts.domain.provider_config())?;
+
+    // records published by this task, with their content
+    let mut published: HashMap<(String, RecordType), RecordData> = HashMap::new();
+
+    loop {
+        interval.tick().await;
+
+        debug!("Checking LAN hosts: {}", &lan_hosts.name);
+
+        let hosts = match freebox.get_lan_hosts().await {
+            Ok(hosts) => hosts,
+            Err(e) => {
+                error!("Failed to get LAN hosts for {}: {}", lan_hosts.name, e);
+                health_state.update(&lan_hosts.name, |status| {
+                    status.source = Some(freebox.name().to_string());
+                    status.last_error = Some(e.to_string());
+                });
+                continue;
+            }
+        };
+
+        let wanted = selection.records(&hosts);
+
+        let mut last_error = None;
+        for ((record_name, record_type), content) in &wanted {
+            let key = (record_name.clone(), record_type.clone());
+            if published.get(&key) == Some(content) {
+                continue;
+            }
+
+            // on first sight, skip the update if the record is already right
+            let first_sight = !published.contains_key(&key);
+            if first_sight {
+                match dns_provider
+                    .get_record(&lan_hosts.domain.domain_name, record_name, record_type)
+                    .await
+                {
+                    Ok(Some(existing_record)) if existing_record.data == *content => {
+                        debug!("DNS record {} already matches {}", record_name, content);
+                        published.insert(key, content.clone());
+                        continue;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("Failed to check DNS record {}: {}", record_name, e);
+                        last_error = Some(e.to_string());
+                        continue;
+                    }
+                }
+            }
+
+            match dns_provider
+                .update_record(
+                    &lan_hosts.domain.domain_name,
+                    &DnsRecord::new(
+                        record_name.clone(),
+                        lan_hosts.domain.record_ttl,
+                        content.clone(),
+                    ),
+                )
+                .await
+            {
+                Ok(_) => {
+                    info!(
+                        "Successfully updated DNS record {}: {}",
+                        record_name, content
+                    );
+                    published.insert(key, content.clone());
+                }
+                Err(e) => {
+                    error!("Failed to update DNS record {}: {}", record_name, e);
+                    last_error = Some(e.to_string());
+                }
+            }
+        }
+
+        // hosts which left the LAN, only records published by this task are
+        // removed
+        let absent: Vec<_> = published
+            .keys()
+            .filter(|key| !wanted.contains_key(key))
+            .cloned()
+            .collect();
+        for key in absent {
+            if !lan_hosts.remove_absent {
+                debug!("LAN host for {} is no longer active", key.0);
+                published.remove(&key);
+                continue;
+            }
+
+            match dns_provider
+                .delete_record(&lan_hosts.domain.domain_name, &key.0, &key.1)
+                .await
+            {
+                Ok(_) => {
+                    info!(
+                        "Removed DNS record {} ({}) of absent LAN host",
+                        key.0, key.1
+                    );
+                    published.remove(&key);
+                }
+                Err(e) => {
+                    error!("Failed to remove DNS record {}: {}", key.0, e);
+                    last_error = Some(e.to_string());
+                }
+            }
+        }
+
+        let mut records: Vec<_> = published
+            .iter()
+            .map(|((name, record_type), content)| {
+                serde_json::json!({
+                    "name": name,
+                    "type": record_type,
+                    "content": content.to_string(),
+                })
+            })
+            .collect();
+        records.sort_by_key(|record| record["name"].to_string());
+        health_state.update(&lan_hosts.name, |status| {
+            status.source = Some(freebox.name().to_string());
+            status.last_error = last_error;
+            status.details = Some(serde_json::json!({ "records": records }));
+        });
+
+        debug!("LAN hosts check completed for {}", lan_hosts.name);
     }
 }
@@ -1,15 +1,62 @@
 use axum::{
     Router,
+    extract::State,
     response::{Html, Json},
     routing::get,
 };
+use serde::Serialize;
 use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
 use tracing::info;
 
-pub async fn start_health_server(port: u16) -> Result<(), anyhow::Error> {
+/// Last check outcome of a DNS record
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RecordStatus {
+    pub source: Option<String>,
+    pub ip_address: Option<String>,
+    pub last_check: Option<u64>,
+    pub last_error: Option<String>,
+    pub details: Option<serde_json::Value>,
+}
+
+/// Record statuses shared between the record tasks and the health endpoint
+#[derive(Debug, Clone, Default)]
+pub struct HealthState {
+    records: Arc<RwLock<BTreeMap<String, RecordStatus>>>,
+}
+
+impl HealthState {
+    pub fn update(&self, record: &str, update: impl FnOnce(&mut RecordStatus)) {
+        let mut records = self
+            .records
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let status = records.entry(record.to_string()).or_default();
+        status.last_check = Some(unix_timestamp());
+        update(status);
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, RecordStatus> {
+        self.records
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+pub async fn start_health_server(port: u16, state: HealthState) -> Result<(), anyhow::Error> {
     let app = Router::new()
         .route("/", get(root_handler))
-        .route("/health", get(health_check));
+        .route("/health", get(health_check))
+        .with_state(state);
 
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
     info!("Health check server listening on port {}", port);
@@ -127,13 +174,11 @@ async fn root_handler() -> Html<String> {
     Html(html)
 }
 
-async fn health_check() -> Json<serde_json::Value> {
+async fn health_check(State(state): State<HealthState>) -> Json<serde_json::Value> {
     Json(json!({
         "status": "healthy",
         "service": "custom-ddns",
-        "timestamp": std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
+        "timestamp": unix_timestamp(),
+        "records": state.snapshot(),
     }))
 }
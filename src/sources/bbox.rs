@@ -0,0 +1,243 @@
+use super::router::{RouterClient, RouterClientBuilder};
+use super::{IpAddress, IpSource, IpVersion, Ipv6Prefix, SourceError, validate_ip_address};
+use crate::config::Bbox;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::time::SystemTime;
+use tracing::debug;
+
+#[derive(Debug, Deserialize)]
+struct BboxWanIp {
+    wan: BboxWan,
+}
+
+#[derive(Debug, Deserialize)]
+struct BboxWan {
+    ip: BboxIp,
+}
+
+#[derive(Debug, Deserialize)]
+struct BboxIp {
+    address: Option<String>,
+    state: Option<String>,
+    #[serde(default)]
+    ip6address: Vec<BboxIpv6Address>,
+    #[serde(default)]
+    ip6prefix: Vec<BboxIpv6Prefix>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BboxIpv6Address {
+    ipaddress: String,
+    status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BboxIpv6Prefix {
+    prefix: String,
+    status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BboxErrorResponse {
+    exception: BboxException,
+}
+
+#[derive(Debug, Deserialize)]
+struct BboxException {
+    code: Option<String>,
+    #[serde(default)]
+    errors: Vec<BboxExceptionError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BboxExceptionError {
+    name: Option<String>,
+    reason: Option<String>,
+}
+
+/// Bouygues Telecom Bbox WAN addresses, read from its REST API
+pub struct BboxSource {
+    config: Bbox,
+    client: RouterClient,
+    name: String,
+    logged_in: bool,
+}
+
+impl BboxSource {
+    pub fn new(config: &Bbox) -> Result<Self, SourceError> {
+        Ok(Self {
+            config: config.clone(),
+            // the session is kept in the `BBOX_ID` cookie set by the login
+            client: RouterClientBuilder::new(config.timeout_in_seconds, config.retries)
+                .ca_file(config.ca_file.as_deref())
+                .cookies()
+                .build()?,
+            name: format!("bbox ({})", config.url),
+            logged_in: false,
+        })
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        format!("{}/api/v1{}", self.config.url.trim_end_matches('/'), path)
+    }
+
+    // turn an error response into the matching `SourceError`
+    async fn api_error(response: reqwest::Response) -> SourceError {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        let message = match serde_json::from_str::<BboxErrorResponse>(&text) {
+            Ok(error) => error
+                .exception
+                .errors
+                .iter()
+                .map(|e| {
+                    format!(
+                        "{} {}",
+                        e.name.as_deref().unwrap_or_default(),
+                        e.reason.as_deref().unwrap_or_default()
+                    )
+                    .trim()
+                    .to_string()
+                })
+                .chain(error.exception.code.map(|code| format!("code {}", code)))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Err(_) => text,
+        };
+
+        let message = format!("Bbox API error ({}): {}", status, message);
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                SourceError::AuthenticationError(message)
+            }
+            _ => SourceError::ApiError(message),
+        }
+    }
+
+    async fn login(&mut self) -> Result<(), SourceError> {
+        let Some(password) = &self.config.password else {
+            return Err(SourceError::AuthenticationError(
+                "Bbox API requires a login, set the bbox password".to_string(),
+            ));
+        };
+
+        debug!("Logging in to the Bbox on {}", self.config.url);
+        let response = self
+            .client
+            .send(
+                self.client
+                    .post(self.api_url("/login"))
+                    .form(&[("password", password.as_str()), ("remember", "1")]),
+            )
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(match Self::api_error(response).await {
+                SourceError::ApiError(message) => SourceError::AuthenticationError(message),
+                e => e,
+            });
+        }
+
+        self.logged_in = true;
+        Ok(())
+    }
+
+    async fn fetch_wan_ip(&self) -> Result<BboxIp, SourceError> {
+        let response = self
+            .client
+            .send(self.client.get(self.api_url("/wan/ip")))
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Self::api_error(response).await);
+        }
+
+        // the API wraps every object in a single element array
+        let wan: Vec<BboxWanIp> = response
+            .json()
+            .await
+            .map_err(|e| SourceError::ApiError(format!("Invalid Bbox response: {}", e)))?;
+        wan.into_iter()
+            .next()
+            .map(|wan| wan.wan.ip)
+            .ok_or_else(|| SourceError::ApiError("Bbox response has no WAN".to_string()))
+    }
+
+    /// Get the WAN addresses, logging in when the API requires it
+    async fn get_wan_ip(&mut self) -> Result<BboxIp, SourceError> {
+        match self.fetch_wan_ip().await {
+            Err(SourceError::AuthenticationError(e)) if self.config.password.is_some() => {
+                debug!(
+                    "Bbox session {}, logging in: {}",
+                    if self.logged_in {
+                        "rejected"
+                    } else {
+                        "required"
+                    },
+                    e
+                );
+                self.login().await?;
+                self.fetch_wan_ip().await
+            }
+            result => result,
+        }
+    }
+}
+
+#[async_trait]
+impl IpSource for BboxSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let wan_ip = self.get_wan_ip().await?;
+
+        if !wan_ip
+            .state
+            .as_deref()
+            .is_some_and(|state| state.eq_ignore_ascii_case("up"))
+        {
+            return Err(SourceError::ConnectionError(format!(
+                "Bbox WAN is not up (state: {})",
+                wan_ip.state.as_deref().unwrap_or("unknown")
+            )));
+        }
+
+        let ip = match version {
+            IpVersion::IPv4 => wan_ip.address,
+            IpVersion::IPv6 => wan_ip
+                .ip6address
+                .into_iter()
+                .find(|address| address.status.as_deref() != Some("Invalid"))
+                .map(|address| address.ipaddress),
+        }
+        .filter(|ip| !ip.is_empty())
+        .ok_or_else(|| {
+            SourceError::IpNotFoundError(format!("No {:?} address on the Bbox WAN", version))
+        })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    async fn get_ipv6_prefix(&mut self) -> Result<Ipv6Prefix, SourceError> {
+        self.get_wan_ip()
+            .await?
+            .ip6prefix
+            .into_iter()
+            .find(|prefix| prefix.status.as_deref() != Some("Invalid"))
+            .ok_or_else(|| {
+                SourceError::IpNotFoundError("No IPv6 prefix delegated to the Bbox".to_string())
+            })?
+            .prefix
+            .parse()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
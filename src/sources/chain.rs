@@ -0,0 +1,406 @@
+use super::{IpAddress, IpSource, IpVersion, Ipv6Prefix, SourceError};
+use crate::config::SourceStrategy;
+use async_trait::async_trait;
+use futures::future::join_all;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::SystemTime;
+use tracing::warn;
+
+/// Outcome of a single source lookup
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceResult {
+    pub source: String,
+    pub address: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Value looked up on every source
+#[derive(Debug, Clone, Copy)]
+enum Lookup {
+    Ip(IpVersion),
+    Ipv6Prefix,
+}
+
+// addresses are returned in their canonical form, so that sources writing the
+// same address differently agree
+async fn lookup(source: &mut Box<dyn IpSource>, lookup: Lookup) -> Result<String, SourceError> {
+    match lookup {
+        Lookup::Ip(version) => {
+            let address = source.get_ip(version).await?.address;
+            address
+                .trim()
+                .parse::<IpAddr>()
+                .map(|ip| ip.to_string())
+                .map_err(|_| {
+                    SourceError::ValidationError(format!("Invalid IP address: {}", address))
+                })
+        }
+        Lookup::Ipv6Prefix => source
+            .get_ipv6_prefix()
+            .await
+            .map(|prefix| prefix.to_string()),
+    }
+}
+
+/// Combine several sources with a fallback or quorum strategy
+pub struct ChainSource {
+    sources: Vec<Box<dyn IpSource>>,
+    strategy: SourceStrategy,
+    quorum: usize,
+    name: String,
+    results: Vec<SourceResult>,
+    disagreeing: Vec<String>,
+    /// Indexes of the sources which produced the last returned value
+    contributing: Vec<usize>,
+}
+
+impl ChainSource {
+    pub fn new(
+        sources: Vec<Box<dyn IpSource>>,
+        strategy: SourceStrategy,
+        quorum: Option<usize>,
+    ) -> Result<Self, SourceError> {
+        if sources.is_empty() {
+            return Err(SourceError::ValidationError(
+                "At least one source is required".to_string(),
+            ));
+        }
+
+        let quorum = quorum.unwrap_or(sources.len() / 2 + 1);
+        if quorum == 0 || quorum > sources.len() {
+            return Err(SourceError::ValidationError(format!(
+                "Quorum must be between 1 and {} (number of sources)",
+                sources.len()
+            )));
+        }
+
+        let name = format!(
+            "{} [{}]",
+            strategy,
+            sources
+                .iter()
+                .map(|source| source.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        Ok(Self {
+            sources,
+            strategy,
+            quorum,
+            name,
+            results: Vec::new(),
+            disagreeing: Vec::new(),
+            contributing: Vec::new(),
+        })
+    }
+
+    async fn first_success(&mut self, lookup_kind: Lookup) -> Result<String, SourceError> {
+        let mut last_error = None;
+
+        for (index, source) in self.sources.iter_mut().enumerate() {
+            match lookup(source, lookup_kind).await {
+                Ok(address) => {
+                    self.results.push(SourceResult {
+                        source: source.name().to_string(),
+                        address: Some(address.clone()),
+                        error: None,
+                    });
+                    self.contributing = vec![index];
+                    return Ok(address);
+                }
+                Err(e) => {
+                    warn!(
+                        "Source {} failed, trying the next one: {}",
+                        source.name(),
+                        e
+                    );
+                    self.results.push(SourceResult {
+                        source: source.name().to_string(),
+                        address: None,
+                        error: Some(e.to_string()),
+                    });
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| SourceError::ConnectionError("No source available".to_string())))
+    }
+
+    async fn quorum(&mut self, lookup_kind: Lookup) -> Result<String, SourceError> {
+        let lookups = join_all(self.sources.iter_mut().map(|source| async move {
+            (source.name().to_string(), lookup(source, lookup_kind).await)
+        }))
+        .await;
+
+        let mut votes: HashMap<String, usize> = HashMap::new();
+        for (source, result) in lookups {
+            match result {
+                Ok(address) => {
+                    *votes.entry(address.clone()).or_default() += 1;
+                    self.results.push(SourceResult {
+                        source,
+                        address: Some(address),
+                        error: None,
+                    });
+                }
+                Err(e) => {
+                    warn!("Source {} failed: {}", source, e);
+                    self.results.push(SourceResult {
+                        source,
+                        address: None,
+                        error: Some(e.to_string()),
+                    });
+                }
+            }
+        }
+
+        let count = votes
+            .values()
+            .copied()
+            .max()
+            .ok_or_else(|| SourceError::QuorumError("All sources failed".to_string()))?;
+        let mut leaders: Vec<String> = votes
+            .into_iter()
+            .filter(|(_, votes)| *votes == count)
+            .map(|(address, _)| address)
+            .collect();
+        leaders.sort();
+
+        // on a tie, no address is preferred and every source disagrees
+        self.disagreeing = self
+            .results
+            .iter()
+            .filter(|result| {
+                result
+                    .address
+                    .as_ref()
+                    .is_some_and(|ip| leaders.len() > 1 || *ip != leaders[0])
+            })
+            .map(|result| result.source.clone())
+            .collect();
+
+        if !self.disagreeing.is_empty() {
+            warn!(
+                "Sources disagree on the address: {}",
+                self.results
+                    .iter()
+                    .filter_map(|result| result
+                        .address
+                        .as_ref()
+                        .map(|ip| format!("{}={}", result.source, ip)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        if leaders.len() > 1 {
+            return Err(SourceError::QuorumError(format!(
+                "Sources are tied between {} with {} each",
+                leaders.join(", "),
+                count
+            )));
+        }
+        let address = leaders.remove(0);
+
+        if count < self.quorum {
+            return Err(SourceError::QuorumError(format!(
+                "Only {} of {} required sources agree on {}",
+                count, self.quorum, address
+            )));
+        }
+
+        // results are in the order of the sources
+        self.contributing = self
+            .results
+            .iter()
+            .enumerate()
+            .filter(|(_, result)| result.address.as_ref() == Some(&address))
+            .map(|(index, _)| index)
+            .collect();
+        Ok(address)
+    }
+
+    async fn run(&mut self, lookup_kind: Lookup) -> Result<String, SourceError> {
+        self.results.clear();
+        self.disagreeing.clear();
+        self.contributing.clear();
+
+        match self.strategy {
+            SourceStrategy::FirstSuccess => self.first_success(lookup_kind).await,
+            SourceStrategy::Quorum => self.quorum(lookup_kind).await,
+        }
+    }
+}
+
+#[async_trait]
+impl IpSource for ChainSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        Ok(IpAddress {
+            version,
+            address: self.run(Lookup::Ip(version)).await?,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    async fn get_ipv6_prefix(&mut self) -> Result<Ipv6Prefix, SourceError> {
+        self.run(Lookup::Ipv6Prefix).await?.parse()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    // only the sources behind the returned address tell about its connection
+    fn is_backup_connection(&self) -> bool {
+        self.contributing
+            .iter()
+            .any(|&index| self.sources[index].is_backup_connection())
+    }
+
+    fn ipv4_port_range(&self) -> Option<(u16, u16)> {
+        self.contributing
+            .iter()
+            .find_map(|&index| self.sources[index].ipv4_port_range())
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "strategy": self.strategy,
+            "quorum": self.quorum,
+            "results": self.results,
+            "disagreeing": self.disagreeing,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Source always answering the same address, possibly on a backup connection
+    struct FixedSource {
+        address: &'static str,
+        backup: bool,
+    }
+
+    #[async_trait]
+    impl IpSource for FixedSource {
+        async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+            Ok(IpAddress {
+                version,
+                address: self.address.to_string(),
+                last_updated: None,
+            })
+        }
+
+        fn name(&self) -> &str {
+            self.address
+        }
+
+        fn is_backup_connection(&self) -> bool {
+            self.backup
+        }
+    }
+
+    fn backup_chain(
+        sources: &[(&'static str, bool)],
+        strategy: SourceStrategy,
+        quorum: Option<usize>,
+    ) -> ChainSource {
+        let sources = sources
+            .iter()
+            .map(|&(address, backup)| {
+                Box::new(FixedSource { address, backup }) as Box<dyn IpSource>
+            })
+            .collect();
+        ChainSource::new(sources, strategy, quorum).unwrap()
+    }
+
+    fn chain(addresses: &[&'static str], quorum: Option<usize>) -> ChainSource {
+        let sources: Vec<_> = addresses.iter().map(|&address| (address, false)).collect();
+        backup_chain(&sources, SourceStrategy::Quorum, quorum)
+    }
+
+    #[tokio::test]
+    async fn tie_is_not_a_quorum() {
+        let mut chain = chain(&["192.0.2.1", "192.0.2.2"], Some(1));
+
+        let result = chain.get_ip(IpVersion::IPv4).await;
+
+        assert!(matches!(result, Err(SourceError::QuorumError(_))));
+        assert_eq!(chain.disagreeing.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn addresses_are_compared_in_canonical_form() {
+        let mut chain = chain(&["2001:db8::1\n", "2001:0DB8:0:0::1", "2001:db8::2"], None);
+
+        let ip = chain.get_ip(IpVersion::IPv6).await.unwrap();
+
+        assert_eq!(ip.address, "2001:db8::1");
+        assert_eq!(chain.disagreeing, vec!["2001:db8::2"]);
+    }
+
+    #[tokio::test]
+    async fn backup_is_reported_by_the_source_used() {
+        let mut chain = backup_chain(
+            &[("192.0.2.1", false), ("198.51.100.1", true)],
+            SourceStrategy::FirstSuccess,
+            None,
+        );
+        chain.get_ip(IpVersion::IPv4).await.unwrap();
+        assert!(!chain.is_backup_connection());
+
+        // the main source fails, the backup one answers
+        let mut chain = backup_chain(
+            &[("not an address", false), ("198.51.100.1", true)],
+            SourceStrategy::FirstSuccess,
+            None,
+        );
+        let ip = chain.get_ip(IpVersion::IPv4).await.unwrap();
+        assert_eq!(ip.address, "198.51.100.1");
+        assert!(chain.is_backup_connection());
+    }
+
+    #[tokio::test]
+    async fn backup_is_reported_by_the_agreeing_sources() {
+        let mut chain = backup_chain(
+            &[
+                ("192.0.2.1", false),
+                ("192.0.2.1", false),
+                ("198.51.100.1", true),
+            ],
+            SourceStrategy::Quorum,
+            None,
+        );
+        chain.get_ip(IpVersion::IPv4).await.unwrap();
+        assert!(!chain.is_backup_connection());
+
+        let mut chain = backup_chain(
+            &[
+                ("198.51.100.1", true),
+                ("198.51.100.1", false),
+                ("192.0.2.1", false),
+            ],
+            SourceStrategy::Quorum,
+            None,
+        );
+        chain.get_ip(IpVersion::IPv4).await.unwrap();
+        assert!(chain.is_backup_connection());
+
+        // no address, no connection to report
+        let mut chain = backup_chain(
+            &[("198.51.100.1", true), ("192.0.2.1", false)],
+            SourceStrategy::Quorum,
+            None,
+        );
+        assert!(chain.get_ip(IpVersion::IPv4).await.is_err());
+        assert!(!chain.is_backup_connection());
+    }
+}
@@ -0,0 +1,418 @@
+use super::{
+    IpAddress, IpSource, IpVersion, SourceError, transaction_id, udp_exchange, validate_ip_address,
+};
+use crate::config::{DnsQuery, RecordType};
+use async_trait::async_trait;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::SystemTime;
+use tokio::net::UdpSocket;
+
+const DNS_PORT: u16 = 53;
+const DNS_HEADER_LENGTH: usize = 12;
+const DNS_CLASS_IN: u16 = 1;
+pub(crate) const DNS_TYPE_A: u16 = 1;
+pub(crate) const DNS_TYPE_PTR: u16 = 12;
+pub(crate) const DNS_TYPE_TXT: u16 = 16;
+pub(crate) const DNS_TYPE_AAAA: u16 = 28;
+pub(crate) const DNS_TYPE_SRV: u16 = 33;
+
+/// Resource record from a DNS response
+#[derive(Debug, Clone)]
+pub(crate) struct DnsResourceRecord {
+    pub name: String,
+    pub record_type: u16,
+    pub data: Vec<u8>,
+}
+
+/// Build a DNS query message for a single question
+pub(crate) fn build_query(id: u16, name: &str, record_type: u16, recursion: bool) -> Vec<u8> {
+    let mut message = Vec::with_capacity(DNS_HEADER_LENGTH + name.len() + 6);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&(if recursion { 0x0100u16 } else { 0 }).to_be_bytes());
+    // one question, no answer, authority or additional records
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&record_type.to_be_bytes());
+    message.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+    message
+}
+
+/// Read a possibly compressed domain name, returning it with the offset
+/// following the name in the message
+pub(crate) fn read_name(message: &[u8], offset: usize) -> Result<(String, usize), SourceError> {
+    let truncated = || SourceError::ApiError("Truncated DNS message".to_string());
+
+    let mut labels = Vec::new();
+    let mut position = offset;
+    let mut end = None;
+    // bound the number of compression pointers followed to avoid loops
+    for _ in 0..128 {
+        let length = *message.get(position).ok_or_else(truncated)? as usize;
+        if length == 0 {
+            return Ok((labels.join("."), end.unwrap_or(position + 1)));
+        }
+        if length & 0xc0 == 0xc0 {
+            let low = *message.get(position + 1).ok_or_else(truncated)? as usize;
+            end.get_or_insert(position + 2);
+            position = ((length & 0x3f) << 8) | low;
+            continue;
+        }
+        let label = message
+            .get(position + 1..position + 1 + length)
+            .ok_or_else(truncated)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        position += 1 + length;
+    }
+
+    Err(SourceError::ApiError(
+        "Too many DNS compression pointers".to_string(),
+    ))
+}
+
+/// Parse all resource records (answers, authority and additional) of a response
+pub(crate) fn parse_response(message: &[u8]) -> Result<Vec<DnsResourceRecord>, SourceError> {
+    let truncated = || SourceError::ApiError("Truncated DNS message".to_string());
+    let read_u16 = |offset: usize| -> Result<u16, SourceError> {
+        message
+            .get(offset..offset + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(truncated)
+    };
+
+    if message.len() < DNS_HEADER_LENGTH {
+        return Err(truncated());
+    }
+
+    let rcode = message[3] & 0x0f;
+    if rcode != 0 {
+        return Err(SourceError::ApiError(format!(
+            "DNS query failed with rcode {}",
+            rcode
+        )));
+    }
+
+    let questions = read_u16(4)?;
+    let records = read_u16(6)? as usize + read_u16(8)? as usize + read_u16(10)? as usize;
+
+    let mut offset = DNS_HEADER_LENGTH;
+    for _ in 0..questions {
+        let (_, next) = read_name(message, offset)?;
+        // type and class
+        offset = next + 4;
+    }
+
+    let mut result = Vec::with_capacity(records);
+    for _ in 0..records {
+        let (name, next) = read_name(message, offset)?;
+        let record_type = read_u16(next)?;
+        // class and ttl are not needed
+        let length = read_u16(next + 8)? as usize;
+        let data_offset = next + 10;
+        let data = message
+            .get(data_offset..data_offset + length)
+            .ok_or_else(truncated)?
+            .to_vec();
+
+        result.push(DnsResourceRecord {
+            name,
+            record_type,
+            data,
+        });
+        offset = data_offset + length;
+    }
+
+    Ok(result)
+}
+
+/// Split the character strings of a TXT record
+pub(crate) fn txt_strings(data: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut offset = 0;
+    while let Some(&length) = data.get(offset) {
+        let end = (offset + 1 + length as usize).min(data.len());
+        strings.push(String::from_utf8_lossy(&data[offset + 1..end]).to_string());
+        offset = end;
+    }
+    strings
+}
+
+pub struct DnsSource {
+    config: DnsQuery,
+    name: String,
+}
+
+impl DnsSource {
+    pub fn new(config: &DnsQuery) -> Result<Self, SourceError> {
+        match config.query_type {
+            None | Some(RecordType::A) | Some(RecordType::Aaaa) | Some(RecordType::Txt) => {}
+            Some(ref record_type) => {
+                return Err(SourceError::ValidationError(format!(
+                    "Unsupported DNS query type: {}",
+                    record_type
+                )));
+            }
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            name: format!("dns ({})", config.query_name),
+        })
+    }
+
+    async fn resolve_resolver(&self, version: IpVersion) -> Result<SocketAddr, SourceError> {
+        let resolver = &self.config.resolver;
+        let address = match resolver.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, DNS_PORT).to_string(),
+            Err(_) if resolver.contains(':') => resolver.clone(),
+            Err(_) => format!("{}:{}", resolver, DNS_PORT),
+        };
+
+        tokio::net::lookup_host(&address)
+            .await
+            .map_err(|e| {
+                SourceError::NetworkError(format!("Failed to resolve {}: {}", resolver, e))
+            })?
+            .find(|address| match version {
+                IpVersion::IPv4 => address.is_ipv4(),
+                IpVersion::IPv6 => address.is_ipv6(),
+            })
+            .ok_or_else(|| {
+                SourceError::NetworkError(format!("No {:?} address for {}", version, resolver))
+            })
+    }
+
+    async fn query(&self, version: IpVersion) -> Result<IpAddr, SourceError> {
+        let record_type = match (&self.config.query_type, version) {
+            (Some(RecordType::Txt), _) => DNS_TYPE_TXT,
+            (Some(RecordType::Aaaa), _) | (None, IpVersion::IPv6) => DNS_TYPE_AAAA,
+            _ => DNS_TYPE_A,
+        };
+
+        // the resolver has to be reached over the address family being looked up
+        let resolver = self.resolve_resolver(version).await?;
+        let bind_address = match resolver {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(bind_address)
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+        socket
+            .connect(resolver)
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        let id = transaction_id();
+        let id = u16::from_be_bytes([id[10], id[11]]);
+        let request = build_query(id, &self.config.query_name, record_type, true);
+        let response = udp_exchange(&socket, &request, self.config.timeout_in_seconds).await?;
+
+        if response.len() < 2 || u16::from_be_bytes([response[0], response[1]]) != id {
+            return Err(SourceError::ApiError(
+                "DNS response id mismatch".to_string(),
+            ));
+        }
+
+        find_address(parse_response(&response)?, record_type, version).ok_or_else(|| {
+            SourceError::IpNotFoundError(format!(
+                "No address in DNS response for {}",
+                self.config.query_name
+            ))
+        })
+    }
+}
+
+/// First address of the wanted version in the records of the queried type
+fn find_address(
+    records: Vec<DnsResourceRecord>,
+    record_type: u16,
+    version: IpVersion,
+) -> Option<IpAddr> {
+    records
+        .into_iter()
+        .filter(|record| record.record_type == record_type)
+        .find_map(|record| match record_type {
+            DNS_TYPE_A => <[u8; 4]>::try_from(record.data.as_slice())
+                .ok()
+                .map(|octets| IpAddr::V4(Ipv4Addr::from(octets))),
+            DNS_TYPE_AAAA => <[u8; 16]>::try_from(record.data.as_slice())
+                .ok()
+                .map(|octets| IpAddr::V6(Ipv6Addr::from(octets))),
+            _ => txt_strings(&record.data)
+                .iter()
+                .filter_map(|text| text.trim().parse::<IpAddr>().ok())
+                .find(|ip| ip.is_ipv4() == (version == IpVersion::IPv4)),
+        })
+}
+
+#[async_trait]
+impl IpSource for DnsSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let ip = self.query(version).await?.to_string();
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Turn a query into a successful response carrying `answers`
+    fn response(query: Vec<u8>, answers: &[&[u8]]) -> Vec<u8> {
+        let mut message = query;
+        message[2] |= 0x80;
+        message[3] = 0x80;
+        message[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        for answer in answers {
+            message.extend_from_slice(answer);
+        }
+        message
+    }
+
+    /// Resource record of class IN with a TTL of 300 seconds
+    fn record(name: &[u8], record_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut record = name.to_vec();
+        record.extend_from_slice(&record_type.to_be_bytes());
+        record.extend_from_slice(&[0, 1, 0, 0, 1, 0x2c]);
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    fn compressed_response() -> Vec<u8> {
+        let query = build_query(0x1234, "home.example.com", DNS_TYPE_A, true);
+        response(
+            query,
+            &[
+                // pointer to the question name
+                &record(&[0xc0, 12], DNS_TYPE_A, &[203, 0, 113, 5]),
+                // "www" followed by a pointer to "example.com" in the question
+                &record(
+                    b"\x03www\xc0\x11",
+                    DNS_TYPE_AAAA,
+                    &"2001:db8::5".parse::<Ipv6Addr>().unwrap().octets(),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn query_asks_for_recursion() {
+        assert_eq!(
+            build_query(0xabcd, "o-o.myaddr.l.google.com.", DNS_TYPE_TXT, true),
+            b"\xab\xcd\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+              \x03o-o\x06myaddr\x01l\x06google\x03com\x00\x00\x10\x00\x01"
+        );
+        assert_eq!(
+            build_query(0xabcd, "example.com", DNS_TYPE_A, false)[2..4],
+            [0, 0]
+        );
+    }
+
+    #[test]
+    fn compressed_names_are_expanded() {
+        let records = parse_response(&compressed_response()).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "home.example.com");
+        assert_eq!(records[0].record_type, DNS_TYPE_A);
+        assert_eq!(records[1].name, "www.example.com");
+        assert_eq!(records[1].record_type, DNS_TYPE_AAAA);
+        assert_eq!(
+            find_address(records.clone(), DNS_TYPE_A, IpVersion::IPv4),
+            Some("203.0.113.5".parse().unwrap())
+        );
+        assert_eq!(
+            find_address(records, DNS_TYPE_AAAA, IpVersion::IPv6),
+            Some("2001:db8::5".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn address_is_found_among_txt_strings() {
+        let query = build_query(0x1234, "o-o.myaddr.l.google.com", DNS_TYPE_TXT, true);
+        let message = response(
+            query,
+            &[&record(
+                &[0xc0, 12],
+                DNS_TYPE_TXT,
+                b"\x20edns0-client-subnet 192.0.2.0/24\x0c198.51.100.4\x0b2001:db8::4",
+            )],
+        );
+
+        let records = parse_response(&message).unwrap();
+        assert_eq!(records[0].name, "o-o.myaddr.l.google.com");
+        assert_eq!(
+            txt_strings(&records[0].data),
+            [
+                "edns0-client-subnet 192.0.2.0/24",
+                "198.51.100.4",
+                "2001:db8::4"
+            ]
+        );
+        assert_eq!(
+            find_address(records.clone(), DNS_TYPE_TXT, IpVersion::IPv4),
+            Some("198.51.100.4".parse().unwrap())
+        );
+        assert_eq!(
+            find_address(records, DNS_TYPE_TXT, IpVersion::IPv6),
+            Some("2001:db8::4".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn pointer_loop_is_an_error() {
+        let query = build_query(0x1234, "example.com", DNS_TYPE_A, true);
+        let offset = query.len() as u8;
+        // the answer name points to itself
+        let message = response(query, &[&record(&[0xc0, offset], DNS_TYPE_A, &[0; 4])]);
+
+        assert!(matches!(
+            parse_response(&message),
+            Err(SourceError::ApiError(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_message_is_an_error() {
+        let message = compressed_response();
+
+        for length in 0..message.len() {
+            assert!(
+                matches!(
+                    parse_response(&message[..length]),
+                    Err(SourceError::ApiError(_))
+                ),
+                "message truncated to {} bytes",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn error_rcode_is_an_error() {
+        let mut message = compressed_response();
+        // NXDOMAIN
+        message[3] |= 3;
+
+        assert!(matches!(
+            parse_response(&message),
+            Err(SourceError::ApiError(_))
+        ));
+    }
+}
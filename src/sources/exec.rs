@@ -0,0 +1,211 @@
+use super::{IpAddress, IpSource, IpVersion, SourceError, validate_ip_address};
+use crate::config::Exec;
+use async_trait::async_trait;
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::time::SystemTime;
+use tokio::process::Command;
+use tracing::debug;
+
+pub struct ExecSource {
+    config: Exec,
+    name: String,
+}
+
+impl ExecSource {
+    pub fn new(config: &Exec) -> Result<Self, SourceError> {
+        let program = config.command.first().ok_or_else(|| {
+            SourceError::ValidationError("Exec command cannot be empty".to_string())
+        })?;
+
+        Ok(Self {
+            config: config.clone(),
+            name: format!("exec ({})", program),
+        })
+    }
+
+    async fn run(&self) -> Result<String, SourceError> {
+        let mut command = Command::new(&self.config.command[0]);
+        command
+            .args(&self.config.command[1..])
+            .envs(self.config.env.iter().flatten())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // the child is killed if the timeout drops the future
+            .kill_on_drop(true);
+        if let Some(working_dir) = &self.config.working_dir {
+            command.current_dir(working_dir);
+        }
+
+        let output = tokio::time::timeout(self.config.timeout_in_seconds, command.output())
+            .await
+            .map_err(|_| {
+                SourceError::TimeoutError(format!(
+                    "Command `{}` did not complete within {:?}",
+                    self.config.command.join(" "),
+                    self.config.timeout_in_seconds
+                ))
+            })?
+            .map_err(|e| {
+                SourceError::CommandError(format!(
+                    "Failed to run `{}`: {}",
+                    self.config.command[0], e
+                ))
+            })?;
+
+        if !output.status.success() {
+            return Err(SourceError::CommandError(format!(
+                "Command `{}` exited with {}: {}",
+                self.config.command.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+/// Find the first address of the requested version in a command output
+fn find_ip(output: &str, version: IpVersion) -> Option<String> {
+    output
+        .split(|c: char| !(c.is_ascii_hexdigit() || c == '.' || c == ':'))
+        .filter_map(|token| token.parse::<IpAddr>().ok())
+        .find(|ip| match version {
+            IpVersion::IPv4 => ip.is_ipv4(),
+            IpVersion::IPv6 => ip.is_ipv6(),
+        })
+        .map(|ip| ip.to_string())
+}
+
+#[async_trait]
+impl IpSource for ExecSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let output = self.run().await?;
+        debug!("Command output: {}", output.trim());
+
+        let ip = find_ip(&output, version).ok_or_else(|| {
+            SourceError::IpNotFoundError(format!(
+                "No {:?} address in `{}` output",
+                version,
+                self.config.command.join(" ")
+            ))
+        })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    fn shell(script: &str) -> ExecSource {
+        ExecSource::new(&Exec {
+            command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            env: Some(HashMap::from([(
+                "WAN_ADDRESS".to_string(),
+                "203.0.113.7".to_string(),
+            )])),
+            working_dir: None,
+            timeout_in_seconds: Duration::from_secs(1),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn address_is_extracted_from_output() {
+        let mut source = shell(
+            "echo '2: eth0    inet 192.0.2.1/24 brd 192.0.2.255 scope global eth0'; \
+             echo '2: eth0    inet6 2001:db8::1/64 scope global'",
+        );
+
+        let ipv4 = source.get_ip(IpVersion::IPv4).await.unwrap();
+        assert_eq!(ipv4.address, "192.0.2.1");
+        let ipv6 = source.get_ip(IpVersion::IPv6).await.unwrap();
+        assert_eq!(ipv6.address, "2001:db8::1");
+    }
+
+    #[tokio::test]
+    async fn environment_is_passed_to_the_command() {
+        let mut source = shell("echo \"address=$WAN_ADDRESS\"");
+
+        let ip = source.get_ip(IpVersion::IPv4).await.unwrap();
+        assert_eq!(ip.address, "203.0.113.7");
+    }
+
+    #[tokio::test]
+    async fn output_without_address_is_not_found() {
+        let mut source = shell("echo 'no address here'");
+
+        assert!(matches!(
+            source.get_ip(IpVersion::IPv4).await,
+            Err(SourceError::IpNotFoundError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn failing_command_is_an_error() {
+        let mut source = shell("echo 192.0.2.1; echo 'lookup failed' >&2; exit 3");
+
+        match source.get_ip(IpVersion::IPv4).await {
+            Err(SourceError::CommandError(message)) => {
+                assert!(message.contains("lookup failed"), "{}", message);
+            }
+            result => panic!("unexpected result: {:?}", result.map(|ip| ip.address)),
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_program_is_an_error() {
+        let mut source = ExecSource::new(&Exec {
+            command: vec!["/nonexistent/get-ip".to_string()],
+            env: None,
+            working_dir: None,
+            timeout_in_seconds: Duration::from_secs(1),
+        })
+        .unwrap();
+
+        assert!(matches!(
+            source.get_ip(IpVersion::IPv4).await,
+            Err(SourceError::CommandError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn slow_command_times_out() {
+        let mut source = shell("sleep 10; echo 192.0.2.1");
+        let start = Instant::now();
+
+        assert!(matches!(
+            source.get_ip(IpVersion::IPv4).await,
+            Err(SourceError::TimeoutError(_))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn empty_command_is_rejected() {
+        let result = ExecSource::new(&Exec {
+            command: Vec::new(),
+            env: None,
+            working_dir: None,
+            timeout_in_seconds: Duration::from_secs(1),
+        });
+
+        assert!(matches!(result, Err(SourceError::ValidationError(_))));
+    }
+}
@@ -1,48 +1,310 @@
-use super::{IpAddress, IpSource, IpVersion, SourceError, validate_ip_address};
+use super::dns::{DNS_TYPE_PTR, DNS_TYPE_SRV, build_query, parse_response};
+use super::{
+    IpAddress, IpSource, IpVersion, Ipv6Prefix, SourceError, client_builder, validate_ip_address,
+};
+use crate::config::{
+    Freebox, FreeboxPortForward, LanHostsConfig, PortForwardProtocol, RecordType, SharedIpv4Policy,
+};
+use crate::dns::RecordData;
+use crate::utils::dns_label;
 use async_trait::async_trait;
 use hex;
 use hmac::{Hmac, Mac};
+use regex::Regex;
 use reqwest::{
-    Client, StatusCode,
+    Client, Method, StatusCode,
     header::{HeaderMap, HeaderName, HeaderValue},
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use sha1::Sha1;
-use std::time::SystemTime;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+// Highest API major version this client has been written against, newer
+// boxes are still queried with it
+const FREEBOX_MAX_API_VERSION: u32 = 13;
+
+// Root CA of the certificates served on the Freebox `api_domain`, from the
+// Freebox SDK documentation. Older boxes signed by the RSA root need it in
+// `ca_file`.
+const FREEBOX_ROOT_CAS: &[u8] = include_bytes!("certs/freebox_ecc_root_ca.pem");
+
+const FREEBOX_MDNS_SERVICE: &str = "_fbx-api._tcp.local";
+const MDNS_ADDRESS: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+
+// Error codes returned when the session is missing or expired
+const FREEBOX_AUTH_ERROR_CODES: [&str; 3] = ["auth_required", "invalid_session", "invalid_token"];
+
+type SharedSession = Arc<Mutex<Option<String>>>;
+
+// Session tokens are shared by every source using the same Freebox and app
+// token, so short check intervals do not open a new session on each tick
+static SESSIONS: LazyLock<std::sync::Mutex<HashMap<String, SharedSession>>> =
+    LazyLock::new(Default::default);
+
+// Port forwards are checked at most once per interval on a box, by whichever
+// of its sources looks it up first
+const PORT_FORWARD_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Last port forward reconciliation on a box
+#[derive(Default)]
+struct PortForwardCheck {
+    checked_at: Option<Instant>,
+    drift: Vec<String>,
+    error: Option<String>,
+}
+
+type SharedPortForwardCheck = Arc<Mutex<PortForwardCheck>>;
 
-const FREEBOX_API_BASE_URL: &str = "/api/v13";
+static PORT_FORWARD_CHECKS: LazyLock<std::sync::Mutex<HashMap<String, SharedPortForwardCheck>>> =
+    LazyLock::new(Default::default);
 
 #[derive(Debug, Serialize, Deserialize)]
 struct FreeboxApiResponse<T> {
     success: bool,
-    result: T,
+    result: Option<T>,
     error_code: Option<String>,
     msg: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct FreeboxConnectionStatus {
     state: String,
     #[serde(rename = "type")]
     connection_type: String,
+    media: Option<String>,
     ipv4: Option<String>,
+    /// Ports usable on `ipv4`, all of them on a full-stack line
+    ipv4_port_range: Option<(u16, u16)>,
     ipv6: Option<String>,
 }
 
+impl FreeboxConnectionStatus {
+    // the 4G backup is reported as a `backup_4g` media by recent firmwares
+    // and as a `4G` type by older ones
+    fn is_backup(&self) -> bool {
+        self.media.as_deref() == Some("backup_4g")
+            || self.connection_type.eq_ignore_ascii_case("4g")
+    }
+
+    // port range of a shared IPv4 address, `None` on a full-stack line which
+    // reports every port
+    fn shared_ipv4_port_range(&self) -> Option<(u16, u16)> {
+        self.ipv4_port_range
+            .filter(|(start, end)| *start > 1 || *end < u16::MAX)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
-struct FreeboxConnection {
-    status: FreeboxConnectionStatus,
-    session_token: String,
+struct FreeboxIpv6Delegation {
+    prefix: String,
+    next_hop: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FreeboxIpv6Config {
+    ipv6_enabled: bool,
+    #[serde(default)]
+    delegations: Vec<FreeboxIpv6Delegation>,
+}
+
+/// Device seen on the Freebox LAN
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreeboxLanHost {
+    pub id: String,
+    #[serde(default)]
+    pub primary_name: String,
+    pub l2ident: FreeboxL2Ident,
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub reachable: bool,
+    #[serde(default)]
+    pub l3connectivities: Vec<FreeboxL3Connectivity>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreeboxL2Ident {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub ident_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreeboxL3Connectivity {
+    pub addr: String,
+    /// `ipv4` or `ipv6`
+    pub af: String,
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub reachable: bool,
+}
+
+impl FreeboxLanHost {
+    /// MAC address of the host, when identified by one
+    pub fn mac(&self) -> Option<&str> {
+        (self.l2ident.ident_type == "mac_address").then_some(self.l2ident.id.as_str())
+    }
+
+    /// First active address of the host for a version, global ones only for IPv6
+    pub fn address(&self, version: IpVersion) -> Option<&str> {
+        self.l3connectivities
+            .iter()
+            .filter(|l3| l3.active)
+            .find(|l3| match version {
+                IpVersion::IPv4 => l3.af == "ipv4",
+                IpVersion::IPv6 => {
+                    l3.af == "ipv6"
+                        && l3
+                            .addr
+                            .parse::<std::net::Ipv6Addr>()
+                            .is_ok_and(|ip| (ip.segments()[0] & 0xe000) == 0x2000)
+                }
+            })
+            .map(|l3| l3.addr.as_str())
+    }
 }
 
-impl FreeboxConnection {
-    pub fn new(status: FreeboxConnectionStatus, session_token: String) -> Self {
+/// LAN hosts published as `<label>.<domain_name>.` records, selected by MAC
+/// address or name
+pub struct LanHostSelection {
+    macs: Vec<String>,
+    name_pattern: Option<Regex>,
+    ipv4: bool,
+    ipv6: bool,
+    domain_name: String,
+}
+
+impl LanHostSelection {
+    pub fn new(config: &LanHostsConfig) -> Result<Self, SourceError> {
+        let name_pattern = config
+            .name_pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| SourceError::ValidationError(format!("Invalid name_pattern: {}", e)))?;
+
+        Ok(Self {
+            macs: config.macs.iter().map(|mac| mac.to_lowercase()).collect(),
+            name_pattern,
+            ipv4: config.ipv4,
+            ipv6: config.ipv6,
+            domain_name: config.domain.domain_name.clone(),
+        })
+    }
+
+    fn is_selected(&self, host: &FreeboxLanHost) -> bool {
+        host.mac()
+            .is_some_and(|mac| self.macs.contains(&mac.to_lowercase()))
+            || self
+                .name_pattern
+                .as_ref()
+                .is_some_and(|pattern| pattern.is_match(&host.primary_name))
+    }
+
+    /// Records of the selected hosts currently active on the LAN, the first
+    /// host by ID keeps a label shared by several names
+    pub fn records(&self, hosts: &[FreeboxLanHost]) -> HashMap<(String, RecordType), RecordData> {
+        let mut records = HashMap::new();
+        let mut labels: HashMap<String, &str> = HashMap::new();
+        let mut active: Vec<_> = hosts.iter().filter(|host| host.active).collect();
+        active.sort_by(|a, b| a.id.cmp(&b.id));
+        for host in active {
+            if !self.is_selected(host) {
+                continue;
+            }
+
+            let Some(label) = dns_label(&host.primary_name) else {
+                warn!(
+                    "Cannot publish LAN host `{}` ({}): invalid DNS name",
+                    host.primary_name, host.id
+                );
+                continue;
+            };
+            if let Some(owner) = labels.get(&label) {
+                warn!(
+                    "Cannot publish LAN host `{}` ({}): {} is already used by {}",
+                    host.primary_name, host.id, label, owner
+                );
+                continue;
+            }
+            labels.insert(label.clone(), &host.id);
+            let record_name = format!("{}.{}.", label, self.domain_name);
+
+            for (enabled, version, record_type) in [
+                (self.ipv4, IpVersion::IPv4, RecordType::A),
+                (self.ipv6, IpVersion::IPv6, RecordType::Aaaa),
+            ] {
+                let Some(address) = host.address(version).filter(|_| enabled) else {
+                    continue;
+                };
+                match RecordData::parse(&record_type, address) {
+                    Ok(data) => {
+                        records.insert((record_name.clone(), record_type), data);
+                    }
+                    Err(e) => warn!("Cannot publish LAN host `{}`: {}", host.primary_name, e),
+                }
+            }
+        }
+
+        records
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FreeboxRedirection {
+    #[serde(default, skip_serializing)]
+    id: u64,
+    enabled: bool,
+    ip_proto: PortForwardProtocol,
+    wan_port_start: u16,
+    wan_port_end: u16,
+    lan_ip: String,
+    lan_port: u16,
+    #[serde(default)]
+    src_ip: String,
+    #[serde(default)]
+    comment: String,
+}
+
+impl FreeboxRedirection {
+    fn from_config(port_forward: &FreeboxPortForward) -> Self {
         Self {
-            status,
-            session_token,
+            id: 0,
+            enabled: true,
+            ip_proto: port_forward.protocol,
+            wan_port_start: port_forward.wan_port_start,
+            wan_port_end: port_forward
+                .wan_port_end
+                .unwrap_or(port_forward.wan_port_start),
+            lan_ip: port_forward.lan_ip.clone(),
+            lan_port: port_forward.lan_port,
+            // any source address
+            src_ip: "0.0.0.0".to_string(),
+            comment: port_forward.comment.clone().unwrap_or_default(),
         }
     }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} {}-{} -> {}:{}{}",
+            self.ip_proto,
+            self.wan_port_start,
+            self.wan_port_end,
+            self.lan_ip,
+            self.lan_port,
+            if self.enabled { "" } else { " (disabled)" }
+        )
+    }
 }
+
 #[derive(Debug, Serialize, Deserialize)]
 struct FreeboxLoginResult {
     logged_in: bool,
@@ -63,21 +325,335 @@ struct FreeboxSessionResult {
     challenge: Option<String>,
 }
 
+#[derive(Debug, Serialize)]
+struct FreeboxAuthorizeRequest {
+    app_id: String,
+    app_name: String,
+    app_version: String,
+    device_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FreeboxAuthorizeResult {
+    app_token: String,
+    track_id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FreeboxAuthorizeStatus {
+    status: FreeboxAuthorizationStatus,
+}
+
+/// Answer of the unauthenticated `/api_version` endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreeboxApiVersion {
+    pub api_base_url: String,
+    pub api_version: String,
+    #[serde(default)]
+    pub https_available: bool,
+    pub https_port: Option<u16>,
+    pub api_domain: Option<String>,
+    pub box_model: Option<String>,
+    pub box_model_name: Option<String>,
+    pub device_name: Option<String>,
+}
+
+impl FreeboxApiVersion {
+    /// Versioned API path, using the highest major version supported by both
+    /// the box and this client, e.g. `/api/v8`
+    pub fn api_path(&self) -> Result<String, SourceError> {
+        let major = self
+            .api_version
+            .split('.')
+            .next()
+            .and_then(|major| major.parse::<u32>().ok())
+            .ok_or_else(|| {
+                SourceError::ApiError(format!("Invalid Freebox API version: {}", self.api_version))
+            })?;
+
+        Ok(format!(
+            "{}/v{}",
+            self.api_base_url.trim_end_matches('/'),
+            major.min(FREEBOX_MAX_API_VERSION)
+        ))
+    }
+}
+
+/// HTTP client for the Freebox API, trusting only the certificates of
+/// `ca_file` when given, or else only the bundled Freebox root CA
+pub fn build_client(ca_file: Option<&str>) -> Result<Client, SourceError> {
+    let mut builder = client_builder(ca_file)?;
+    if ca_file.is_none() {
+        builder = builder.tls_built_in_root_certs(false);
+        for certificate in reqwest::Certificate::from_pem_bundle(FREEBOX_ROOT_CAS)
+            .map_err(|e| SourceError::ValidationError(e.to_string()))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    builder
+        .build()
+        .map_err(|e| SourceError::ValidationError(e.to_string()))
+}
+
+/// Query `/api_version` on a Freebox URL
+pub async fn discover_api(client: &Client, url: &str) -> Result<FreeboxApiVersion, SourceError> {
+    let response = client
+        .get(format!("{}/api_version", url.trim_end_matches('/')))
+        .timeout(Duration::from_secs(3))
+        .send()
+        .await
+        .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(SourceError::ApiError(format!(
+            "Freebox API version lookup failed ({})",
+            response.status()
+        )));
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| SourceError::ApiError(format!("Invalid Freebox API version: {}", e)))
+}
+
+/// Find a Freebox on the local network with an mDNS `_fbx-api._tcp` query,
+/// returning its HTTP URL
+pub async fn discover_mdns(timeout: Duration) -> Result<String, SourceError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+    // queries sent from a port other than 5353 get a unicast answer
+    socket
+        .send_to(
+            &build_query(0, FREEBOX_MDNS_SERVICE, DNS_TYPE_PTR, false),
+            MDNS_ADDRESS,
+        )
+        .await
+        .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut buffer = vec![0u8; 4096];
+    loop {
+        let (size, sender) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer))
+            .await
+            .map_err(|_| {
+                SourceError::TimeoutError(format!(
+                    "No Freebox answered the mDNS query within {:?}",
+                    timeout
+                ))
+            })?
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        let Ok(records) = parse_response(&buffer[..size]) else {
+            continue;
+        };
+        let port = records
+            .iter()
+            .filter(|record| {
+                record.record_type == DNS_TYPE_SRV && record.name.ends_with(FREEBOX_MDNS_SERVICE)
+            })
+            .find_map(|record| record.data.get(4..6))
+            .map(|port| u16::from_be_bytes([port[0], port[1]]));
+
+        if let Some(port) = port {
+            let url = format!("http://{}:{}", sender.ip(), port);
+            info!("Found Freebox with mDNS on {}", url);
+            return Ok(url);
+        }
+    }
+}
+
+/// Status of an application authorization request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FreeboxAuthorizationStatus {
+    Unknown,
+    Pending,
+    Timeout,
+    Granted,
+    Denied,
+}
+
+/// Application registered on the Freebox by `authorize`
+#[derive(Debug, Clone)]
+pub struct FreeboxApp {
+    pub app_id: String,
+    pub app_name: String,
+    pub app_version: String,
+    pub device_name: String,
+}
+
+/// Request an app token and wait until the user grants or denies it on the
+/// Freebox front panel, `progress` is called on every status poll
+pub async fn authorize(
+    url: &str,
+    app: &FreeboxApp,
+    ca_file: Option<&str>,
+    timeout: Duration,
+    mut progress: impl FnMut(FreeboxAuthorizationStatus),
+) -> Result<String, SourceError> {
+    let client = build_client(ca_file)?;
+    let api_path = discover_api(&client, url).await?.api_path()?;
+    let url = format!("{}{}/login/authorize/", url, api_path);
+
+    let response = client
+        .post(&url)
+        .json(&FreeboxAuthorizeRequest {
+            app_id: app.app_id.clone(),
+            app_name: app.app_name.clone(),
+            app_version: app.app_version.clone(),
+            device_name: app.device_name.clone(),
+        })
+        .timeout(Duration::from_secs(3))
+        .send()
+        .await
+        .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+    let authorization: FreeboxAuthorizeResult = parse_api_response(response).await?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let response = client
+            .get(format!("{}{}", url, authorization.track_id))
+            .timeout(Duration::from_secs(3))
+            .send()
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+        let status: FreeboxAuthorizeStatus = parse_api_response(response).await?;
+        progress(status.status);
+
+        match status.status {
+            FreeboxAuthorizationStatus::Granted => return Ok(authorization.app_token),
+            FreeboxAuthorizationStatus::Denied => {
+                return Err(SourceError::AuthenticationError(
+                    "Authorization denied on the Freebox".to_string(),
+                ));
+            }
+            FreeboxAuthorizationStatus::Timeout => {
+                return Err(SourceError::TimeoutError(
+                    "Authorization was not confirmed on the Freebox in time".to_string(),
+                ));
+            }
+            FreeboxAuthorizationStatus::Unknown => {
+                return Err(SourceError::ApiError(
+                    "Authorization request is unknown to the Freebox".to_string(),
+                ));
+            }
+            FreeboxAuthorizationStatus::Pending => {}
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            return Err(SourceError::TimeoutError(format!(
+                "Authorization still pending after {:?}",
+                timeout
+            )));
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+// check the HTTP status and the API `success` flag, then return the result,
+// session errors are reported as `AuthenticationError`
+async fn parse_api_response<T: DeserializeOwned>(
+    response: reqwest::Response,
+) -> Result<T, SourceError> {
+    let status = response.status();
+    let text = response
+        .text()
+        .await
+        .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+    let api_response: FreeboxApiResponse<T> = match serde_json::from_str(&text) {
+        Ok(api_response) => api_response,
+        Err(_) if status == StatusCode::FORBIDDEN || status == StatusCode::UNAUTHORIZED => {
+            return Err(SourceError::AuthenticationError(format!(
+                "Freebox API error ({}): {}",
+                status, text
+            )));
+        }
+        Err(e) if status == StatusCode::OK => return Err(SourceError::ApiError(e.to_string())),
+        Err(_) => {
+            return Err(SourceError::ApiError(format!(
+                "Freebox API error ({}): {}",
+                status, text
+            )));
+        }
+    };
+
+    if !api_response.success || status != StatusCode::OK {
+        let message = format!(
+            "Freebox API failure: {}",
+            api_response
+                .msg
+                .or(api_response.error_code.clone())
+                .unwrap_or_else(|| "Unknown error".to_string())
+        );
+        let auth_error = status == StatusCode::FORBIDDEN
+            || status == StatusCode::UNAUTHORIZED
+            || api_response
+                .error_code
+                .as_deref()
+                .is_some_and(|code| FREEBOX_AUTH_ERROR_CODES.contains(&code));
+
+        return Err(if auth_error {
+            SourceError::AuthenticationError(message)
+        } else {
+            SourceError::ApiError(message)
+        });
+    }
+
+    match api_response.result {
+        Some(result) => Ok(result),
+        // some endpoints omit `result` instead of returning an empty list,
+        // which is accepted when `T` is an `Option`
+        None => serde_json::from_value(serde_json::Value::Null)
+            .map_err(|_| SourceError::ApiError("Freebox API response has no result".to_string())),
+    }
+}
+
 pub struct FreeboxSource {
     client: Client,
     base_url: String,
+    mdns: bool,
+    https: bool,
+    // base URL followed by the versioned API path, known after discovery
+    api_url: Option<String>,
+    api_version: Option<FreeboxApiVersion>,
+    // connection status of the last lookup
+    connection: Option<FreeboxConnectionStatus>,
+    port_forwards: Vec<FreeboxPortForward>,
+    // differences found by the last port forward reconciliation
+    port_forward_drift: Vec<String>,
+    port_forward_error: Option<String>,
+    shared_ipv4: SharedIpv4Policy,
     app_token: String,
+    app_id: String,
     challenge: Option<String>,
     password_salt: Option<String>,
     session_token: Option<String>,
 }
 
 impl FreeboxSource {
-    pub fn new(url: Option<String>, app_token: String) -> Result<Self, SourceError> {
+    pub fn new(config: &Freebox) -> Result<Self, SourceError> {
         Ok(Self {
-            client: Client::new(),
-            base_url: url.unwrap_or_else(|| "http://mafreebox.freebox.fr".to_string()),
-            app_token,
+            client: build_client(config.ca_file.as_deref())?,
+            base_url: config
+                .url
+                .clone()
+                .unwrap_or_else(|| "http://mafreebox.freebox.fr".to_string()),
+            mdns: config.mdns,
+            https: config.https,
+            api_url: None,
+            api_version: None,
+            connection: None,
+            port_forwards: config.port_forwards.clone(),
+            port_forward_drift: Vec::new(),
+            port_forward_error: None,
+            shared_ipv4: config.shared_ipv4,
+            app_token: config.token.clone(),
+            app_id: config.app_id.clone(),
             challenge: None,
             password_salt: None,
             session_token: None,
@@ -111,8 +687,8 @@ impl FreeboxSource {
     }
 
     // login to the freebox to get the challenge and password salt
-    async fn login(&mut self) -> Result<(), SourceError> {
-        let url = format!("{}{}/login/", self.base_url, FREEBOX_API_BASE_URL);
+    async fn login(&mut self, api_url: &str) -> Result<(), SourceError> {
+        let url = format!("{}/login/", api_url);
 
         let response = self
             .client
@@ -122,27 +698,7 @@ impl FreeboxSource {
             .await
             .map_err(|e| SourceError::NetworkError(e.to_string()))?;
 
-        if response.status() != StatusCode::OK {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(SourceError::ApiError(format!(
-                "Freebox login error: {}",
-                error_text
-            )));
-        }
-
-        let api_response: FreeboxApiResponse<FreeboxLoginResult> = response
-            .json()
-            .await
-            .map_err(|e| SourceError::ApiError(e.to_string()))?;
-
-        if !api_response.success {
-            return Err(SourceError::ApiError(format!(
-                "Freebox login challenge failure: {:?}",
-                api_response
-            )));
-        }
-
-        let result = api_response.result;
+        let result: FreeboxLoginResult = parse_api_response(response).await?;
         if !result.logged_in {
             self.challenge = result.challenge;
             self.password_salt = result.password_salt;
@@ -152,7 +708,7 @@ impl FreeboxSource {
     }
 
     // open a session to the freebox
-    async fn open_session(&mut self) -> Result<(), SourceError> {
+    async fn open_session(&mut self, api_url: &str) -> Result<(), SourceError> {
         let challenge = self.challenge.as_ref().ok_or_else(|| {
             SourceError::ApiError("No challenge available. Call login() first.".to_string())
         })?;
@@ -163,9 +719,9 @@ impl FreeboxSource {
         mac.update(challenge.as_bytes());
         let password = hex::encode(mac.finalize().into_bytes());
 
-        let url = format!("{}{}/login/session/", self.base_url, FREEBOX_API_BASE_URL);
+        let url = format!("{}/login/session/", api_url);
         let payload = FreeboxSessionRequest {
-            app_id: "fr.freebox.cddns".to_string(),
+            app_id: self.app_id.clone(),
             password,
         };
 
@@ -179,91 +735,244 @@ impl FreeboxSource {
             .await
             .map_err(|e| SourceError::NetworkError(e.to_string()))?;
 
-        if response.status() != StatusCode::OK {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(SourceError::ApiError(format!(
-                "Freebox session error: {}",
-                error_text
-            )));
-        }
+        let result: FreeboxSessionResult = parse_api_response(response).await?;
+        self.session_token = Some(result.session_token);
 
-        let api_response: FreeboxApiResponse<FreeboxSessionResult> = response
-            .json()
-            .await
-            .map_err(|e| SourceError::ApiError(e.to_string()))?;
+        Ok(())
+    }
 
-        if !api_response.success {
-            return Err(SourceError::ApiError(format!(
-                "Freebox session failure: {:?}",
-                api_response
-            )));
+    // find the box and its API version, once per source unless the box
+    // becomes unreachable
+    async fn api_url(&mut self) -> Result<String, SourceError> {
+        if let Some(api_url) = &self.api_url {
+            return Ok(api_url.clone());
         }
 
-        let result = api_response.result;
-        self.session_token = Some(result.session_token);
+        if self.mdns {
+            self.base_url = discover_mdns(Duration::from_secs(3)).await?;
+        }
+        let mut api_version = discover_api(&self.client, &self.base_url).await?;
+        if self.https && !self.base_url.starts_with("https://") {
+            // move to the HTTPS endpoint of the box, the discovery itself
+            // does not carry any secret
+            let (Some(api_domain), Some(https_port), true) = (
+                &api_version.api_domain,
+                api_version.https_port,
+                api_version.https_available,
+            ) else {
+                return Err(SourceError::ConnectionError(
+                    "HTTPS access is not available on this Freebox".to_string(),
+                ));
+            };
+            self.base_url = format!("https://{}:{}", api_domain, https_port);
+            api_version = discover_api(&self.client, &self.base_url).await?;
+        }
+        let api_url = format!("{}{}", self.base_url, api_version.api_path()?);
+        debug!(
+            "Using Freebox API {} ({})",
+            api_url,
+            api_version
+                .box_model_name
+                .as_deref()
+                .unwrap_or("unknown model")
+        );
 
-        Ok(())
+        self.api_url = Some(api_url.clone());
+        self.api_version = Some(api_version);
+        Ok(api_url)
     }
 
-    async fn get_connection_status(&mut self) -> Result<FreeboxConnection, SourceError> {
-        self.login().await?;
-        self.open_session().await?;
+    fn shared_session(&self, api_url: &str) -> SharedSession {
+        let key = format!("{}|{}|{}", api_url, self.app_id, self.app_token);
+        SESSIONS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(key)
+            .or_default()
+            .clone()
+    }
 
-        let session_token = match &self.session_token {
-            Some(token) => token.clone(),
-            None => {
-                return Err(SourceError::ApiError(
-                    "No session token available. Call open_session() first.".to_string(),
-                ));
+    /// Call an authenticated API endpoint, reusing the cached session and
+    /// opening a new one only when the Freebox rejects it
+    async fn api_request<T: DeserializeOwned>(
+        &mut self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, SourceError> {
+        let api_url = self.api_url().await?;
+        let session = self.shared_session(&api_url);
+        let mut session_token = session.lock().await;
+        let url = format!("{}{}", api_url, path);
+
+        for attempt in 0..2 {
+            match session_token.as_ref() {
+                Some(token) => self.session_token = Some(token.clone()),
+                None => {
+                    debug!("Opening a new Freebox session on {}", self.base_url);
+                    self.login(&api_url).await?;
+                    self.open_session(&api_url).await?;
+                    *session_token = self.session_token.clone();
+                }
             }
-        };
 
-        let url = format!("{}{}/connection/", self.base_url, FREEBOX_API_BASE_URL);
+            let mut request = self
+                .client
+                .request(method.clone(), &url)
+                .headers(self.get_headers())
+                .timeout(std::time::Duration::from_secs(3));
+            if let Some(body) = &body {
+                request = request.json(body);
+            }
+            let response = match request.send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    // the box may have moved or been replaced, look it up again
+                    self.api_url = None;
+                    return Err(SourceError::NetworkError(e.to_string()));
+                }
+            };
 
-        let response = self
-            .client
-            .get(&url)
-            .headers(self.get_headers())
-            .send()
+            match parse_api_response(response).await {
+                Err(SourceError::AuthenticationError(e)) if attempt == 0 => {
+                    debug!("Freebox session rejected, re-authenticating: {}", e);
+                    *session_token = None;
+                }
+                result => return result,
+            }
+        }
+
+        Err(SourceError::AuthenticationError(
+            "Freebox rejected a new session".to_string(),
+        ))
+    }
+
+    async fn api_get<T: DeserializeOwned>(&mut self, path: &str) -> Result<T, SourceError> {
+        self.api_request(Method::GET, path, None).await
+    }
+
+    async fn get_connection_status(&mut self) -> Result<FreeboxConnectionStatus, SourceError> {
+        self.api_get("/connection/").await
+    }
+
+    /// Devices known on the `pub` LAN interface of the Freebox
+    pub async fn get_lan_hosts(&mut self) -> Result<Vec<FreeboxLanHost>, SourceError> {
+        self.api_get::<Option<Vec<FreeboxLanHost>>>("/lan/browser/pub/")
             .await
-            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Create or fix the configured port forwards, returning the differences
+    /// found. Redirections which are not configured are left untouched.
+    async fn reconcile_port_forwards(&mut self) -> Result<Vec<String>, SourceError> {
+        let existing: Vec<FreeboxRedirection> = self
+            .api_get::<Option<Vec<FreeboxRedirection>>>("/fw/redir/")
+            .await?
+            .unwrap_or_default();
 
-        match response.status() {
-            StatusCode::OK => {
-                let api_response: FreeboxApiResponse<FreeboxConnectionStatus> = response
-                    .json()
-                    .await
-                    .map_err(|e| SourceError::ApiError(e.to_string()))?;
-
-                if !api_response.success {
-                    return Err(SourceError::ApiError(
-                        api_response
-                            .msg
-                            .unwrap_or_else(|| "Unknown error".to_string()),
-                    ));
+        let mut drift = Vec::new();
+        for port_forward in self.port_forwards.clone() {
+            let wanted = FreeboxRedirection::from_config(&port_forward);
+            let body = serde_json::to_value(&wanted)
+                .map_err(|e| SourceError::ValidationError(e.to_string()))?;
+
+            match existing.iter().find(|redirection| {
+                redirection.ip_proto == wanted.ip_proto
+                    && redirection.wan_port_start == wanted.wan_port_start
+            }) {
+                Some(current)
+                    if current.enabled
+                        && current.wan_port_end == wanted.wan_port_end
+                        && current.lan_ip == wanted.lan_ip
+                        && current.lan_port == wanted.lan_port => {}
+                Some(current) => {
+                    let message = format!(
+                        "port forward {} instead of {}",
+                        current.describe(),
+                        wanted.describe()
+                    );
+                    warn!("Freebox {}, updating it", message);
+                    self.api_request::<serde_json::Value>(
+                        Method::PUT,
+                        &format!("/fw/redir/{}", current.id),
+                        Some(body),
+                    )
+                    .await?;
+                    drift.push(message);
+                }
+                None => {
+                    let message = format!("port forward {} missing", wanted.describe());
+                    warn!("Freebox {}, creating it", message);
+                    self.api_request::<serde_json::Value>(Method::POST, "/fw/redir/", Some(body))
+                        .await?;
+                    drift.push(message);
                 }
+            }
+        }
+
+        Ok(drift)
+    }
+
+    /// Reconcile the port forwards unless another source of the box did it
+    /// less than `PORT_FORWARD_CHECK_INTERVAL` ago, keeping the last result
+    /// for the details. A failure is reported but does not prevent the
+    /// address from being published.
+    async fn check_port_forwards(&mut self) {
+        if self.port_forwards.is_empty() {
+            return;
+        }
 
-                let connection = FreeboxConnection::new(api_response.result, session_token);
+        let key = format!("{}|{}|{:?}", self.base_url, self.app_id, self.port_forwards);
+        let check = PORT_FORWARD_CHECKS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(key)
+            .or_default()
+            .clone();
+        let mut check = check.lock().await;
 
-                Ok(connection)
+        if check
+            .checked_at
+            .is_none_or(|checked_at| checked_at.elapsed() >= PORT_FORWARD_CHECK_INTERVAL)
+        {
+            match self.reconcile_port_forwards().await {
+                Ok(drift) => {
+                    check.drift = drift;
+                    check.error = None;
+                }
+                Err(e) => {
+                    warn!("Failed to reconcile Freebox port forwards: {}", e);
+                    check.error = Some(e.to_string());
+                }
             }
-            StatusCode::UNAUTHORIZED => Err(SourceError::AuthenticationError(
-                "Invalid API key".to_string(),
-            )),
-            _ => Err(SourceError::ApiError(format!(
-                "Failed to get connection status: {}",
-                response.text().await.unwrap_or_default()
-            ))),
+            check.checked_at = Some(Instant::now());
         }
+
+        self.port_forward_drift = check.drift.clone();
+        self.port_forward_error = check.error.clone();
+    }
+
+    async fn get_ipv6_config(&mut self) -> Result<FreeboxIpv6Config, SourceError> {
+        self.api_get("/connection/ipv6/config/").await
     }
 }
 
 impl Clone for FreeboxSource {
     fn clone(&self) -> Self {
         Self {
-            client: Client::new(),
+            client: self.client.clone(),
             base_url: self.base_url.clone(),
+            mdns: self.mdns,
+            https: self.https,
+            api_url: self.api_url.clone(),
+            api_version: self.api_version.clone(),
+            connection: self.connection.clone(),
+            port_forwards: self.port_forwards.clone(),
+            port_forward_drift: self.port_forward_drift.clone(),
+            port_forward_error: self.port_forward_error.clone(),
+            shared_ipv4: self.shared_ipv4,
             app_token: self.app_token.clone(),
+            app_id: self.app_id.clone(),
             challenge: self.challenge.clone(),
             password_salt: self.password_salt.clone(),
             session_token: self.session_token.clone(),
@@ -275,19 +984,40 @@ impl Clone for FreeboxSource {
 impl IpSource for FreeboxSource {
     async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
         let status = self.get_connection_status().await?;
+        self.connection = Some(status.clone());
 
-        if status.status.state != "up" {
+        self.check_port_forwards().await;
+
+        if status.state != "up" {
             return Err(SourceError::ConnectionError(format!(
                 "Connection is not up (state: {})",
-                status.status.state
+                status.state
             )));
         }
 
+        if let (IpVersion::IPv4, Some((start, end))) = (version, status.shared_ipv4_port_range()) {
+            match self.shared_ipv4 {
+                SharedIpv4Policy::Refuse => {
+                    return Err(SourceError::ValidationError(format!(
+                        "IPv4 address is shared, only ports {}-{} are usable, set `shared_ipv4` to publish it",
+                        start, end
+                    )));
+                }
+                SharedIpv4Policy::Warn => {
+                    warn!(
+                        "IPv4 address is shared, only ports {}-{} are usable",
+                        start, end
+                    );
+                }
+                SharedIpv4Policy::Allow => {}
+            }
+        }
+
         let ip = match version {
-            IpVersion::IPv4 => status.status.ipv4.ok_or_else(|| {
+            IpVersion::IPv4 => status.ipv4.ok_or_else(|| {
                 SourceError::IpNotFoundError("No IPv4 address available".to_string())
             })?,
-            IpVersion::IPv6 => status.status.ipv6.ok_or_else(|| {
+            IpVersion::IPv6 => status.ipv6.ok_or_else(|| {
                 SourceError::IpNotFoundError("No IPv6 address available".to_string())
             })?,
         };
@@ -300,4 +1030,531 @@ impl IpSource for FreeboxSource {
             last_updated: Some(SystemTime::now()),
         })
     }
+
+    async fn get_ipv6_prefix(&mut self) -> Result<Ipv6Prefix, SourceError> {
+        self.check_port_forwards().await;
+
+        let config = self.get_ipv6_config().await?;
+        if !config.ipv6_enabled {
+            return Err(SourceError::ConnectionError(
+                "IPv6 is disabled on the Freebox".to_string(),
+            ));
+        }
+
+        // the prefix without next hop is the one the Freebox uses on its LAN,
+        // the others are delegated to downstream routers
+        config
+            .delegations
+            .iter()
+            .find(|delegation| delegation.next_hop.is_empty())
+            .or_else(|| config.delegations.first())
+            .ok_or_else(|| {
+                SourceError::IpNotFoundError("No IPv6 prefix delegated to the LAN".to_string())
+            })?
+            .prefix
+            .parse()
+    }
+
+    fn name(&self) -> &str {
+        "freebox"
+    }
+
+    fn ipv4_port_range(&self) -> Option<(u16, u16)> {
+        self.connection
+            .as_ref()
+            .and_then(FreeboxConnectionStatus::shared_ipv4_port_range)
+    }
+
+    fn is_backup_connection(&self) -> bool {
+        self.connection
+            .as_ref()
+            .is_some_and(FreeboxConnectionStatus::is_backup)
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        let mut details = serde_json::json!({ "url": self.base_url });
+        if let Some(api_version) = &self.api_version {
+            details["api"] = serde_json::json!({
+                "api_url": self.api_url,
+                "api_version": api_version.api_version,
+                "box_model": api_version.box_model,
+                "box_model_name": api_version.box_model_name,
+                "api_domain": api_version.api_domain,
+                "https_port": api_version.https_port,
+                "https_available": api_version.https_available,
+            });
+        }
+        if let Some(connection) = &self.connection {
+            details["connection"] = serde_json::json!({
+                "state": connection.state,
+                "type": connection.connection_type,
+                "media": connection.media,
+                "backup": connection.is_backup(),
+                "ipv4_port_range": connection.ipv4_port_range,
+                "shared_ipv4": connection.shared_ipv4_port_range().is_some(),
+            });
+        }
+        if !self.port_forwards.is_empty() {
+            details["port_forwards"] = serde_json::json!({
+                "configured": self.port_forwards.len(),
+                "drift": self.port_forward_drift,
+                "error": self.port_forward_error,
+            });
+        }
+        Some(details)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        extract::State,
+        response::{IntoResponse, Response},
+        routing::{get, post},
+    };
+    use serde_json::{Value, json};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    const APP_TOKEN: &str = "app-token";
+    const CHALLENGE: &str = "challenge";
+
+    #[derive(Clone, Copy)]
+    enum Rejection {
+        AuthRequired,
+        InvalidSession,
+        Forbidden,
+    }
+
+    /// Freebox API answering `/connection/` to the last opened session only
+    struct MockFreebox {
+        rejection: Rejection,
+        valid_session: std::sync::Mutex<Option<String>>,
+        // sessions are opened but never accepted
+        reject_all: AtomicBool,
+        sessions_opened: AtomicUsize,
+        requests: AtomicUsize,
+        redirection_listings: AtomicUsize,
+    }
+
+    impl MockFreebox {
+        fn expire_session(&self) {
+            *self.valid_session.lock().unwrap() = None;
+        }
+    }
+
+    async fn api_version() -> Json<Value> {
+        Json(json!({
+            "api_base_url": "/api/",
+            "api_version": "8.2",
+            "box_model_name": "Mock Freebox",
+        }))
+    }
+
+    async fn login() -> Json<Value> {
+        Json(json!({
+            "success": true,
+            "result": {
+                "logged_in": false,
+                "challenge": CHALLENGE,
+                "password_salt": "salt",
+                "password_set": true,
+            },
+        }))
+    }
+
+    async fn open_session(
+        State(mock): State<Arc<MockFreebox>>,
+        Json(request): Json<Value>,
+    ) -> Json<Value> {
+        let mut mac = Hmac::<Sha1>::new_from_slice(APP_TOKEN.as_bytes()).unwrap();
+        mac.update(CHALLENGE.as_bytes());
+        if request["password"] != hex::encode(mac.finalize().into_bytes()) {
+            return Json(json!({"success": false, "error_code": "invalid_token"}));
+        }
+
+        let session = format!(
+            "session-{}",
+            mock.sessions_opened.fetch_add(1, Ordering::SeqCst) + 1
+        );
+        if !mock.reject_all.load(Ordering::SeqCst) {
+            *mock.valid_session.lock().unwrap() = Some(session.clone());
+        }
+        Json(json!({
+            "success": true,
+            "result": {"session_token": session, "challenge": CHALLENGE},
+        }))
+    }
+
+    async fn connection(State(mock): State<Arc<MockFreebox>>, headers: HeaderMap) -> Response {
+        mock.requests.fetch_add(1, Ordering::SeqCst);
+        let session = headers
+            .get("x-fbx-app-auth")
+            .and_then(|session| session.to_str().ok());
+        if session.is_some() && session == mock.valid_session.lock().unwrap().as_deref() {
+            return Json(json!({
+                "success": true,
+                "result": {
+                    "state": "up",
+                    "type": "ethernet",
+                    "media": "ftth",
+                    "ipv4": "203.0.113.1",
+                    "ipv4_port_range": [0, 65535],
+                    "ipv6": "2001:db8::1",
+                },
+            }))
+            .into_response();
+        }
+
+        match mock.rejection {
+            Rejection::AuthRequired => (
+                StatusCode::FORBIDDEN,
+                Json(json!({
+                    "success": false,
+                    "error_code": "auth_required",
+                    "msg": "Invalid session token, or no session token sent",
+                })),
+            )
+                .into_response(),
+            Rejection::InvalidSession => Json(json!({
+                "success": false,
+                "error_code": "invalid_session",
+                "msg": "Invalid session",
+            }))
+            .into_response(),
+            Rejection::Forbidden => (StatusCode::FORBIDDEN, "Forbidden").into_response(),
+        }
+    }
+
+    /// The port forward of `source_with_port_forward`, already in place
+    async fn redirections(State(mock): State<Arc<MockFreebox>>) -> Json<Value> {
+        mock.redirection_listings.fetch_add(1, Ordering::SeqCst);
+        Json(json!({
+            "success": true,
+            "result": [{
+                "id": 1,
+                "enabled": true,
+                "ip_proto": "tcp",
+                "wan_port_start": 443,
+                "wan_port_end": 443,
+                "lan_ip": "192.168.1.10",
+                "lan_port": 443,
+            }],
+        }))
+    }
+
+    async fn start_mock(rejection: Rejection) -> (Arc<MockFreebox>, String) {
+        let mock = Arc::new(MockFreebox {
+            rejection,
+            valid_session: std::sync::Mutex::new(None),
+            reject_all: AtomicBool::new(false),
+            sessions_opened: AtomicUsize::new(0),
+            requests: AtomicUsize::new(0),
+            redirection_listings: AtomicUsize::new(0),
+        });
+        let app = Router::new()
+            .route("/api_version", get(api_version))
+            .route("/api/v8/login/", get(login))
+            .route("/api/v8/login/session/", post(open_session))
+            .route("/api/v8/connection/", get(connection))
+            .route("/api/v8/fw/redir/", get(redirections))
+            .with_state(mock.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (mock, url)
+    }
+
+    fn source(url: &str) -> FreeboxSource {
+        let config: Freebox =
+            serde_yaml::from_str(&format!("url: {}\ntoken: {}", url, APP_TOKEN)).unwrap();
+        FreeboxSource::new(&config).unwrap()
+    }
+
+    fn source_with_port_forward(url: &str) -> FreeboxSource {
+        let config: Freebox = serde_yaml::from_str(&format!(
+            "url: {}\ntoken: {}\nport_forwards:\n  - protocol: tcp\n    wan_port_start: 443\n    lan_ip: 192.168.1.10\n    lan_port: 443",
+            url, APP_TOKEN
+        ))
+        .unwrap();
+        FreeboxSource::new(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn session_is_shared_between_sources() {
+        let (mock, url) = start_mock(Rejection::AuthRequired).await;
+        let mut first = source(&url);
+        let mut second = source(&url);
+
+        first.get_ip(IpVersion::IPv4).await.unwrap();
+        second.get_ip(IpVersion::IPv4).await.unwrap();
+        let ip = first.get_ip(IpVersion::IPv6).await.unwrap();
+
+        assert_eq!(ip.address, "2001:db8::1");
+        assert_eq!(mock.sessions_opened.load(Ordering::SeqCst), 1);
+        assert_eq!(mock.requests.load(Ordering::SeqCst), 3);
+    }
+
+    async fn assert_reopened_on(rejection: Rejection) {
+        let (mock, url) = start_mock(rejection).await;
+        let mut source = source(&url);
+        source.get_ip(IpVersion::IPv4).await.unwrap();
+
+        mock.expire_session();
+        let ip = source.get_ip(IpVersion::IPv4).await.unwrap();
+
+        assert_eq!(ip.address, "203.0.113.1");
+        assert_eq!(mock.sessions_opened.load(Ordering::SeqCst), 2);
+        // the rejected request is sent again once with the new session
+        assert_eq!(mock.requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn session_is_reopened_on_auth_required() {
+        assert_reopened_on(Rejection::AuthRequired).await;
+    }
+
+    #[tokio::test]
+    async fn session_is_reopened_on_invalid_session() {
+        assert_reopened_on(Rejection::InvalidSession).await;
+    }
+
+    #[tokio::test]
+    async fn session_is_reopened_on_forbidden() {
+        assert_reopened_on(Rejection::Forbidden).await;
+    }
+
+    #[tokio::test]
+    async fn rejected_new_session_is_retried_once() {
+        let (mock, url) = start_mock(Rejection::AuthRequired).await;
+        mock.reject_all.store(true, Ordering::SeqCst);
+
+        let result = source(&url).get_ip(IpVersion::IPv4).await;
+
+        assert!(matches!(result, Err(SourceError::AuthenticationError(_))));
+        assert_eq!(mock.sessions_opened.load(Ordering::SeqCst), 2);
+        assert_eq!(mock.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn port_forwards_are_checked_once_per_box() {
+        let (mock, url) = start_mock(Rejection::AuthRequired).await;
+        let mut ipv4 = source_with_port_forward(&url);
+        let mut ipv6 = source_with_port_forward(&url);
+
+        ipv4.get_ip(IpVersion::IPv4).await.unwrap();
+        ipv6.get_ip(IpVersion::IPv6).await.unwrap();
+        ipv4.get_ip(IpVersion::IPv4).await.unwrap();
+
+        assert_eq!(mock.redirection_listings.load(Ordering::SeqCst), 1);
+        for source in [&ipv4, &ipv6] {
+            let details = source.details().unwrap();
+            assert_eq!(details["port_forwards"]["configured"], 1);
+            assert_eq!(details["port_forwards"]["drift"], json!([]));
+        }
+    }
+
+    /// HTTPS server answering `/api_version` with a certificate for
+    /// 127.0.0.1 which no Freebox root CA signed
+    async fn start_tls_mock() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls::{
+            ServerConfig,
+            crypto::ring,
+            pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+        };
+
+        let certificate =
+            CertificateDer::from_pem_slice(include_bytes!("testdata/tls_cert.pem")).unwrap();
+        let key = PrivateKeyDer::from_pem_slice(include_bytes!("testdata/tls_key.pem")).unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], key)
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut request = [0; 4096];
+                    let _ = stream.read(&mut request).await;
+                    let body = json!({
+                        "api_base_url": "/api/",
+                        "api_version": "8.2",
+                        "box_model_name": "Mock Freebox",
+                    })
+                    .to_string();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn certificate_not_signed_by_freebox_root_is_rejected() {
+        let url = start_tls_mock().await;
+
+        let result = discover_api(&build_client(None).unwrap(), &url).await;
+
+        assert!(
+            matches!(result, Err(SourceError::NetworkError(_))),
+            "{:?}",
+            result.map(|_| ())
+        );
+    }
+
+    #[tokio::test]
+    async fn ca_file_replaces_freebox_root() {
+        let url = start_tls_mock().await;
+        let ca_file = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/sources/testdata/tls_cert.pem"
+        );
+
+        let api_version = discover_api(&build_client(Some(ca_file)).unwrap(), &url)
+            .await
+            .unwrap();
+
+        assert_eq!(api_version.box_model_name.as_deref(), Some("Mock Freebox"));
+    }
+
+    fn lan_host(id: u8, name: &str, active: bool, addresses: &[&str]) -> FreeboxLanHost {
+        let mac = format!("00:24:d4:aa:bb:{:02x}", id);
+        serde_json::from_value(json!({
+            "id": format!("ether-{}", mac),
+            "primary_name": name,
+            "l2ident": {"id": mac.to_uppercase(), "type": "mac_address"},
+            "active": active,
+            "l3connectivities": addresses.iter().map(|address| json!({
+                "addr": address,
+                "af": if address.contains(':') { "ipv6" } else { "ipv4" },
+                "active": true,
+            })).collect::<Vec<_>>(),
+        }))
+        .unwrap()
+    }
+
+    fn selection(extra: &str) -> LanHostSelection {
+        LanHostSelection::new(
+            &serde_yaml::from_str(&format!(
+                r#"
+name: lan
+freebox: {{token: token}}
+macs: ["00:24:D4:AA:BB:01", "00:24:d4:aa:bb:03", "00:24:d4:aa:bb:04", "00:24:d4:aa:bb:06"]
+name_pattern: "(?i)tv$"
+check_interval_in_seconds: 300
+domain: {{provider: cloudflare, domain_name: lan.example.com, record_ttl: 300}}
+{}
+"#,
+                extra
+            ))
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn lan_hosts() -> Vec<FreeboxLanHost> {
+        vec![
+            // listed out of order, the first by ID keeps a shared label
+            lan_host(3, "nas-synology", true, &["192.168.1.13"]),
+            lan_host(
+                1,
+                "NAS Synology",
+                true,
+                &[
+                    "192.168.1.11",
+                    "fe80::224:d4ff:feaa:bb01",
+                    "2a01:e0a:1:2::11",
+                ],
+            ),
+            lan_host(2, "Living Room TV", true, &["192.168.1.12"]),
+            // selected but gone
+            lan_host(4, "Printer", false, &["192.168.1.14"]),
+            // not selected
+            lan_host(5, "Laptop", true, &["192.168.1.15"]),
+            // selected without a usable name
+            lan_host(6, "@@@", true, &["192.168.1.16"]),
+        ]
+    }
+
+    fn record(
+        name: &str,
+        record_type: RecordType,
+        data: &str,
+    ) -> ((String, RecordType), RecordData) {
+        (
+            (name.to_string(), record_type.clone()),
+            RecordData::parse(&record_type, data).unwrap(),
+        )
+    }
+
+    #[test]
+    fn selected_active_hosts_are_published() {
+        assert_eq!(
+            selection("").records(&lan_hosts()),
+            HashMap::from([
+                record(
+                    "nas-synology.lan.example.com.",
+                    RecordType::A,
+                    "192.168.1.11"
+                ),
+                record(
+                    "living-room-tv.lan.example.com.",
+                    RecordType::A,
+                    "192.168.1.12"
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn global_ipv6_address_is_published() {
+        assert_eq!(
+            selection("ipv4: false\nipv6: true").records(&lan_hosts()),
+            HashMap::from([record(
+                "nas-synology.lan.example.com.",
+                RecordType::Aaaa,
+                "2a01:e0a:1:2::11"
+            )])
+        );
+        assert_eq!(selection("ipv6: true").records(&lan_hosts()).len(), 3);
+    }
+
+    #[test]
+    fn invalid_name_pattern_is_rejected() {
+        let config = serde_yaml::from_str(
+            r#"
+name: lan
+freebox: {token: token}
+name_pattern: "tv("
+check_interval_in_seconds: 300
+domain: {provider: cloudflare, domain_name: lan.example.com, record_ttl: 300}
+"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            LanHostSelection::new(&config),
+            Err(SourceError::ValidationError(_))
+        ));
+    }
 }
@@ -0,0 +1,325 @@
+use super::router::{RouterClient, RouterClientBuilder};
+use super::{
+    IpAddress, IpSource, IpVersion, Ipv6Prefix, SourceError, transaction_id, validate_ip_address,
+};
+use crate::config::FritzBox;
+use crate::utils::xml_element_text;
+use async_trait::async_trait;
+use md5::{Digest, Md5};
+use reqwest::{StatusCode, header};
+use std::net::Ipv6Addr;
+use std::time::SystemTime;
+use tracing::debug;
+
+const WAN_IP_CONNECTION_SERVICE: &str = "urn:dslforum-org:service:WANIPConnection:1";
+const WAN_IP_CONNECTION_CONTROL_URL: &str = "/upnp/control/wanipconnection1";
+
+// UPnP error codes returned when the user is not allowed to call an action
+const UPNP_AUTH_ERROR_CODES: [&str; 2] = ["606", "820"];
+
+/// HTTP digest challenge sent by the Fritz!Box in `WWW-Authenticate`
+#[derive(Debug, Clone)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    qop: Option<String>,
+    opaque: Option<String>,
+    stale: bool,
+}
+
+impl DigestChallenge {
+    fn parse(header: &str) -> Option<Self> {
+        let (scheme, parameters) = header.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+
+        // key=value or key="value, with commas" pairs separated by commas
+        let mut values = Vec::new();
+        let mut rest = parameters.trim();
+        while let Some((key, value)) = rest.split_once('=') {
+            let key = key
+                .trim()
+                .trim_start_matches(',')
+                .trim()
+                .to_ascii_lowercase();
+            let value = value.trim_start();
+            let (value, remaining) = match value.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted.find('"')?;
+                    (&quoted[..end], &quoted[end + 1..])
+                }
+                None => value.split_at(value.find(',').unwrap_or(value.len())),
+            };
+            values.push((key, value.trim().to_string()));
+            rest = remaining;
+        }
+        let value = |name: &str| {
+            values
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        if value("algorithm").is_some_and(|algorithm| !algorithm.eq_ignore_ascii_case("md5")) {
+            return None;
+        }
+
+        Some(Self {
+            realm: value("realm")?,
+            nonce: value("nonce")?,
+            // only `auth` is implemented, the body is not protected
+            qop: value("qop").and_then(|qop| {
+                qop.split(',')
+                    .map(str::trim)
+                    .find(|qop| *qop == "auth")
+                    .map(str::to_string)
+            }),
+            opaque: value("opaque"),
+            stale: value("stale").is_some_and(|stale| stale.eq_ignore_ascii_case("true")),
+        })
+    }
+}
+
+fn md5_hex(data: &str) -> String {
+    hex::encode(Md5::digest(data.as_bytes()))
+}
+
+/// AVM Fritz!Box WAN addresses, read with TR-064 SOAP actions
+pub struct FritzBoxSource {
+    config: FritzBox,
+    client: RouterClient,
+    name: String,
+    challenge: Option<DigestChallenge>,
+    nonce_count: u32,
+}
+
+impl FritzBoxSource {
+    pub fn new(config: &FritzBox) -> Result<Self, SourceError> {
+        if config.username.is_some() != config.password.is_some() {
+            return Err(SourceError::ValidationError(
+                "Fritz!Box username and password must be set together".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            client: RouterClientBuilder::new(config.timeout_in_seconds, config.retries)
+                .ca_file(config.ca_file.as_deref())
+                .build()?,
+            name: format!("fritzbox ({})", config.url),
+            challenge: None,
+            nonce_count: 0,
+        })
+    }
+
+    /// `Authorization` header answering the last digest challenge
+    fn authorization(&mut self, uri: &str) -> Option<String> {
+        let challenge = self.challenge.as_ref()?;
+        let username = self.config.username.as_deref()?;
+        let password = self.config.password.as_deref()?;
+
+        let ha1 = md5_hex(&format!("{}:{}:{}", username, challenge.realm, password));
+        let ha2 = md5_hex(&format!("POST:{}", uri));
+        let mut authorization = format!(
+            r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", algorithm=MD5"#,
+            username, challenge.realm, challenge.nonce, uri
+        );
+
+        let response = match &challenge.qop {
+            Some(qop) => {
+                self.nonce_count += 1;
+                let nonce_count = format!("{:08x}", self.nonce_count);
+                let cnonce = hex::encode(transaction_id());
+                authorization.push_str(&format!(
+                    r#", qop={}, nc={}, cnonce="{}""#,
+                    qop, nonce_count, cnonce
+                ));
+                md5_hex(&format!(
+                    "{}:{}:{}:{}:{}:{}",
+                    ha1, challenge.nonce, nonce_count, cnonce, qop, ha2
+                ))
+            }
+            None => md5_hex(&format!("{}:{}:{}", ha1, challenge.nonce, ha2)),
+        };
+        authorization.push_str(&format!(r#", response="{}""#, response));
+        if let Some(opaque) = &challenge.opaque {
+            authorization.push_str(&format!(r#", opaque="{}""#, opaque));
+        }
+
+        Some(authorization)
+    }
+
+    /// Call a `WANIPConnection` action and return the response body, answering
+    /// the digest challenge when the box sends one
+    async fn call(&mut self, action: &str) -> Result<String, SourceError> {
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0"?>"#,
+                r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" "#,
+                r#"s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+                r#"<s:Body><u:{} xmlns:u="{}"/></s:Body></s:Envelope>"#
+            ),
+            action, WAN_IP_CONNECTION_SERVICE
+        );
+
+        // a cached challenge is reused until its nonce expires, a fresh one
+        // is only answered once
+        let mut fresh_challenge = false;
+        loop {
+            let mut request = self
+                .client
+                .post(format!(
+                    "{}{}",
+                    self.config.url.trim_end_matches('/'),
+                    WAN_IP_CONNECTION_CONTROL_URL
+                ))
+                .header(header::CONTENT_TYPE, r#"text/xml; charset="utf-8""#)
+                .header(
+                    "SOAPAction",
+                    format!("\"{}#{}\"", WAN_IP_CONNECTION_SERVICE, action),
+                )
+                .body(body.clone());
+            if let Some(authorization) = self.authorization(WAN_IP_CONNECTION_CONTROL_URL) {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+
+            let response = self.client.send(request).await?;
+            let status = response.status();
+
+            if status == StatusCode::UNAUTHORIZED {
+                if self.config.username.is_none() {
+                    return Err(SourceError::AuthenticationError(format!(
+                        "Fritz!Box requires a login for {}, set the fritzbox username and password",
+                        action
+                    )));
+                }
+
+                let challenge = response
+                    .headers()
+                    .get(header::WWW_AUTHENTICATE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(DigestChallenge::parse)
+                    .ok_or_else(|| {
+                        SourceError::AuthenticationError(
+                            "Fritz!Box sent no supported digest challenge".to_string(),
+                        )
+                    })?;
+                if fresh_challenge && !challenge.stale {
+                    self.challenge = None;
+                    return Err(SourceError::AuthenticationError(format!(
+                        "Fritz!Box rejected the credentials of {}",
+                        self.config.username.as_deref().unwrap_or_default()
+                    )));
+                }
+
+                debug!("Answering Fritz!Box digest challenge for {}", action);
+                self.challenge = Some(challenge);
+                self.nonce_count = 0;
+                fresh_challenge = true;
+                continue;
+            }
+
+            let text = response
+                .text()
+                .await
+                .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+            if !status.is_success() {
+                // <s:Fault>...<UPnPError><errorCode>606</errorCode>...
+                return Err(match xml_element_text(&text, "errorCode") {
+                    Some(code) => {
+                        let message = format!(
+                            "Fritz!Box {} failed: {} (UPnP error {})",
+                            action,
+                            xml_element_text(&text, "errorDescription")
+                                .unwrap_or_else(|| "Unknown error".to_string()),
+                            code
+                        );
+                        if UPNP_AUTH_ERROR_CODES.contains(&code.as_str()) {
+                            SourceError::AuthenticationError(message)
+                        } else {
+                            SourceError::ApiError(message)
+                        }
+                    }
+                    None => {
+                        SourceError::ApiError(format!("Fritz!Box {} failed: {}", action, status))
+                    }
+                });
+            }
+
+            return Ok(text);
+        }
+    }
+
+    async fn check_connection(&mut self) -> Result<(), SourceError> {
+        let response = self.call("GetStatusInfo").await?;
+        let status = xml_element_text(&response, "NewConnectionStatus")
+            .unwrap_or_else(|| "unknown".to_string());
+
+        if status != "Connected" {
+            return Err(SourceError::ConnectionError(format!(
+                "Fritz!Box WAN is not connected (status: {})",
+                status
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IpSource for FritzBoxSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        self.check_connection().await?;
+
+        let (action, element) = match version {
+            IpVersion::IPv4 => ("GetExternalIPAddress", "NewExternalIPAddress"),
+            IpVersion::IPv6 => ("X_AVM_DE_GetExternalIPv6Address", "NewExternalIPv6Address"),
+        };
+        let response = self.call(action).await?;
+
+        let ip = xml_element_text(&response, element)
+            .filter(|ip| !ip.is_empty())
+            .ok_or_else(|| {
+                SourceError::IpNotFoundError(format!(
+                    "No {:?} address on the Fritz!Box WAN",
+                    version
+                ))
+            })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    async fn get_ipv6_prefix(&mut self) -> Result<Ipv6Prefix, SourceError> {
+        self.check_connection().await?;
+        let response = self.call("X_AVM_DE_GetIPv6Prefix").await?;
+
+        let address = xml_element_text(&response, "NewIPv6Prefix")
+            .filter(|prefix| !prefix.is_empty())
+            .ok_or_else(|| {
+                SourceError::IpNotFoundError(
+                    "No IPv6 prefix delegated to the Fritz!Box".to_string(),
+                )
+            })?;
+        let address: Ipv6Addr = address.parse().map_err(|_| {
+            SourceError::ValidationError(format!("Invalid IPv6 prefix: {}", address))
+        })?;
+        let length = xml_element_text(&response, "NewPrefixLength")
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| {
+                SourceError::ApiError("Fritz!Box sent no IPv6 prefix length".to_string())
+            })?;
+
+        Ipv6Prefix::new(address, length)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
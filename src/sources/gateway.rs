@@ -0,0 +1,638 @@
+use super::{
+    IpAddress, IpSource, IpVersion, SourceError, transaction_id, udp_exchange, validate_ip_address,
+};
+use crate::config::{Gateway, GatewayProtocol};
+use crate::utils::xml_element_text;
+use async_trait::async_trait;
+use reqwest::Client;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::SystemTime;
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+const SSDP_MULTICAST_ADDRESS: &str = "239.255.255.250:1900";
+const SSDP_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+const IGD_SERVICE_TYPES: [&str; 2] = ["WANIPConnection", "WANPPPConnection"];
+
+const NAT_PMP_PORT: u16 = 5351;
+const NAT_PMP_VERSION: u8 = 0;
+const NAT_PMP_OP_EXTERNAL_ADDRESS: u8 = 0;
+
+const PCP_VERSION: u8 = 2;
+const PCP_OP_MAP: u8 = 1;
+const PCP_PROTOCOL_UDP: u8 = 17;
+// The mapping is only used to learn the external address and deleted right
+// after, it expires on its own if the deletion is lost
+const PCP_MAPPING_LIFETIME: u32 = 60;
+const PCP_RESPONSE_LENGTH: usize = 60;
+
+const PROC_NET_ROUTE: &str = "/proc/net/route";
+
+pub struct GatewaySource {
+    client: Client,
+    config: Gateway,
+    name: String,
+}
+
+impl GatewaySource {
+    pub fn new(config: &Gateway) -> Result<Self, SourceError> {
+        let name = match config.protocol {
+            GatewayProtocol::Upnp => "upnp",
+            GatewayProtocol::NatPmp => "nat-pmp",
+            GatewayProtocol::Pcp => "pcp",
+        };
+
+        Ok(Self {
+            client: Client::new(),
+            config: config.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    // find the IGD description URL with an SSDP M-SEARCH
+    async fn discover_igd(&self) -> Result<String, SourceError> {
+        // replies come from the gateway unicast address, so the socket is not
+        // connected to the multicast group
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+        let target: SocketAddr = SSDP_MULTICAST_ADDRESS
+            .parse()
+            .map_err(|e| SourceError::ValidationError(format!("Invalid SSDP address: {}", e)))?;
+
+        let request = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+            SSDP_MULTICAST_ADDRESS, SSDP_SEARCH_TARGET
+        );
+
+        socket
+            .send_to(request.as_bytes(), target)
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        let mut buffer = vec![0u8; 2048];
+        let (size, from) = tokio::time::timeout(
+            self.config.timeout_in_seconds,
+            socket.recv_from(&mut buffer),
+        )
+        .await
+        .map_err(|_| {
+            SourceError::ConnectionError("No UPnP gateway answered SSDP search".to_string())
+        })?
+        .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        let response = String::from_utf8_lossy(&buffer[..size]);
+        debug!("SSDP response from {}: {}", from, response);
+
+        ssdp_location(&response)
+    }
+
+    // find the WAN connection service and its control URL in the IGD description
+    async fn get_igd_control(
+        &self,
+        description_url: &str,
+    ) -> Result<(String, String), SourceError> {
+        let description = self
+            .client
+            .get(description_url)
+            .timeout(self.config.timeout_in_seconds)
+            .send()
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?
+            .text()
+            .await
+            .map_err(|e| SourceError::ApiError(e.to_string()))?;
+
+        igd_control(&description, description_url)
+    }
+
+    async fn get_upnp_ip(&self) -> Result<Ipv4Addr, SourceError> {
+        let description_url = match &self.config.description_url {
+            Some(url) => url.clone(),
+            None => self.discover_igd().await?,
+        };
+        let (service_type, control_url) = self.get_igd_control(&description_url).await?;
+
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0"?>"#,
+                r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" "#,
+                r#"s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+                r#"<s:Body><u:GetExternalIPAddress xmlns:u="{}"/></s:Body></s:Envelope>"#
+            ),
+            service_type
+        );
+
+        let response = self
+            .client
+            .post(&control_url)
+            .header("Content-Type", r#"text/xml; charset="utf-8""#)
+            .header(
+                "SOAPAction",
+                format!("\"{}#GetExternalIPAddress\"", service_type),
+            )
+            .body(body)
+            .timeout(self.config.timeout_in_seconds)
+            .send()
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(SourceError::ApiError(format!(
+                "GetExternalIPAddress failed: {}",
+                response.status()
+            )));
+        }
+
+        let text = response
+            .text()
+            .await
+            .map_err(|e| SourceError::ApiError(e.to_string()))?;
+
+        soap_external_address(&text)
+    }
+
+    // read the IPv4 default route gateway
+    fn default_gateway() -> Result<Ipv4Addr, SourceError> {
+        let routes = std::fs::read_to_string(PROC_NET_ROUTE).map_err(|e| {
+            SourceError::ConnectionError(format!("Failed to read {}: {}", PROC_NET_ROUTE, e))
+        })?;
+
+        routes
+            .lines()
+            .skip(1)
+            .find_map(|line| {
+                // Iface Destination Gateway Flags ...
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() < 3 || fields[1] != "00000000" {
+                    return None;
+                }
+                let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+                Some(Ipv4Addr::from(gateway.to_ne_bytes()))
+            })
+            .ok_or_else(|| SourceError::ConnectionError("No default gateway found".to_string()))
+    }
+
+    fn gateway_address(&self) -> Result<SocketAddr, SourceError> {
+        match &self.config.address {
+            Some(address) => address
+                .parse::<SocketAddr>()
+                .or_else(|_| {
+                    address
+                        .parse::<IpAddr>()
+                        .map(|ip| SocketAddr::new(ip, NAT_PMP_PORT))
+                })
+                .map_err(|e| {
+                    SourceError::ValidationError(format!(
+                        "Invalid gateway address `{}`: {}",
+                        address, e
+                    ))
+                }),
+            None => Ok(SocketAddr::new(
+                IpAddr::V4(Self::default_gateway()?),
+                NAT_PMP_PORT,
+            )),
+        }
+    }
+
+    async fn connect(&self) -> Result<UdpSocket, SourceError> {
+        let gateway = self.gateway_address()?;
+        let bind_address = match gateway {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+
+        let socket = UdpSocket::bind(bind_address)
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+        socket
+            .connect(gateway)
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        Ok(socket)
+    }
+
+    // RFC 6886 section 3.2
+    async fn get_nat_pmp_ip(&self) -> Result<Ipv4Addr, SourceError> {
+        let socket = self.connect().await?;
+        let request = [NAT_PMP_VERSION, NAT_PMP_OP_EXTERNAL_ADDRESS];
+        let response = udp_exchange(&socket, &request, self.config.timeout_in_seconds).await?;
+
+        parse_nat_pmp_response(&response)
+    }
+
+    // RFC 6887 section 11, a MAP request returns the assigned external address
+    async fn get_pcp_ip(&self) -> Result<Ipv4Addr, SourceError> {
+        let socket = self.connect().await?;
+        let local_address = socket
+            .local_addr()
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        let client_address = match local_address.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        let nonce = transaction_id();
+        let map =
+            |lifetime| pcp_map_request(lifetime, client_address, &nonce, local_address.port());
+
+        let response = udp_exchange(
+            &socket,
+            &map(PCP_MAPPING_LIFETIME),
+            self.config.timeout_in_seconds,
+        )
+        .await?;
+        let external = parse_pcp_response(&response, &nonce)?;
+
+        // a zero lifetime deletes the mapping (section 15)
+        match udp_exchange(&socket, &map(0), self.config.timeout_in_seconds)
+            .await
+            .and_then(|response| parse_pcp_response(&response, &nonce))
+        {
+            Ok(_) => debug!("Deleted PCP mapping of port {}", local_address.port()),
+            Err(e) => debug!(
+                "Failed to delete PCP mapping, it expires in {} seconds: {}",
+                PCP_MAPPING_LIFETIME, e
+            ),
+        }
+
+        external.to_ipv4_mapped().ok_or_else(|| {
+            SourceError::IpNotFoundError("PCP external address is not IPv4".to_string())
+        })
+    }
+}
+
+/// Location of the IGD description in an SSDP search response
+fn ssdp_location(response: &str) -> Result<String, SourceError> {
+    response
+        .lines()
+        .find_map(|line| {
+            let (header, value) = line.split_once(':')?;
+            header
+                .trim()
+                .eq_ignore_ascii_case("location")
+                .then(|| value.trim().to_string())
+        })
+        .ok_or_else(|| SourceError::ApiError("SSDP response has no LOCATION header".to_string()))
+}
+
+/// Service type and absolute control URL of the WAN connection service
+fn igd_control(description: &str, description_url: &str) -> Result<(String, String), SourceError> {
+    let document = roxmltree::Document::parse(description)
+        .map_err(|e| SourceError::ApiError(format!("Invalid IGD description: {}", e)))?;
+
+    let child_text = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|child| child.tag_name().name() == name)
+            .and_then(|child| child.text())
+            .map(|text| text.trim().to_string())
+    };
+
+    let (service_type, control_url) = document
+        .descendants()
+        .filter(|node| node.tag_name().name() == "service")
+        .find_map(|service| {
+            let service_type = child_text(service, "serviceType")?;
+            if !IGD_SERVICE_TYPES
+                .iter()
+                .any(|kind| service_type.contains(kind))
+            {
+                return None;
+            }
+            Some((service_type, child_text(service, "controlURL")?))
+        })
+        .ok_or_else(|| {
+            SourceError::ApiError("No WAN connection service in IGD description".to_string())
+        })?;
+
+    let base_url =
+        xml_element_text(description, "URLBase").unwrap_or_else(|| description_url.to_string());
+    let control_url = reqwest::Url::parse(&base_url)
+        .and_then(|base| base.join(&control_url))
+        .map_err(|e| SourceError::ApiError(format!("Invalid IGD control URL: {}", e)))?;
+
+    Ok((service_type, control_url.to_string()))
+}
+
+/// External address in a GetExternalIPAddress SOAP response
+fn soap_external_address(response: &str) -> Result<Ipv4Addr, SourceError> {
+    xml_element_text(response, "NewExternalIPAddress")
+        .ok_or_else(|| {
+            SourceError::IpNotFoundError("No NewExternalIPAddress in response".to_string())
+        })?
+        .parse()
+        .map_err(|e| SourceError::ValidationError(format!("Invalid external address: {}", e)))
+}
+
+/// External address in a NAT-PMP response (RFC 6886 section 3.2)
+fn parse_nat_pmp_response(response: &[u8]) -> Result<Ipv4Addr, SourceError> {
+    if response.len() < 12 || response[1] != 128 + NAT_PMP_OP_EXTERNAL_ADDRESS {
+        return Err(SourceError::ApiError(
+            "Invalid NAT-PMP response".to_string(),
+        ));
+    }
+
+    let result_code = u16::from_be_bytes([response[2], response[3]]);
+    if result_code != 0 {
+        return Err(SourceError::ApiError(format!(
+            "NAT-PMP error result code: {}",
+            result_code
+        )));
+    }
+
+    Ok(Ipv4Addr::new(
+        response[8],
+        response[9],
+        response[10],
+        response[11],
+    ))
+}
+
+/// PCP MAP request for the UDP `port` of `client_address` (RFC 6887 section 11.1)
+fn pcp_map_request(
+    lifetime: u32,
+    client_address: Ipv6Addr,
+    nonce: &[u8; 12],
+    port: u16,
+) -> Vec<u8> {
+    let mut request = Vec::with_capacity(60);
+    request.extend_from_slice(&[PCP_VERSION, PCP_OP_MAP, 0, 0]);
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    request.extend_from_slice(&client_address.octets());
+    request.extend_from_slice(nonce);
+    request.extend_from_slice(&[PCP_PROTOCOL_UDP, 0, 0, 0]);
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    // no preferred external address
+    request.extend_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+    request
+}
+
+/// Assigned external address in the response to a MAP request
+fn parse_pcp_response(response: &[u8], nonce: &[u8; 12]) -> Result<Ipv6Addr, SourceError> {
+    if response.len() < PCP_RESPONSE_LENGTH || response[1] != 0x80 | PCP_OP_MAP {
+        return Err(SourceError::ApiError("Invalid PCP response".to_string()));
+    }
+    if response[3] != 0 {
+        return Err(SourceError::ApiError(format!(
+            "PCP error result code: {}",
+            response[3]
+        )));
+    }
+    if response[24..36] != nonce[..] {
+        return Err(SourceError::ApiError(
+            "PCP response nonce mismatch".to_string(),
+        ));
+    }
+
+    let mut external = [0u8; 16];
+    external.copy_from_slice(&response[44..60]);
+    Ok(Ipv6Addr::from(external))
+}
+
+#[async_trait]
+impl IpSource for GatewaySource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        if version != IpVersion::IPv4 {
+            return Err(SourceError::ValidationError(format!(
+                "{} only provides the external IPv4 address",
+                self.name
+            )));
+        }
+
+        let ip = match self.config.protocol {
+            GatewayProtocol::Upnp => self.get_upnp_ip().await?,
+            GatewayProtocol::NatPmp => self.get_nat_pmp_ip().await?,
+            GatewayProtocol::Pcp => self.get_pcp_ip().await?,
+        }
+        .to_string();
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IGD_DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <deviceList>
+      <device>
+        <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+        <serviceList>
+          <service>
+            <serviceType>urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1</serviceType>
+            <controlURL>/ctl/CmnIfCfg</controlURL>
+          </service>
+        </serviceList>
+        <deviceList>
+          <device>
+            <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+            <serviceList>
+              <service>
+                <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                <controlURL>/ctl/IPConn</controlURL>
+              </service>
+            </serviceList>
+          </device>
+        </deviceList>
+      </device>
+    </deviceList>
+  </device>
+</root>"#;
+
+    fn pcp_response(request: &[u8], result_code: u8, external: Ipv4Addr) -> Vec<u8> {
+        let mut response = request.to_vec();
+        response[1] = 0x80 | PCP_OP_MAP;
+        response[3] = result_code;
+        // epoch, then reserved
+        response[8..12].copy_from_slice(&1234u32.to_be_bytes());
+        response[12..24].fill(0);
+        response[42..44].copy_from_slice(&40000u16.to_be_bytes());
+        response[44..60].copy_from_slice(&external.to_ipv6_mapped().octets());
+        response
+    }
+
+    #[test]
+    fn ssdp_location_is_read() {
+        let response = "HTTP/1.1 200 OK\r\n\
+            CACHE-CONTROL: max-age=120\r\n\
+            ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+            Location: http://192.168.1.1:5000/rootDesc.xml\r\n\
+            SERVER: OpenWRT/OpenWrt UPnP/1.1 MiniUPnPd/2.3.3\r\n\r\n";
+
+        assert_eq!(
+            ssdp_location(response).unwrap(),
+            "http://192.168.1.1:5000/rootDesc.xml"
+        );
+        assert!(matches!(
+            ssdp_location("HTTP/1.1 200 OK\r\nST: upnp:rootdevice\r\n\r\n"),
+            Err(SourceError::ApiError(_))
+        ));
+    }
+
+    #[test]
+    fn wan_connection_service_is_found() {
+        assert_eq!(
+            igd_control(IGD_DESCRIPTION, "http://192.168.1.1:5000/rootDesc.xml").unwrap(),
+            (
+                "urn:schemas-upnp-org:service:WANIPConnection:1".to_string(),
+                "http://192.168.1.1:5000/ctl/IPConn".to_string()
+            )
+        );
+
+        let with_base = IGD_DESCRIPTION.replace(
+            "  <device>\n    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice",
+            "  <URLBase>http://192.168.1.1:49000/</URLBase>\n  <device>\n    \
+             <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice",
+        );
+        assert_eq!(
+            igd_control(&with_base, "http://192.168.1.1:5000/rootDesc.xml")
+                .unwrap()
+                .1,
+            "http://192.168.1.1:49000/ctl/IPConn"
+        );
+
+        let without_wan = IGD_DESCRIPTION.replace("WANIPConnection", "Layer3Forwarding");
+        assert!(matches!(
+            igd_control(&without_wan, "http://192.168.1.1:5000/rootDesc.xml"),
+            Err(SourceError::ApiError(_))
+        ));
+    }
+
+    #[test]
+    fn soap_external_address_is_read() {
+        let response = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+  <s:Body>
+    <u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+      <NewExternalIPAddress>203.0.113.7</NewExternalIPAddress>
+    </u:GetExternalIPAddressResponse>
+  </s:Body>
+</s:Envelope>"#;
+
+        assert_eq!(
+            soap_external_address(response).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7)
+        );
+        assert!(matches!(
+            soap_external_address(&response.replace("203.0.113.7", "")),
+            Err(SourceError::IpNotFoundError(_))
+        ));
+        assert!(matches!(
+            soap_external_address(&response.replace("203.0.113.7", "203.0.113")),
+            Err(SourceError::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn nat_pmp_external_address_is_read() {
+        // version, opcode, result code, epoch, address
+        let response = [0, 128, 0, 0, 0, 0, 0x04, 0xd2, 203, 0, 113, 7];
+
+        assert_eq!(
+            parse_nat_pmp_response(&response).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7)
+        );
+        // network failure
+        let mut failure = response;
+        failure[3] = 3;
+        assert!(parse_nat_pmp_response(&failure).is_err());
+        // response to another opcode
+        let mut other = response;
+        other[1] = 129;
+        assert!(parse_nat_pmp_response(&other).is_err());
+        assert!(parse_nat_pmp_response(&response[..11]).is_err());
+    }
+
+    #[test]
+    fn pcp_map_request_layout() {
+        let nonce = [7u8; 12];
+        let client = Ipv4Addr::new(192, 168, 1, 10).to_ipv6_mapped();
+        let request = pcp_map_request(PCP_MAPPING_LIFETIME, client, &nonce, 50000);
+
+        assert_eq!(request.len(), 60);
+        assert_eq!(request[..4], [PCP_VERSION, PCP_OP_MAP, 0, 0]);
+        assert_eq!(request[4..8], 60u32.to_be_bytes());
+        assert_eq!(request[8..24], client.octets());
+        assert_eq!(request[24..36], nonce);
+        assert_eq!(request[36..40], [PCP_PROTOCOL_UDP, 0, 0, 0]);
+        assert_eq!(request[40..42], 50000u16.to_be_bytes());
+        assert_eq!(request[42..44], [0, 0]);
+        assert_eq!(
+            request[44..60],
+            Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets()
+        );
+        // a deletion only differs by its lifetime
+        let deletion = pcp_map_request(0, client, &nonce, 50000);
+        assert_eq!(deletion[4..8], [0, 0, 0, 0]);
+        assert_eq!(deletion[..4], request[..4]);
+        assert_eq!(deletion[8..], request[8..]);
+    }
+
+    #[test]
+    fn pcp_external_address_is_read() {
+        let nonce = [7u8; 12];
+        let client = Ipv4Addr::new(192, 168, 1, 10).to_ipv6_mapped();
+        let request = pcp_map_request(PCP_MAPPING_LIFETIME, client, &nonce, 50000);
+        let response = pcp_response(&request, 0, Ipv4Addr::new(203, 0, 113, 7));
+
+        assert_eq!(
+            parse_pcp_response(&response, &nonce).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7).to_ipv6_mapped()
+        );
+        // NOT_AUTHORIZED
+        let refused = pcp_response(&request, 2, Ipv4Addr::UNSPECIFIED);
+        assert!(parse_pcp_response(&refused, &nonce).is_err());
+        assert!(parse_pcp_response(&response, &[8u8; 12]).is_err());
+        // the request itself is not a response
+        assert!(parse_pcp_response(&request, &nonce).is_err());
+        assert!(parse_pcp_response(&response[..59], &nonce).is_err());
+    }
+
+    #[tokio::test]
+    async fn pcp_mapping_is_deleted_after_reading_the_address() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = format!(
+            "{{protocol: pcp, address: '{}'}}",
+            server.local_addr().unwrap()
+        );
+        let mut source = GatewaySource::new(&serde_yaml::from_str(&config).unwrap()).unwrap();
+
+        let gateway = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            let mut buffer = [0u8; 1100];
+            for _ in 0..2 {
+                let (size, from) = server.recv_from(&mut buffer).await.unwrap();
+                let request = buffer[..size].to_vec();
+                let response = pcp_response(&request, 0, Ipv4Addr::new(203, 0, 113, 7));
+                server.send_to(&response, from).await.unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let ip = source.get_ip(IpVersion::IPv4).await.unwrap();
+        assert_eq!(ip.address, "203.0.113.7");
+
+        let requests = gateway.await.unwrap();
+        assert_eq!(requests[0][4..8], PCP_MAPPING_LIFETIME.to_be_bytes());
+        assert_eq!(requests[1][4..8], [0, 0, 0, 0]);
+        // same nonce and internal port, so the same mapping
+        assert_eq!(requests[1][24..44], requests[0][24..44]);
+    }
+}
@@ -0,0 +1,275 @@
+use super::{IpAddress, IpSource, IpVersion, SourceError, validate_ip_address};
+use crate::config::{Http, HttpMethod};
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::{
+    Client,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime};
+
+/// How the address is extracted from the response body
+enum Extractor {
+    PlainText,
+    JsonPointer(String),
+    Regex(Regex),
+}
+
+pub struct HttpSource {
+    name: String,
+    client_v4: Client,
+    client_v6: Client,
+    url: String,
+    method: HttpMethod,
+    body: Option<String>,
+    headers: HeaderMap,
+    extractor: Extractor,
+    timeout: Duration,
+}
+
+impl HttpSource {
+    pub fn new(config: &Http) -> Result<Self, SourceError> {
+        let extractor = match (&config.json_pointer, &config.regex) {
+            (Some(_), Some(_)) => {
+                return Err(SourceError::ValidationError(
+                    "Only one of json_pointer or regex can be set".to_string(),
+                ));
+            }
+            (Some(pointer), None) => Extractor::JsonPointer(pointer.clone()),
+            (None, Some(pattern)) => Extractor::Regex(Regex::new(pattern).map_err(|e| {
+                SourceError::ValidationError(format!("Invalid regex `{}`: {}", pattern, e))
+            })?),
+            (None, None) => Extractor::PlainText,
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in config.headers.iter().flatten() {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+                SourceError::ValidationError(format!("Invalid header name `{}`: {}", name, e))
+            })?;
+            let value = HeaderValue::from_str(value).map_err(|e| {
+                SourceError::ValidationError(format!("Invalid header value: {}", e))
+            })?;
+            headers.insert(name, value);
+        }
+
+        Ok(Self {
+            name: format!("http ({})", config.url),
+            client_v4: Self::build_client(IpAddr::V4(Ipv4Addr::UNSPECIFIED))?,
+            client_v6: Self::build_client(IpAddr::V6(Ipv6Addr::UNSPECIFIED))?,
+            url: config.url.clone(),
+            method: config.method.clone(),
+            body: config.body.clone(),
+            headers,
+            extractor,
+            timeout: config.timeout_in_seconds,
+        })
+    }
+
+    // bind the client to the requested address family so dual-stack hosts
+    // reach the endpoint over the IP version being looked up
+    fn build_client(local_address: IpAddr) -> Result<Client, SourceError> {
+        Client::builder()
+            .local_address(local_address)
+            .build()
+            .map_err(|e| SourceError::NetworkError(format!("Failed to create HTTP client: {}", e)))
+    }
+
+    async fn fetch_body(&self, version: IpVersion) -> Result<String, SourceError> {
+        let client = match version {
+            IpVersion::IPv4 => &self.client_v4,
+            IpVersion::IPv6 => &self.client_v6,
+        };
+
+        let request = match self.method {
+            HttpMethod::Get => client.get(&self.url),
+            HttpMethod::Post => client.post(&self.url),
+        };
+        let request = match &self.body {
+            Some(body) => request.body(body.clone()),
+            None => request,
+        };
+
+        let response = request
+            .headers(self.headers.clone())
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(SourceError::ApiError(format!(
+                "HTTP source {} returned {}",
+                self.url,
+                response.status()
+            )));
+        }
+
+        response
+            .text()
+            .await
+            .map_err(|e| SourceError::ApiError(e.to_string()))
+    }
+
+    fn extract_ip(&self, body: &str) -> Result<String, SourceError> {
+        let ip = match &self.extractor {
+            Extractor::PlainText => body.trim().to_string(),
+            Extractor::JsonPointer(pointer) => {
+                let json: serde_json::Value = serde_json::from_str(body)
+                    .map_err(|e| SourceError::ApiError(format!("Invalid JSON response: {}", e)))?;
+                match json.pointer(pointer) {
+                    Some(serde_json::Value::String(value)) => value.trim().to_string(),
+                    Some(value) => {
+                        return Err(SourceError::ApiError(format!(
+                            "JSON pointer `{}` is not a string: {}",
+                            pointer, value
+                        )));
+                    }
+                    None => {
+                        return Err(SourceError::IpNotFoundError(format!(
+                            "JSON pointer `{}` not found in response",
+                            pointer
+                        )));
+                    }
+                }
+            }
+            Extractor::Regex(regex) => {
+                let captures = regex.captures(body).ok_or_else(|| {
+                    SourceError::IpNotFoundError(format!(
+                        "Regex `{}` did not match response",
+                        regex.as_str()
+                    ))
+                })?;
+                // prefer the first capture group, fall back to the whole match
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|m| m.as_str().trim().to_string())
+                    .unwrap_or_default()
+            }
+        };
+
+        if ip.is_empty() {
+            return Err(SourceError::IpNotFoundError(
+                "Empty address in response".to_string(),
+            ));
+        }
+
+        Ok(ip)
+    }
+}
+
+#[async_trait]
+impl IpSource for HttpSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let body = self.fetch_body(version).await?;
+        let ip = self.extract_ip(&body)?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        http::{HeaderMap as AxumHeaderMap, StatusCode},
+        routing::{get, post},
+    };
+    use serde_json::json;
+
+    /// Local stand-in for "what is my IP" services
+    async fn start_server() -> String {
+        let app = Router::new()
+            .route("/plain", get(|| async { "203.0.113.5\n" }))
+            .route(
+                "/json",
+                get(|| async { Json(json!({"data": {"ip": "203.0.113.6", "port": 443}})) }),
+            )
+            .route(
+                "/html",
+                get(|| async { "<p>Current IP Address: 203.0.113.7</p>" }),
+            )
+            // echo the body to requests carrying the expected header
+            .route(
+                "/echo",
+                post(|headers: AxumHeaderMap, body: String| async move {
+                    match headers.get("x-token").and_then(|token| token.to_str().ok()) {
+                        Some("secret") => (StatusCode::OK, body),
+                        _ => (StatusCode::UNAUTHORIZED, String::new()),
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    async fn get_ip(config: &str) -> Result<IpAddress, SourceError> {
+        let config: Http = serde_yaml::from_str(config).unwrap();
+        HttpSource::new(&config)?.get_ip(IpVersion::IPv4).await
+    }
+
+    #[tokio::test]
+    async fn plain_text_body() {
+        let url = start_server().await;
+
+        let ip = get_ip(&format!("url: {}/plain", url)).await.unwrap();
+
+        assert_eq!(ip.address, "203.0.113.5");
+    }
+
+    #[tokio::test]
+    async fn json_pointer() {
+        let url = start_server().await;
+
+        let ip = get_ip(&format!("url: {}/json\njson_pointer: /data/ip", url))
+            .await
+            .unwrap();
+        let not_a_string = get_ip(&format!("url: {}/json\njson_pointer: /data/port", url)).await;
+        let missing = get_ip(&format!("url: {}/json\njson_pointer: /ip", url)).await;
+
+        assert_eq!(ip.address, "203.0.113.6");
+        assert!(matches!(not_a_string, Err(SourceError::ApiError(_))));
+        assert!(matches!(missing, Err(SourceError::IpNotFoundError(_))));
+    }
+
+    #[tokio::test]
+    async fn regex_capture_group() {
+        let url = start_server().await;
+
+        let ip = get_ip(&format!("url: {}/html\nregex: 'Address: ([0-9.]+)'", url))
+            .await
+            .unwrap();
+
+        assert_eq!(ip.address, "203.0.113.7");
+    }
+
+    #[tokio::test]
+    async fn post_with_body_and_headers() {
+        let url = start_server().await;
+        let config = format!(
+            "url: {}/echo\nmethod: POST\nbody: '203.0.113.8'\nheaders:\n  x-token: secret",
+            url
+        );
+
+        let ip = get_ip(&config).await.unwrap();
+        let unauthorized = get_ip(&config.replace("secret", "wrong")).await;
+
+        assert_eq!(ip.address, "203.0.113.8");
+        assert!(matches!(unauthorized, Err(SourceError::ApiError(_))));
+    }
+}
@@ -0,0 +1,308 @@
+use super::{IpAddress, IpSource, IpVersion, Ipv6Prefix, SourceError, validate_ip_address};
+use crate::config::Interface;
+use async_trait::async_trait;
+use if_addrs::IfAddr;
+use std::net::Ipv6Addr;
+use std::time::SystemTime;
+
+// The kernel exposes IPv6 address flags (temporary, deprecated...) only
+// through netlink or this file, getifaddrs(3) does not return them
+const PROC_NET_IF_INET6: &str = "/proc/net/if_inet6";
+
+// Flags from include/uapi/linux/if_addr.h
+const IFA_F_TEMPORARY: u32 = 0x01;
+const IFA_F_DEPRECATED: u32 = 0x20;
+
+// Scopes from include/net/ipv6.h
+const IPV6_ADDR_SCOPE_LINK: u32 = 0x20;
+
+#[derive(Debug, Clone)]
+struct Ipv6Candidate {
+    address: Ipv6Addr,
+    prefix_length: u8,
+    scope: u32,
+    flags: u32,
+}
+
+impl Ipv6Candidate {
+    fn is_link_local(&self) -> bool {
+        self.scope == IPV6_ADDR_SCOPE_LINK || (self.address.segments()[0] & 0xffc0) == 0xfe80
+    }
+
+    fn is_ula(&self) -> bool {
+        (self.address.segments()[0] & 0xfe00) == 0xfc00
+    }
+
+    fn is_temporary(&self) -> bool {
+        self.flags & IFA_F_TEMPORARY != 0
+    }
+
+    fn is_deprecated(&self) -> bool {
+        self.flags & IFA_F_DEPRECATED != 0
+    }
+
+    // EUI-64 interface identifiers have ff:fe in the middle of the MAC address
+    fn is_eui64(&self) -> bool {
+        let octets = self.address.octets();
+        octets[11] == 0xff && octets[12] == 0xfe
+    }
+}
+
+pub struct InterfaceSource {
+    config: Interface,
+    name: String,
+}
+
+impl InterfaceSource {
+    pub fn new(config: &Interface) -> Result<Self, SourceError> {
+        if config.name.is_empty() {
+            return Err(SourceError::ValidationError(
+                "Interface name cannot be empty".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            name: format!("interface ({})", config.name),
+        })
+    }
+
+    fn get_ipv4(&self) -> Result<String, SourceError> {
+        let interfaces = if_addrs::get_if_addrs().map_err(|e| {
+            SourceError::ConnectionError(format!("Failed to list interfaces: {}", e))
+        })?;
+
+        interfaces
+            .into_iter()
+            .filter(|interface| interface.name == self.config.name)
+            .find_map(|interface| match interface.addr {
+                IfAddr::V4(addr) if !(self.config.skip_link_local && addr.ip.is_link_local()) => {
+                    Some(addr.ip.to_string())
+                }
+                _ => None,
+            })
+            .ok_or_else(|| {
+                SourceError::IpNotFoundError(format!(
+                    "No IPv4 address found on interface {}",
+                    self.config.name
+                ))
+            })
+    }
+
+    fn select_ipv6(&self) -> Result<Ipv6Candidate, SourceError> {
+        let contents = std::fs::read_to_string(PROC_NET_IF_INET6).map_err(|e| {
+            SourceError::ConnectionError(format!("Failed to read {}: {}", PROC_NET_IF_INET6, e))
+        })?;
+        self.best_ipv6(parse_if_inet6(&contents, &self.config.name)?)
+    }
+
+    fn best_ipv6(&self, candidates: Vec<Ipv6Candidate>) -> Result<Ipv6Candidate, SourceError> {
+        let mut candidates: Vec<Ipv6Candidate> = candidates
+            .into_iter()
+            .filter(|c| !c.address.is_loopback())
+            .filter(|c| !(self.config.skip_link_local && c.is_link_local()))
+            .filter(|c| !(self.config.skip_ula && c.is_ula()))
+            .filter(|c| !(self.config.skip_deprecated && c.is_deprecated()))
+            .filter(|c| !(self.config.skip_temporary && c.is_temporary()))
+            .collect();
+
+        // EUI-64 addresses first if requested, then stable before temporary ones
+        candidates.sort_by_key(|c| (self.config.prefer_eui64 && !c.is_eui64(), c.is_temporary()));
+
+        candidates.into_iter().next().ok_or_else(|| {
+            SourceError::IpNotFoundError(format!(
+                "No matching IPv6 address found on interface {}",
+                self.config.name
+            ))
+        })
+    }
+}
+
+/// Parse the addresses of `interface` from the contents of /proc/net/if_inet6
+fn parse_if_inet6(contents: &str, interface: &str) -> Result<Vec<Ipv6Candidate>, SourceError> {
+    let mut candidates = Vec::new();
+    for line in contents.lines() {
+        // address ifindex prefix_length scope flags name
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 6 || fields[5] != interface {
+            continue;
+        }
+
+        let address = u128::from_str_radix(fields[0], 16).map_err(|e| {
+            SourceError::ValidationError(format!("Invalid address in {}: {}", PROC_NET_IF_INET6, e))
+        })?;
+        let parse_hex = |field: &str| {
+            u32::from_str_radix(field, 16).map_err(|e| {
+                SourceError::ValidationError(format!(
+                    "Invalid field in {}: {}",
+                    PROC_NET_IF_INET6, e
+                ))
+            })
+        };
+
+        candidates.push(Ipv6Candidate {
+            address: Ipv6Addr::from(address),
+            prefix_length: parse_hex(fields[2])? as u8,
+            scope: parse_hex(fields[3])?,
+            flags: parse_hex(fields[4])?,
+        });
+    }
+
+    Ok(candidates)
+}
+
+#[async_trait]
+impl IpSource for InterfaceSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let ip = match version {
+            IpVersion::IPv4 => self.get_ipv4()?,
+            IpVersion::IPv6 => self.select_ipv6()?.address.to_string(),
+        };
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    async fn get_ipv6_prefix(&mut self) -> Result<Ipv6Prefix, SourceError> {
+        let candidate = self.select_ipv6()?;
+        Ipv6Prefix::new(candidate.address, candidate.prefix_length)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IF_INET6: &str = include_str!("testdata/if_inet6");
+
+    fn source(config: &str) -> InterfaceSource {
+        InterfaceSource::new(&serde_yaml::from_str(config).unwrap()).unwrap()
+    }
+
+    fn best(config: &str, contents: &str) -> Result<Ipv6Addr, SourceError> {
+        let source = source(config);
+        let candidates = parse_if_inet6(contents, &source.config.name)?;
+        Ok(source.best_ipv6(candidates)?.address)
+    }
+
+    #[test]
+    fn lines_of_the_interface_are_parsed() {
+        let candidates = parse_if_inet6(IF_INET6, "eth0").unwrap();
+
+        assert_eq!(candidates.len(), 6);
+        let link_local = &candidates[0];
+        assert_eq!(
+            link_local.address,
+            "fe80::21a:2bff:fe3c:4d5e".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(link_local.prefix_length, 64);
+        assert!(link_local.is_link_local() && link_local.is_eui64());
+        assert!(candidates[1].is_ula());
+        assert!(candidates[2].is_temporary() && !candidates[2].is_deprecated());
+        assert!(candidates[3].is_deprecated() && !candidates[3].is_temporary());
+        assert_eq!(candidates[3].prefix_length, 56);
+
+        assert_eq!(parse_if_inet6(IF_INET6, "wlan0").unwrap().len(), 1);
+        assert!(parse_if_inet6(IF_INET6, "eth1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_line_is_an_error() {
+        let contents = "20010db8zz0100020000000000000001 02 40 00 80     eth0\n";
+
+        assert!(matches!(
+            parse_if_inet6(contents, "eth0"),
+            Err(SourceError::ValidationError(_))
+        ));
+        // lines of other interfaces are not even parsed
+        assert!(parse_if_inet6(contents, "eth1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn first_stable_global_address_is_selected() {
+        // link-local, ULA, temporary and deprecated addresses come first
+        assert_eq!(
+            best("name: eth0", IF_INET6).unwrap(),
+            "2001:db8:1:2:1234:5678:90ab:cdef"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn eui64_address_is_preferred() {
+        assert_eq!(
+            best("{name: eth0, prefer_eui64: true}", IF_INET6).unwrap(),
+            "2001:db8:1:2:21a:2bff:fe3c:4d5e"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+        // unless deprecated addresses are allowed, the one of another prefix
+        assert_eq!(
+            best(
+                "{name: eth0, prefer_eui64: true, skip_deprecated: false}",
+                IF_INET6
+            )
+            .unwrap(),
+            "2001:db8:1:ff:21a:2bff:fe3c:4d5e"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn temporary_address_comes_after_stable_ones() {
+        // only the temporary and the deprecated addresses
+        let lines: Vec<&str> = IF_INET6.lines().collect();
+        let contents = lines[3..5].join("\n");
+
+        assert_eq!(
+            best("{name: eth0, skip_temporary: false}", IF_INET6).unwrap(),
+            "2001:db8:1:2:1234:5678:90ab:cdef"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+        // a deprecated address is still stable
+        assert_eq!(
+            best(
+                "{name: eth0, skip_temporary: false, skip_deprecated: false}",
+                &contents
+            )
+            .unwrap(),
+            "2001:db8:1:ff:21a:2bff:fe3c:4d5e"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+        assert_eq!(
+            best("{name: eth0, skip_temporary: false}", &contents).unwrap(),
+            "2001:db8:1:2:a1b:2c3d:4e5f:6071"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn link_local_address_is_skipped() {
+        let contents: String = IF_INET6.lines().take(2).collect::<Vec<_>>().join("\n");
+
+        assert!(matches!(
+            best("{name: eth0}", &contents),
+            Err(SourceError::IpNotFoundError(_))
+        ));
+        assert_eq!(
+            best("{name: eth0, skip_link_local: false}", &contents).unwrap(),
+            "fe80::21a:2bff:fe3c:4d5e".parse::<Ipv6Addr>().unwrap()
+        );
+        // loopback never is an option
+        assert!(best("{name: lo, skip_link_local: false}", IF_INET6).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use super::router::{RouterClient, RouterClientBuilder};
+use super::{IpAddress, IpSource, IpVersion, Ipv6Prefix, SourceError, validate_ip_address};
+use crate::config::Livebox;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::json;
+use std::time::SystemTime;
+use tracing::debug;
+
+const SYSBUS_CONTENT_TYPE: &str = "application/x-sah-ws-4-call+json";
+
+#[derive(Debug, Deserialize)]
+struct SysbusResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<SysbusError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SysbusError {
+    error: Option<i64>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LiveboxContext {
+    #[serde(rename = "contextID")]
+    context_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LiveboxWanStatus {
+    link_state: Option<String>,
+    connection_state: Option<String>,
+    #[serde(rename = "IPAddress")]
+    ip_address: Option<String>,
+    #[serde(rename = "IPv6Address")]
+    ipv6_address: Option<String>,
+    #[serde(rename = "IPv6DelegatedPrefix")]
+    ipv6_delegated_prefix: Option<String>,
+}
+
+/// Orange Livebox WAN addresses, read from the sysbus API
+pub struct LiveboxSource {
+    config: Livebox,
+    client: RouterClient,
+    name: String,
+    context_id: Option<String>,
+}
+
+impl LiveboxSource {
+    pub fn new(config: &Livebox) -> Result<Self, SourceError> {
+        Ok(Self {
+            config: config.clone(),
+            // the session is bound to a cookie set by the login
+            client: RouterClientBuilder::new(config.timeout_in_seconds, config.retries)
+                .ca_file(config.ca_file.as_deref())
+                .cookies()
+                .build()?,
+            name: format!("livebox ({})", config.url),
+            context_id: None,
+        })
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        service: &str,
+        method: &str,
+        parameters: serde_json::Value,
+    ) -> Result<T, SourceError> {
+        let mut request = self
+            .client
+            .post(format!("{}/ws", self.config.url.trim_end_matches('/')))
+            .header("content-type", SYSBUS_CONTENT_TYPE)
+            .json(&json!({
+                "service": service,
+                "method": method,
+                "parameters": parameters,
+            }));
+        request = match &self.context_id {
+            Some(context_id) => request
+                .header("authorization", format!("X-Sah {}", context_id))
+                .header("x-context", context_id),
+            None => request.header("authorization", "X-Sah-Login"),
+        };
+
+        let response = self.client.send(request).await?;
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(SourceError::AuthenticationError(format!(
+                "Livebox rejected {}.{} ({})",
+                service, method, status
+            )));
+        }
+        let text = response
+            .text()
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        let response: SysbusResponse<T> = serde_json::from_str(&text).map_err(|e| {
+            SourceError::ApiError(format!("Invalid Livebox response ({}): {}", status, e))
+        })?;
+
+        if let Some(error) = response.errors.first() {
+            let description = error
+                .description
+                .clone()
+                .unwrap_or_else(|| "Unknown error".to_string());
+            // 13: permission denied, the context is missing or expired
+            return Err(
+                if error.error == Some(13) || description == "Permission denied" {
+                    SourceError::AuthenticationError(format!("Livebox error: {}", description))
+                } else {
+                    SourceError::ApiError(format!("Livebox error: {}", description))
+                },
+            );
+        }
+
+        response
+            .data
+            .ok_or_else(|| SourceError::ApiError("Livebox response has no data".to_string()))
+    }
+
+    async fn login(&mut self) -> Result<(), SourceError> {
+        debug!("Opening a new Livebox session on {}", self.config.url);
+        self.context_id = None;
+
+        let context: LiveboxContext = self
+            .call(
+                "sah.Device.Information",
+                "createContext",
+                json!({
+                    "applicationName": "webui",
+                    "username": self.config.username,
+                    "password": self.config.password,
+                }),
+            )
+            .await
+            .map_err(|e| match e {
+                SourceError::ApiError(message) => SourceError::AuthenticationError(message),
+                e => e,
+            })?;
+
+        self.context_id = Some(context.context_id);
+        Ok(())
+    }
+
+    /// Get the WAN status, reusing the session and logging in again once
+    /// when it is rejected
+    async fn get_wan_status(&mut self) -> Result<LiveboxWanStatus, SourceError> {
+        if self.context_id.is_none() {
+            self.login().await?;
+        }
+
+        match self.call("NMC", "getWANStatus", json!({})).await {
+            Err(SourceError::AuthenticationError(e)) => {
+                debug!("Livebox session rejected, re-authenticating: {}", e);
+                self.login().await?;
+                self.call("NMC", "getWANStatus", json!({})).await
+            }
+            result => result,
+        }
+    }
+}
+
+#[async_trait]
+impl IpSource for LiveboxSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let status = self.get_wan_status().await?;
+
+        if status.link_state.as_deref() != Some("up") {
+            return Err(SourceError::ConnectionError(format!(
+                "Livebox WAN link is not up (state: {})",
+                status.link_state.as_deref().unwrap_or("unknown")
+            )));
+        }
+        debug!(
+            "Livebox WAN connection state: {}",
+            status.connection_state.as_deref().unwrap_or("unknown")
+        );
+
+        let ip = match version {
+            IpVersion::IPv4 => status.ip_address,
+            IpVersion::IPv6 => status.ipv6_address,
+        }
+        .filter(|ip| !ip.is_empty())
+        .ok_or_else(|| {
+            SourceError::IpNotFoundError(format!("No {:?} address on the Livebox WAN", version))
+        })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    async fn get_ipv6_prefix(&mut self) -> Result<Ipv6Prefix, SourceError> {
+        self.get_wan_status()
+            .await?
+            .ipv6_delegated_prefix
+            .filter(|prefix| !prefix.is_empty())
+            .ok_or_else(|| {
+                SourceError::IpNotFoundError("No IPv6 prefix delegated to the Livebox".to_string())
+            })?
+            .parse()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::State, http::HeaderMap, routing::post};
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// sysbus API answering `getWANStatus` to the last created context only
+    #[derive(Default)]
+    struct MockLivebox {
+        valid_context: Mutex<Option<String>>,
+        contexts_created: AtomicUsize,
+        wan_status_calls: AtomicUsize,
+        link_state: Mutex<String>,
+    }
+
+    fn permission_denied() -> Json<Value> {
+        Json(json!({
+            "status": null,
+            "errors": [{"error": 13, "description": "Permission denied", "info": "NMC"}],
+        }))
+    }
+
+    async fn ws(
+        State(mock): State<Arc<MockLivebox>>,
+        headers: HeaderMap,
+        Json(request): Json<Value>,
+    ) -> Json<Value> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        assert_eq!(header("content-type").as_deref(), Some(SYSBUS_CONTENT_TYPE));
+
+        match (request["service"].as_str(), request["method"].as_str()) {
+            (Some("sah.Device.Information"), Some("createContext")) => {
+                assert_eq!(header("authorization").as_deref(), Some("X-Sah-Login"));
+                if request["parameters"]["username"] != "admin"
+                    || request["parameters"]["password"] != "secret"
+                {
+                    return Json(json!({
+                        "status": null,
+                        "errors": [{"error": 221, "description": "Invalid user/password"}],
+                    }));
+                }
+
+                let context = format!(
+                    "context-{}",
+                    mock.contexts_created.fetch_add(1, Ordering::SeqCst) + 1
+                );
+                *mock.valid_context.lock().unwrap() = Some(context.clone());
+                Json(json!({
+                    "status": 0,
+                    "data": {"contextID": context, "username": "admin", "groups": "http,admin"},
+                }))
+            }
+            (Some("NMC"), Some("getWANStatus")) => {
+                mock.wan_status_calls.fetch_add(1, Ordering::SeqCst);
+                let Some(context) = mock.valid_context.lock().unwrap().clone() else {
+                    return permission_denied();
+                };
+                if header("x-context") != Some(context.clone())
+                    || header("authorization") != Some(format!("X-Sah {}", context))
+                {
+                    return permission_denied();
+                }
+
+                Json(json!({
+                    "status": true,
+                    "data": {
+                        "LinkType": "gpon",
+                        "LinkState": *mock.link_state.lock().unwrap(),
+                        "Protocol": "dhcp",
+                        "ConnectionState": "Bound",
+                        "IPAddress": "90.12.34.56",
+                        "IPv6Address": "2a01:cb00:1234:5600::1",
+                        "IPv6DelegatedPrefix": "2a01:cb00:1234:5600::/56",
+                    },
+                }))
+            }
+            _ => Json(json!({"status": null, "errors": [{"error": 196618}]})),
+        }
+    }
+
+    async fn start_mock() -> (Arc<MockLivebox>, LiveboxSource) {
+        let mock = Arc::new(MockLivebox::default());
+        *mock.link_state.lock().unwrap() = "up".to_string();
+        let app = Router::new()
+            .route("/ws", post(ws))
+            .with_state(mock.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config: Livebox =
+            serde_yaml::from_str(&format!("url: {}\npassword: secret\nretries: 0", url)).unwrap();
+        (mock, LiveboxSource::new(&config).unwrap())
+    }
+
+    #[tokio::test]
+    async fn context_is_created_once() {
+        let (mock, mut source) = start_mock().await;
+
+        source.get_ip(IpVersion::IPv4).await.unwrap();
+        source.get_ip(IpVersion::IPv4).await.unwrap();
+
+        assert_eq!(source.context_id.as_deref(), Some("context-1"));
+        assert_eq!(mock.contexts_created.load(Ordering::SeqCst), 1);
+        assert_eq!(mock.wan_status_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn wrong_password_is_an_authentication_error() {
+        let (mock, mut source) = start_mock().await;
+        source.config.password = "wrong".to_string();
+
+        let result = source.get_ip(IpVersion::IPv4).await;
+
+        assert!(matches!(result, Err(SourceError::AuthenticationError(_))));
+        assert_eq!(mock.wan_status_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn wan_status_addresses_and_prefix() {
+        let (_mock, mut source) = start_mock().await;
+
+        let ipv4 = source.get_ip(IpVersion::IPv4).await.unwrap();
+        let ipv6 = source.get_ip(IpVersion::IPv6).await.unwrap();
+        let prefix = source.get_ipv6_prefix().await.unwrap();
+
+        assert_eq!(ipv4.address, "90.12.34.56");
+        assert_eq!(ipv6.address, "2a01:cb00:1234:5600::1");
+        assert_eq!(prefix.to_string(), "2a01:cb00:1234:5600::/56");
+    }
+
+    #[tokio::test]
+    async fn wan_link_down_is_a_connection_error() {
+        let (mock, mut source) = start_mock().await;
+        *mock.link_state.lock().unwrap() = "down".to_string();
+
+        let result = source.get_ip(IpVersion::IPv4).await;
+
+        assert!(matches!(result, Err(SourceError::ConnectionError(_))));
+    }
+
+    #[tokio::test]
+    async fn expired_context_logs_in_again() {
+        let (mock, mut source) = start_mock().await;
+        source.get_ip(IpVersion::IPv4).await.unwrap();
+
+        *mock.valid_context.lock().unwrap() = None;
+        let ip = source.get_ip(IpVersion::IPv4).await.unwrap();
+
+        assert_eq!(ip.address, "90.12.34.56");
+        assert_eq!(source.context_id.as_deref(), Some("context-2"));
+        assert_eq!(mock.contexts_created.load(Ordering::SeqCst), 2);
+        // rejected once, then sent again with the new context
+        assert_eq!(mock.wan_status_calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,9 +1,48 @@
+use crate::config::{Source, SourceProvider};
 use async_trait::async_trait;
+use bbox::BboxSource;
+use chain::ChainSource;
+use dns::DnsSource;
+use exec::ExecSource;
+use freebox::FreeboxSource;
+use fritzbox::FritzBoxSource;
+use gateway::GatewaySource;
+use http::HttpSource;
+use interface::InterfaceSource;
+use livebox::LiveboxSource;
+use openwrt::OpenWrtSource;
+use opnsense::OpnSenseSource;
+use pfsense::PfSenseSource;
+use routeros::RouterOsSource;
 use serde::{Deserialize, Serialize};
-use std::time::SystemTime;
+use sfrbox::SfrBoxSource;
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use stun::StunSource;
 use thiserror::Error;
+use tokio::net::UdpSocket;
+use unifi::UnifiSource;
 
+pub mod bbox;
+pub mod chain;
+pub mod dns;
+pub mod exec;
 pub mod freebox;
+pub mod fritzbox;
+pub mod gateway;
+pub mod http;
+pub mod interface;
+pub mod livebox;
+pub mod openwrt;
+pub mod opnsense;
+pub mod pfsense;
+pub(crate) mod router;
+pub mod routeros;
+pub mod sfrbox;
+pub mod stun;
+pub mod unifi;
 
 #[derive(Debug, Error)]
 pub enum SourceError {
@@ -19,6 +58,12 @@ pub enum SourceError {
     ConnectionError(String),
     #[error("IP not found: {0}")]
     IpNotFoundError(String),
+    #[error("Command error: {0}")]
+    CommandError(String),
+    #[error("Timeout error: {0}")]
+    TimeoutError(String),
+    #[error("Quorum error: {0}")]
+    QuorumError(String),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -34,10 +79,135 @@ pub struct IpAddress {
     pub last_updated: Option<SystemTime>,
 }
 
+/// IPv6 prefix, e.g. a prefix delegated by the ISP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Prefix {
+    pub address: Ipv6Addr,
+    pub length: u8,
+}
+
+impl Ipv6Prefix {
+    pub fn new(address: Ipv6Addr, length: u8) -> Result<Self, SourceError> {
+        if length > 128 {
+            return Err(SourceError::ValidationError(format!(
+                "Invalid IPv6 prefix length: {}",
+                length
+            )));
+        }
+
+        let mask = Self::mask(length);
+        Ok(Self {
+            address: Ipv6Addr::from(u128::from(address) & mask),
+            length,
+        })
+    }
+
+    fn mask(length: u8) -> u128 {
+        u128::MAX.checked_shl(128 - length as u32).unwrap_or(0)
+    }
+
+    /// Address made of this prefix followed by the host bits of `interface_id`
+    pub fn host_address(&self, interface_id: Ipv6Addr) -> Ipv6Addr {
+        let mask = Self::mask(self.length);
+        Ipv6Addr::from(u128::from(self.address) | (u128::from(interface_id) & !mask))
+    }
+}
+
+impl FromStr for Ipv6Prefix {
+    type Err = SourceError;
+
+    fn from_str(prefix: &str) -> Result<Self, Self::Err> {
+        let invalid = || SourceError::ValidationError(format!("Invalid IPv6 prefix: {}", prefix));
+        let (address, length) = prefix.split_once('/').ok_or_else(invalid)?;
+        Self::new(
+            address.parse().map_err(|_| invalid())?,
+            length.parse().map_err(|_| invalid())?,
+        )
+    }
+}
+
+impl fmt::Display for Ipv6Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.length)
+    }
+}
+
 #[async_trait]
 pub trait IpSource: Send + Sync {
     /// Get the current IP address for the specified version
     async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError>;
+
+    /// Get the IPv6 prefix delegated to the LAN, for records built from a
+    /// prefix and a static interface identifier
+    async fn get_ipv6_prefix(&mut self) -> Result<Ipv6Prefix, SourceError> {
+        Err(SourceError::ValidationError(format!(
+            "Source {} does not provide IPv6 prefixes",
+            self.name()
+        )))
+    }
+
+    /// Name of the source, used in logs
+    fn name(&self) -> &str;
+
+    /// Whether the last lookup went through a backup connection (e.g. 4G)
+    /// whose address is not reachable from the Internet
+    fn is_backup_connection(&self) -> bool {
+        false
+    }
+
+    /// Port range usable on the IPv4 address of the last lookup when it is
+    /// shared with other subscribers
+    fn ipv4_port_range(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    /// Details about the last lookup, exposed on the health endpoint
+    fn details(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// Build the IP source configured for a record, combining multiple sources
+/// with the configured strategy
+pub fn from_config(source: &Source) -> Result<Box<dyn IpSource>, SourceError> {
+    let mut sources = source
+        .providers()
+        .iter()
+        .map(build_source)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match sources.len() {
+        0 => Err(SourceError::ValidationError(
+            "No IP source configured".to_string(),
+        )),
+        1 => Ok(sources.remove(0)),
+        _ => Ok(Box::new(ChainSource::new(
+            sources,
+            source.strategy,
+            source.quorum,
+        )?)),
+    }
+}
+
+fn build_source(provider: &SourceProvider) -> Result<Box<dyn IpSource>, SourceError> {
+    Ok(match provider {
+        SourceProvider::Freebox(freebox) => Box::new(FreeboxSource::new(freebox)?),
+        SourceProvider::Http(http) => Box::new(HttpSource::new(http)?),
+        SourceProvider::Interface(interface) => Box::new(InterfaceSource::new(interface)?),
+        SourceProvider::Gateway(gateway) => Box::new(GatewaySource::new(gateway)?),
+        SourceProvider::Stun(stun) => Box::new(StunSource::new(stun)?),
+        SourceProvider::Dns(dns) => Box::new(DnsSource::new(dns)?),
+        SourceProvider::Exec(exec) => Box::new(ExecSource::new(exec)?),
+        SourceProvider::Livebox(livebox) => Box::new(LiveboxSource::new(livebox)?),
+        SourceProvider::Bbox(bbox) => Box::new(BboxSource::new(bbox)?),
+        SourceProvider::SfrBox(sfrbox) => Box::new(SfrBoxSource::new(sfrbox)?),
+        SourceProvider::FritzBox(fritzbox) => Box::new(FritzBoxSource::new(fritzbox)?),
+        SourceProvider::OpnSense(opnsense) => Box::new(OpnSenseSource::new(opnsense)?),
+        SourceProvider::PfSense(pfsense) => Box::new(PfSenseSource::new(pfsense)?),
+        SourceProvider::OpenWrt(openwrt) => Box::new(OpenWrtSource::new(openwrt)?),
+        SourceProvider::RouterOs(routeros) => Box::new(RouterOsSource::new(routeros)?),
+        SourceProvider::Unifi(unifi) => Box::new(UnifiSource::new(unifi)?),
+    })
 }
 
 /// Validate an IP address based on its version
@@ -61,3 +231,80 @@ pub fn validate_ip_address(ip: &str, version: &IpVersion) -> Result<(), SourceEr
     }
     Ok(())
 }
+
+/// Send a UDP request on a connected socket and wait for the first reply,
+/// retransmitting with an exponential backoff until the timeout elapses
+pub(crate) async fn udp_exchange(
+    socket: &UdpSocket,
+    request: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>, SourceError> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut wait = Duration::from_millis(250);
+    let mut buffer = vec![0u8; 4096];
+
+    loop {
+        socket
+            .send(request)
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        let attempt_deadline = std::cmp::min(tokio::time::Instant::now() + wait, deadline);
+        match tokio::time::timeout_at(attempt_deadline, socket.recv(&mut buffer)).await {
+            Ok(Ok(size)) => {
+                buffer.truncate(size);
+                return Ok(buffer);
+            }
+            Ok(Err(e)) => return Err(SourceError::NetworkError(e.to_string())),
+            Err(_) if attempt_deadline >= deadline => {
+                return Err(SourceError::NetworkError(format!(
+                    "No UDP response from {} after {:?}",
+                    socket
+                        .peer_addr()
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_default(),
+                    timeout
+                )));
+            }
+            Err(_) => wait *= 2,
+        }
+    }
+}
+
+/// Random identifier matching a response with our own request: DNS query ID,
+/// STUN transaction ID, PCP nonce or HTTP digest cnonce. It must not be
+/// guessable, or an off-path attacker could spoof the response and the
+/// published address.
+pub(crate) fn transaction_id() -> [u8; 12] {
+    let mut id = [0u8; 12];
+    getrandom::fill(&mut id).expect("the system random number generator is available");
+    id
+}
+
+/// HTTP client builder trusting only the certificates of `ca_file` when given,
+/// for routers serving their API with a self-signed certificate
+pub(crate) fn client_builder(ca_file: Option<&str>) -> Result<reqwest::ClientBuilder, SourceError> {
+    let mut builder = reqwest::Client::builder();
+
+    if let Some(ca_file) = ca_file {
+        let pem = std::fs::read(ca_file).map_err(|e| {
+            SourceError::ValidationError(format!("Failed to read CA file {}: {}", ca_file, e))
+        })?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| {
+            SourceError::ValidationError(format!("Invalid CA file {}: {}", ca_file, e))
+        })?;
+        if certificates.is_empty() {
+            return Err(SourceError::ValidationError(format!(
+                "No certificate found in CA file {}",
+                ca_file
+            )));
+        }
+
+        builder = builder.tls_built_in_root_certs(false);
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(builder)
+}
@@ -0,0 +1,240 @@
+use super::router::{RouterClient, RouterClientBuilder};
+use super::{IpAddress, IpSource, IpVersion, Ipv6Prefix, SourceError, validate_ip_address};
+use crate::config::OpenWrt;
+use async_trait::async_trait;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::json;
+use std::net::Ipv6Addr;
+use std::time::SystemTime;
+use tracing::debug;
+
+// session id used before logging in
+const UBUS_ANONYMOUS_SESSION: &str = "00000000000000000000000000000000";
+const UBUS_STATUS_NOT_FOUND: u64 = 4;
+const UBUS_STATUS_PERMISSION_DENIED: u64 = 6;
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcResponse {
+    /// `[status]` or `[status, data]`
+    #[serde(default)]
+    result: Vec<serde_json::Value>,
+    error: Option<JsonRpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UbusSession {
+    ubus_rpc_session: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenWrtInterfaceStatus {
+    up: bool,
+    #[serde(default, rename = "ipv4-address")]
+    ipv4_address: Vec<OpenWrtAddress>,
+    #[serde(default, rename = "ipv6-address")]
+    ipv6_address: Vec<OpenWrtAddress>,
+    #[serde(default, rename = "ipv6-prefix")]
+    ipv6_prefix: Vec<OpenWrtAddress>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenWrtAddress {
+    address: String,
+    mask: u8,
+}
+
+/// OpenWrt WAN addresses, read from the `network.interface` ubus objects
+/// over the rpcd JSON-RPC endpoint
+pub struct OpenWrtSource {
+    config: OpenWrt,
+    client: RouterClient,
+    name: String,
+    session: Option<String>,
+}
+
+impl OpenWrtSource {
+    pub fn new(config: &OpenWrt) -> Result<Self, SourceError> {
+        Ok(Self {
+            config: config.clone(),
+            client: RouterClientBuilder::new(config.timeout_in_seconds, config.retries)
+                .ca_file(config.ca_file.as_deref())
+                .build()?,
+            name: format!("openwrt ({})", config.url),
+            session: None,
+        })
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        session: &str,
+        object: &str,
+        method: &str,
+        arguments: serde_json::Value,
+    ) -> Result<T, SourceError> {
+        let request = self
+            .client
+            .post(format!("{}/ubus", self.config.url.trim_end_matches('/')))
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "call",
+                "params": [session, object, method, arguments],
+            }));
+        let response: JsonRpcResponse = self
+            .client
+            .send(request)
+            .await?
+            .json()
+            .await
+            .map_err(|e| SourceError::ApiError(format!("Invalid ubus response: {}", e)))?;
+
+        // -32002: the session is unknown, expired or not allowed to call the object
+        if let Some(error) = response.error {
+            let message = format!(
+                "ubus {}.{} failed: {} ({})",
+                object, method, error.message, error.code
+            );
+            return Err(if error.code == -32002 {
+                SourceError::AuthenticationError(message)
+            } else {
+                SourceError::ApiError(message)
+            });
+        }
+
+        let mut result = response.result.into_iter();
+        match (
+            result.next().and_then(|status| status.as_u64()),
+            result.next(),
+        ) {
+            (Some(0), Some(data)) => serde_json::from_value(data)
+                .map_err(|e| SourceError::ApiError(format!("Invalid ubus response: {}", e))),
+            (Some(UBUS_STATUS_PERMISSION_DENIED), _) => Err(SourceError::AuthenticationError(
+                format!("ubus {}.{}: permission denied", object, method),
+            )),
+            (Some(UBUS_STATUS_NOT_FOUND), _) => Err(SourceError::ValidationError(format!(
+                "ubus object {} not found",
+                object
+            ))),
+            (Some(status), _) => Err(SourceError::ApiError(format!(
+                "ubus {}.{} failed with status {}",
+                object, method, status
+            ))),
+            (None, _) => Err(SourceError::ApiError(
+                "ubus response has no result".to_string(),
+            )),
+        }
+    }
+
+    async fn login(&mut self) -> Result<String, SourceError> {
+        debug!("Opening a new ubus session on {}", self.config.url);
+        let session: UbusSession = self
+            .call(
+                UBUS_ANONYMOUS_SESSION,
+                "session",
+                "login",
+                json!({
+                    "username": self.config.username,
+                    "password": self.config.password,
+                }),
+            )
+            .await?;
+
+        self.session = Some(session.ubus_rpc_session.clone());
+        Ok(session.ubus_rpc_session)
+    }
+
+    /// Status of a logical interface, logging in again once when the session
+    /// is rejected
+    async fn get_interface_status(
+        &mut self,
+        interface: &str,
+    ) -> Result<OpenWrtInterfaceStatus, SourceError> {
+        let object = format!("network.interface.{}", interface);
+        let session = match self.session.clone() {
+            Some(session) => session,
+            None => self.login().await?,
+        };
+
+        let status: OpenWrtInterfaceStatus =
+            match self.call(&session, &object, "status", json!({})).await {
+                Err(SourceError::AuthenticationError(e)) => {
+                    debug!("ubus session rejected, logging in again: {}", e);
+                    let session = self.login().await?;
+                    self.call(&session, &object, "status", json!({})).await?
+                }
+                result => result?,
+            };
+
+        if !status.up {
+            return Err(SourceError::ConnectionError(format!(
+                "OpenWrt interface {} is down",
+                interface
+            )));
+        }
+        Ok(status)
+    }
+}
+
+#[async_trait]
+impl IpSource for OpenWrtSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let interface = match version {
+            IpVersion::IPv4 => self.config.interface.clone(),
+            IpVersion::IPv6 => self.config.ipv6_interface.clone(),
+        };
+        let status = self.get_interface_status(&interface).await?;
+
+        let ip = match version {
+            IpVersion::IPv4 => status.ipv4_address,
+            IpVersion::IPv6 => status.ipv6_address,
+        }
+        .into_iter()
+        .next()
+        .map(|address| address.address)
+        .ok_or_else(|| {
+            SourceError::IpNotFoundError(format!(
+                "No {:?} address on OpenWrt interface {}",
+                version, interface
+            ))
+        })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    async fn get_ipv6_prefix(&mut self) -> Result<Ipv6Prefix, SourceError> {
+        let interface = self.config.ipv6_interface.clone();
+        let prefix = self
+            .get_interface_status(&interface)
+            .await?
+            .ipv6_prefix
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                SourceError::IpNotFoundError(format!(
+                    "No IPv6 prefix delegated on OpenWrt interface {}",
+                    interface
+                ))
+            })?;
+
+        let address: Ipv6Addr = prefix.address.parse().map_err(|_| {
+            SourceError::ValidationError(format!("Invalid IPv6 prefix: {}", prefix.address))
+        })?;
+        Ipv6Prefix::new(address, prefix.mask)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -0,0 +1,159 @@
+use super::router::{RouterClient, RouterClientBuilder};
+use super::{IpAddress, IpSource, IpVersion, SourceError, validate_ip_address};
+use crate::config::OpnSense;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::{Deserialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::time::SystemTime;
+use tracing::debug;
+
+#[derive(Debug, Deserialize)]
+struct OpnSenseInterface {
+    #[serde(default)]
+    flags: Vec<String>,
+    #[serde(default)]
+    ipv4: Vec<OpnSenseAddress>,
+    #[serde(default)]
+    ipv6: Vec<OpnSenseAddress>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpnSenseAddress {
+    ipaddr: String,
+    #[serde(default, rename = "link-local")]
+    link_local: bool,
+    #[serde(default)]
+    deprecated: bool,
+    #[serde(default)]
+    tentative: bool,
+}
+
+/// OPNsense WAN addresses, read from the diagnostics API
+pub struct OpnSenseSource {
+    config: OpnSense,
+    client: RouterClient,
+    name: String,
+    device: Option<String>,
+}
+
+impl OpnSenseSource {
+    pub fn new(config: &OpnSense) -> Result<Self, SourceError> {
+        Ok(Self {
+            config: config.clone(),
+            client: RouterClientBuilder::new(config.timeout_in_seconds, config.retries)
+                .ca_file(config.ca_file.as_deref())
+                .build()?,
+            name: format!("opnsense ({})", config.url),
+            device: None,
+        })
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, SourceError> {
+        let request = self
+            .client
+            .get(format!(
+                "{}/api/diagnostics/interface/{}",
+                self.config.url.trim_end_matches('/'),
+                path
+            ))
+            .basic_auth(&self.config.api_key, Some(&self.config.api_secret));
+        let response = self.client.send(request).await?;
+
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err(SourceError::AuthenticationError(format!(
+                    "OPNsense rejected the API key for {} ({})",
+                    path,
+                    response.status()
+                )))
+            }
+            status if !status.is_success() => Err(SourceError::ApiError(format!(
+                "OPNsense {} failed: {}",
+                path, status
+            ))),
+            _ => response
+                .json()
+                .await
+                .map_err(|e| SourceError::ApiError(format!("Invalid OPNsense response: {}", e))),
+        }
+    }
+
+    /// Device of the configured interface, given either as a device name or
+    /// as the interface description
+    async fn get_interface(&mut self) -> Result<OpnSenseInterface, SourceError> {
+        let mut interfaces: HashMap<String, OpnSenseInterface> =
+            self.get("getInterfaceConfig").await?;
+
+        if self.device.is_none() {
+            let device = if interfaces.contains_key(&self.config.interface) {
+                self.config.interface.clone()
+            } else {
+                let names: HashMap<String, String> = self.get("getInterfaceNames").await?;
+                names
+                    .into_iter()
+                    .find(|(_, description)| {
+                        description.eq_ignore_ascii_case(&self.config.interface)
+                    })
+                    .map(|(device, _)| device)
+                    .ok_or_else(|| {
+                        SourceError::ValidationError(format!(
+                            "No OPNsense interface named {}",
+                            self.config.interface
+                        ))
+                    })?
+            };
+            debug!(
+                "OPNsense interface {} is device {}",
+                self.config.interface, device
+            );
+            self.device = Some(device);
+        }
+
+        let device = self.device.as_deref().unwrap_or_default();
+        interfaces
+            .remove(device)
+            .ok_or_else(|| SourceError::ApiError(format!("OPNsense device {} not found", device)))
+    }
+}
+
+#[async_trait]
+impl IpSource for OpnSenseSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let interface = self.get_interface().await?;
+
+        if !interface.flags.iter().any(|flag| flag == "up") {
+            return Err(SourceError::ConnectionError(format!(
+                "OPNsense interface {} is down",
+                self.config.interface
+            )));
+        }
+
+        let addresses = match version {
+            IpVersion::IPv4 => interface.ipv4,
+            IpVersion::IPv6 => interface.ipv6,
+        };
+        let ip = addresses
+            .into_iter()
+            .find(|address| !address.link_local && !address.deprecated && !address.tentative)
+            .map(|address| address.ipaddr)
+            .ok_or_else(|| {
+                SourceError::IpNotFoundError(format!(
+                    "No {:?} address on OPNsense interface {}",
+                    version, self.config.interface
+                ))
+            })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -0,0 +1,134 @@
+use super::router::{RouterClient, RouterClientBuilder};
+use super::{IpAddress, IpSource, IpVersion, SourceError, validate_ip_address};
+use crate::config::PfSense;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::time::SystemTime;
+
+#[derive(Debug, Deserialize)]
+struct PfSenseResponse<T> {
+    message: Option<String>,
+    response_id: Option<String>,
+    data: Option<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PfSenseInterface {
+    name: Option<String>,
+    descr: Option<String>,
+    hwif: Option<String>,
+    status: Option<String>,
+    ipaddr: Option<String>,
+    ipaddrv6: Option<String>,
+}
+
+impl PfSenseInterface {
+    fn matches(&self, interface: &str) -> bool {
+        [&self.name, &self.descr, &self.hwif]
+            .into_iter()
+            .flatten()
+            .any(|name| name.eq_ignore_ascii_case(interface))
+    }
+}
+
+/// pfSense WAN addresses, read from the REST API package
+pub struct PfSenseSource {
+    config: PfSense,
+    client: RouterClient,
+    name: String,
+}
+
+impl PfSenseSource {
+    pub fn new(config: &PfSense) -> Result<Self, SourceError> {
+        Ok(Self {
+            config: config.clone(),
+            client: RouterClientBuilder::new(config.timeout_in_seconds, config.retries)
+                .ca_file(config.ca_file.as_deref())
+                .build()?,
+            name: format!("pfsense ({})", config.url),
+        })
+    }
+
+    async fn get_interface(&self) -> Result<PfSenseInterface, SourceError> {
+        let request = self
+            .client
+            .get(format!(
+                "{}/api/v2/status/interfaces",
+                self.config.url.trim_end_matches('/')
+            ))
+            .header("X-API-Key", &self.config.api_key);
+        let response = self.client.send(request).await?;
+        let status = response.status();
+
+        // errors come in the same envelope, with an empty `data`
+        let response: PfSenseResponse<Vec<PfSenseInterface>> =
+            response.json().await.map_err(|e| {
+                SourceError::ApiError(format!("Invalid pfSense response ({}): {}", status, e))
+            })?;
+        if !status.is_success() {
+            let message = format!(
+                "pfSense API error ({}): {}",
+                response.response_id.as_deref().unwrap_or(status.as_str()),
+                response.message.as_deref().unwrap_or_default()
+            );
+            return Err(match status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                    SourceError::AuthenticationError(message)
+                }
+                _ => SourceError::ApiError(message),
+            });
+        }
+
+        response
+            .data
+            .into_iter()
+            .flatten()
+            .find(|interface| interface.matches(&self.config.interface))
+            .ok_or_else(|| {
+                SourceError::ValidationError(format!(
+                    "No pfSense interface named {}",
+                    self.config.interface
+                ))
+            })
+    }
+}
+
+#[async_trait]
+impl IpSource for PfSenseSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let interface = self.get_interface().await?;
+
+        if interface.status.as_deref() != Some("up") {
+            return Err(SourceError::ConnectionError(format!(
+                "pfSense interface {} is not up (status: {})",
+                self.config.interface,
+                interface.status.as_deref().unwrap_or("unknown")
+            )));
+        }
+
+        let ip = match version {
+            IpVersion::IPv4 => interface.ipaddr,
+            IpVersion::IPv6 => interface.ipaddrv6,
+        }
+        .filter(|ip| !ip.is_empty())
+        .ok_or_else(|| {
+            SourceError::IpNotFoundError(format!(
+                "No {:?} address on pfSense interface {}",
+                version, self.config.interface
+            ))
+        })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
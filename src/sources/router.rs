@@ -0,0 +1,181 @@
+use super::{SourceError, client_builder};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use std::time::Duration;
+use tracing::debug;
+
+// delay before the first retry, doubled after each attempt
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Settings of the HTTP client of a router source
+pub(crate) struct RouterClientBuilder {
+    ca_file: Option<String>,
+    timeout: Duration,
+    retries: u32,
+    cookies: bool,
+}
+
+impl RouterClientBuilder {
+    pub(crate) fn new(timeout: Duration, retries: u32) -> Self {
+        Self {
+            ca_file: None,
+            timeout,
+            retries,
+            cookies: false,
+        }
+    }
+
+    /// Trust only the certificates of `ca_file` when given
+    pub(crate) fn ca_file(mut self, ca_file: Option<&str>) -> Self {
+        self.ca_file = ca_file.map(str::to_string);
+        self
+    }
+
+    /// Keep the cookies set by the router, for APIs with a cookie session
+    pub(crate) fn cookies(mut self) -> Self {
+        self.cookies = true;
+        self
+    }
+
+    pub(crate) fn build(self) -> Result<RouterClient, SourceError> {
+        let client = client_builder(self.ca_file.as_deref())?
+            .cookie_store(self.cookies)
+            .build()
+            .map_err(|e| {
+                SourceError::NetworkError(format!("Failed to create HTTP client: {}", e))
+            })?;
+
+        Ok(RouterClient {
+            client,
+            timeout: self.timeout,
+            retries: self.retries,
+        })
+    }
+}
+
+/// HTTP client of a router API, applying the request timeout and retrying
+/// requests failing on the network or with a gateway error. Only GET
+/// requests are sent again, other ones only when the connection failed
+/// before they were sent, as a login or an API call may not be repeatable.
+pub(crate) struct RouterClient {
+    client: Client,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl RouterClient {
+    pub(crate) fn get(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.client.get(url.as_ref()).timeout(self.timeout)
+    }
+
+    pub(crate) fn post(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.client.post(url.as_ref()).timeout(self.timeout)
+    }
+
+    pub(crate) async fn send(&self, request: RequestBuilder) -> Result<Response, SourceError> {
+        let (client, request) = request.build_split();
+        let request = request.map_err(Self::error)?;
+        let idempotent = request.method() == Method::GET;
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 0;
+
+        loop {
+            // only streamed bodies can't be cloned, and they are never used here
+            let Some(attempt_request) = request.try_clone() else {
+                return client.execute(request).await.map_err(Self::error);
+            };
+            let result = client.execute(attempt_request).await;
+
+            let retryable = match &result {
+                Ok(response) => {
+                    idempotent
+                        && matches!(
+                            response.status(),
+                            StatusCode::BAD_GATEWAY
+                                | StatusCode::SERVICE_UNAVAILABLE
+                                | StatusCode::GATEWAY_TIMEOUT
+                        )
+                }
+                Err(e) => e.is_connect() || (idempotent && (e.is_timeout() || e.is_request())),
+            };
+            if !retryable || attempt >= self.retries {
+                return result.map_err(Self::error);
+            }
+
+            attempt += 1;
+            debug!(
+                "Router request failed ({}), retry {}/{} in {:?}",
+                match &result {
+                    Ok(response) => response.status().to_string(),
+                    Err(e) => e.to_string(),
+                },
+                attempt,
+                self.retries,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    fn error(e: reqwest::Error) -> SourceError {
+        if e.is_timeout() {
+            SourceError::TimeoutError(e.to_string())
+        } else {
+            SourceError::NetworkError(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::any};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Server answering every request with a gateway error, counting them
+    async fn start_unavailable() -> (Arc<AtomicUsize>, String) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = Router::new().route(
+            "/",
+            any(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { StatusCode::SERVICE_UNAVAILABLE }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (requests, url)
+    }
+
+    fn client() -> RouterClient {
+        RouterClientBuilder::new(Duration::from_secs(1), 2)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn get_is_retried_on_gateway_error() {
+        let (requests, url) = start_unavailable().await;
+        let client = client();
+
+        let response = client.send(client.get(&url)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn post_is_not_retried_once_sent() {
+        let (requests, url) = start_unavailable().await;
+        let client = client();
+
+        let response = client.send(client.post(&url).body("login")).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
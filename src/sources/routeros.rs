@@ -0,0 +1,202 @@
+use super::router::{RouterClient, RouterClientBuilder};
+use super::{IpAddress, IpSource, IpVersion, Ipv6Prefix, SourceError, validate_ip_address};
+use crate::config::RouterOs;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::{Deserialize, de::DeserializeOwned};
+use std::time::SystemTime;
+
+#[derive(Debug, Deserialize)]
+struct RouterOsError {
+    message: Option<String>,
+    detail: Option<String>,
+}
+
+// RouterOS returns every property as a string, booleans included
+#[derive(Debug, Deserialize)]
+struct RouterOsAddress {
+    address: String,
+    disabled: Option<String>,
+    invalid: Option<String>,
+    #[serde(rename = "link-local")]
+    link_local: Option<String>,
+}
+
+impl RouterOsAddress {
+    fn is_usable(&self) -> bool {
+        [&self.disabled, &self.invalid, &self.link_local]
+            .iter()
+            .all(|flag| flag.as_deref() != Some("true"))
+    }
+
+    /// Address without its prefix length
+    fn ip(&self) -> String {
+        self.address
+            .split_once('/')
+            .map_or(self.address.as_str(), |(ip, _)| ip)
+            .to_string()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RouterOsDhcpClient {
+    status: Option<String>,
+    disabled: Option<String>,
+    address: Option<String>,
+    /// `2001:db8:100::/56, 2d23h59m50s` for DHCPv6 clients
+    prefix: Option<String>,
+}
+
+/// MikroTik RouterOS WAN addresses, read from the REST API (RouterOS 7+)
+pub struct RouterOsSource {
+    config: RouterOs,
+    client: RouterClient,
+    name: String,
+}
+
+impl RouterOsSource {
+    pub fn new(config: &RouterOs) -> Result<Self, SourceError> {
+        Ok(Self {
+            config: config.clone(),
+            client: RouterClientBuilder::new(config.timeout_in_seconds, config.retries)
+                .ca_file(config.ca_file.as_deref())
+                .build()?,
+            name: format!("routeros ({})", config.url),
+        })
+    }
+
+    /// Entries of a menu (e.g. `ip/address`) on the configured interface
+    async fn get<T: DeserializeOwned>(&self, menu: &str) -> Result<Vec<T>, SourceError> {
+        let request = self
+            .client
+            .get(format!(
+                "{}/rest/{}",
+                self.config.url.trim_end_matches('/'),
+                menu
+            ))
+            .query(&[("interface", &self.config.interface)])
+            .basic_auth(&self.config.username, Some(&self.config.password));
+        let response = self.client.send(request).await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            // {"error":400,"message":"Bad Request","detail":"no such command"}
+            let error: Option<RouterOsError> = response.json().await.ok();
+            let message = format!(
+                "RouterOS {} failed ({}): {}",
+                menu,
+                status,
+                error
+                    .and_then(|error| error.detail.or(error.message))
+                    .unwrap_or_default()
+            );
+            return Err(match status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                    SourceError::AuthenticationError(message)
+                }
+                _ => SourceError::ApiError(message),
+            });
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| SourceError::ApiError(format!("Invalid RouterOS response: {}", e)))
+    }
+
+    /// Bound DHCP client of the interface, `None` when it has none
+    async fn get_dhcp_client(&self, menu: &str) -> Result<Option<RouterOsDhcpClient>, SourceError> {
+        let Some(client) = self
+            .get::<RouterOsDhcpClient>(menu)
+            .await?
+            .into_iter()
+            .find(|client| client.disabled.as_deref() != Some("true"))
+        else {
+            return Ok(None);
+        };
+
+        if client.status.as_deref() != Some("bound") {
+            return Err(SourceError::ConnectionError(format!(
+                "RouterOS {} on {} is not bound (status: {})",
+                menu,
+                self.config.interface,
+                client.status.as_deref().unwrap_or("unknown")
+            )));
+        }
+        Ok(Some(client))
+    }
+
+    async fn get_ipv4(&self) -> Result<Option<String>, SourceError> {
+        // a DHCP client holds the WAN lease, otherwise the address is static
+        // or comes from a PPPoE interface
+        if let Some(address) = self
+            .get_dhcp_client("ip/dhcp-client")
+            .await?
+            .and_then(|client| client.address)
+        {
+            return Ok(Some(
+                address.split('/').next().unwrap_or_default().to_string(),
+            ));
+        }
+
+        Ok(self
+            .get::<RouterOsAddress>("ip/address")
+            .await?
+            .iter()
+            .find(|address| address.is_usable())
+            .map(RouterOsAddress::ip))
+    }
+}
+
+#[async_trait]
+impl IpSource for RouterOsSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let ip = match version {
+            IpVersion::IPv4 => self.get_ipv4().await?,
+            IpVersion::IPv6 => self
+                .get::<RouterOsAddress>("ipv6/address")
+                .await?
+                .iter()
+                .find(|address| address.is_usable())
+                .map(RouterOsAddress::ip),
+        }
+        .ok_or_else(|| {
+            SourceError::IpNotFoundError(format!(
+                "No {:?} address on RouterOS interface {}",
+                version, self.config.interface
+            ))
+        })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    async fn get_ipv6_prefix(&mut self) -> Result<Ipv6Prefix, SourceError> {
+        self.get_dhcp_client("ipv6/dhcp-client")
+            .await?
+            .and_then(|client| client.prefix)
+            .and_then(|prefix| {
+                prefix
+                    .split(',')
+                    .next()
+                    .map(|prefix| prefix.trim().to_string())
+            })
+            .filter(|prefix| !prefix.is_empty())
+            .ok_or_else(|| {
+                SourceError::IpNotFoundError(format!(
+                    "No IPv6 prefix delegated on RouterOS interface {}",
+                    self.config.interface
+                ))
+            })?
+            .parse()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -0,0 +1,127 @@
+use super::router::{RouterClient, RouterClientBuilder};
+use super::{IpAddress, IpSource, IpVersion, SourceError, validate_ip_address};
+use crate::config::SfrBox;
+use async_trait::async_trait;
+use std::time::SystemTime;
+
+// API error codes reported when a method needs an authenticated token
+const SFRBOX_AUTH_ERROR_CODES: [&str; 2] = ["115", "204"];
+
+/// SFR box (NB4, NB6...) WAN addresses, read from its XML API
+pub struct SfrBoxSource {
+    config: SfrBox,
+    client: RouterClient,
+    name: String,
+}
+
+impl SfrBoxSource {
+    pub fn new(config: &SfrBox) -> Result<Self, SourceError> {
+        Ok(Self {
+            config: config.clone(),
+            client: RouterClientBuilder::new(config.timeout_in_seconds, config.retries)
+                .ca_file(config.ca_file.as_deref())
+                .build()?,
+            name: format!("sfrbox ({})", config.url),
+        })
+    }
+
+    async fn call(&self, method: &str) -> Result<String, SourceError> {
+        let response = self
+            .client
+            .send(self.client.get(format!(
+                "{}/api/1.0/?method={}",
+                self.config.url.trim_end_matches('/'),
+                method
+            )))
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(SourceError::ApiError(format!(
+                "SFR box API error ({})",
+                response.status()
+            )));
+        }
+
+        response
+            .text()
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))
+    }
+}
+
+/// Attributes of the `<wan>` element of a `wan.getInfo` response
+fn parse_wan_info(xml: &str) -> Result<(String, Option<String>, Option<String>), SourceError> {
+    let document = roxmltree::Document::parse(xml)
+        .map_err(|e| SourceError::ApiError(format!("Invalid SFR box response: {}", e)))?;
+    let rsp = document.root_element();
+
+    // <rsp stat="fail"><err code="115" msg="Authentication needed"/></rsp>
+    if rsp.attribute("stat") != Some("ok") {
+        let error = rsp.children().find(|node| node.has_tag_name("err"));
+        let code = error
+            .and_then(|error| error.attribute("code"))
+            .unwrap_or_default();
+        let message = format!(
+            "SFR box API failure: {} (code {})",
+            error
+                .and_then(|error| error.attribute("msg"))
+                .unwrap_or("Unknown error"),
+            code
+        );
+        return Err(if SFRBOX_AUTH_ERROR_CODES.contains(&code) {
+            SourceError::AuthenticationError(message)
+        } else {
+            SourceError::ApiError(message)
+        });
+    }
+
+    let wan = rsp
+        .children()
+        .find(|node| node.has_tag_name("wan"))
+        .ok_or_else(|| SourceError::ApiError("SFR box response has no wan".to_string()))?;
+    let attribute = |name: &str| {
+        wan.attribute(name)
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    };
+
+    Ok((
+        attribute("status").unwrap_or_default(),
+        attribute("ip_addr"),
+        attribute("ipv6_addr"),
+    ))
+}
+
+#[async_trait]
+impl IpSource for SfrBoxSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let (status, ipv4, ipv6) = parse_wan_info(&self.call("wan.getInfo").await?)?;
+
+        if status != "up" {
+            return Err(SourceError::ConnectionError(format!(
+                "SFR box WAN is not up (status: {})",
+                status
+            )));
+        }
+
+        let ip = match version {
+            IpVersion::IPv4 => ipv4,
+            IpVersion::IPv6 => ipv6,
+        }
+        .ok_or_else(|| {
+            SourceError::IpNotFoundError(format!("No {:?} address on the SFR box WAN", version))
+        })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
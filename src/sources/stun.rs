@@ -0,0 +1,371 @@
+use super::{
+    IpAddress, IpSource, IpVersion, SourceError, transaction_id, udp_exchange, validate_ip_address,
+};
+use crate::config::Stun;
+use async_trait::async_trait;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::SystemTime;
+use tokio::net::UdpSocket;
+use tracing::warn;
+
+// RFC 5389 constants
+const STUN_BINDING_REQUEST: u16 = 0x0001;
+const STUN_BINDING_SUCCESS: u16 = 0x0101;
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+const STUN_HEADER_LENGTH: usize = 20;
+const STUN_ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const STUN_ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const STUN_FAMILY_IPV4: u8 = 0x01;
+const STUN_FAMILY_IPV6: u8 = 0x02;
+
+pub struct StunSource {
+    config: Stun,
+}
+
+impl StunSource {
+    pub fn new(config: &Stun) -> Result<Self, SourceError> {
+        if config.servers.is_empty() {
+            return Err(SourceError::ValidationError(
+                "At least one STUN server is required".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            config: config.clone(),
+        })
+    }
+
+    async fn resolve(server: &str, version: IpVersion) -> Result<SocketAddr, SourceError> {
+        tokio::net::lookup_host(server)
+            .await
+            .map_err(|e| SourceError::NetworkError(format!("Failed to resolve {}: {}", server, e)))?
+            .find(|address| match version {
+                IpVersion::IPv4 => address.is_ipv4(),
+                IpVersion::IPv6 => address.is_ipv6(),
+            })
+            .ok_or_else(|| {
+                SourceError::NetworkError(format!("No {:?} address for {}", version, server))
+            })
+    }
+
+    async fn query(&self, server: &str, version: IpVersion) -> Result<IpAddr, SourceError> {
+        let address = Self::resolve(server, version).await?;
+        let bind_address = match version {
+            IpVersion::IPv4 => "0.0.0.0:0",
+            IpVersion::IPv6 => "[::]:0",
+        };
+
+        let socket = UdpSocket::bind(bind_address)
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+        socket
+            .connect(address)
+            .await
+            .map_err(|e| SourceError::NetworkError(e.to_string()))?;
+
+        let id = transaction_id();
+        let mut request = Vec::with_capacity(STUN_HEADER_LENGTH);
+        request.extend_from_slice(&STUN_BINDING_REQUEST.to_be_bytes());
+        request.extend_from_slice(&0u16.to_be_bytes());
+        request.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        request.extend_from_slice(&id);
+
+        let response = udp_exchange(&socket, &request, self.config.timeout_in_seconds).await?;
+        parse_binding_response(&response, &id)
+    }
+}
+
+/// Read the mapped address from a STUN Binding success response
+fn parse_binding_response(response: &[u8], id: &[u8; 12]) -> Result<IpAddr, SourceError> {
+    if response.len() < STUN_HEADER_LENGTH {
+        return Err(SourceError::ApiError("STUN response too short".to_string()));
+    }
+
+    let message_type = u16::from_be_bytes([response[0], response[1]]);
+    if message_type != STUN_BINDING_SUCCESS {
+        return Err(SourceError::ApiError(format!(
+            "Unexpected STUN message type: {:#06x}",
+            message_type
+        )));
+    }
+    if response[4..8] != STUN_MAGIC_COOKIE.to_be_bytes() || response[8..20] != id[..] {
+        return Err(SourceError::ApiError(
+            "STUN response transaction mismatch".to_string(),
+        ));
+    }
+
+    let length = u16::from_be_bytes([response[2], response[3]]) as usize;
+    let attributes = response
+        .get(STUN_HEADER_LENGTH..STUN_HEADER_LENGTH + length)
+        .ok_or_else(|| SourceError::ApiError("Truncated STUN response".to_string()))?;
+
+    let mut mapped_address = None;
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let kind = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let size = u16::from_be_bytes([attributes[offset + 2], attributes[offset + 3]]) as usize;
+        let value = attributes
+            .get(offset + 4..offset + 4 + size)
+            .ok_or_else(|| SourceError::ApiError("Truncated STUN attribute".to_string()))?;
+
+        match kind {
+            STUN_ATTR_XOR_MAPPED_ADDRESS => return decode_address(value, Some(id)),
+            STUN_ATTR_MAPPED_ADDRESS => mapped_address = Some(decode_address(value, None)?),
+            _ => {}
+        }
+
+        // attributes are padded to a multiple of 4 bytes
+        offset += 4 + size.div_ceil(4) * 4;
+    }
+
+    mapped_address.ok_or_else(|| {
+        SourceError::IpNotFoundError("No mapped address in STUN response".to_string())
+    })
+}
+
+/// Decode a (XOR-)MAPPED-ADDRESS attribute, `id` is set for the XOR variant
+fn decode_address(value: &[u8], id: Option<&[u8; 12]>) -> Result<IpAddr, SourceError> {
+    let mut mask = [0u8; 16];
+    if let Some(id) = id {
+        mask[..4].copy_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(id);
+    }
+
+    match (value.get(1), value.len()) {
+        (Some(&STUN_FAMILY_IPV4), 8) => {
+            let mut octets = [0u8; 4];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + i] ^ mask[i];
+            }
+            Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        (Some(&STUN_FAMILY_IPV6), 20) => {
+            let mut octets = [0u8; 16];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + i] ^ mask[i];
+            }
+            Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => Err(SourceError::ApiError(
+            "Invalid STUN address attribute".to_string(),
+        )),
+    }
+}
+
+#[async_trait]
+impl IpSource for StunSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let mut last_error = None;
+
+        for server in &self.config.servers {
+            match self.query(server, version).await {
+                Ok(ip) => {
+                    let ip = ip.to_string();
+                    validate_ip_address(&ip, &version)?;
+
+                    return Ok(IpAddress {
+                        version,
+                        address: ip,
+                        last_updated: Some(SystemTime::now()),
+                    });
+                }
+                Err(e) => {
+                    warn!("STUN server {} failed: {}", server, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            SourceError::ConnectionError("No STUN server configured".to_string())
+        }))
+    }
+
+    fn name(&self) -> &str {
+        "stun"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: [u8; 12] = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    fn binding_response(id: &[u8; 12], attributes: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (kind, value) in attributes {
+            body.extend_from_slice(&kind.to_be_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(value);
+            body.resize(body.len().div_ceil(4) * 4, 0);
+        }
+
+        let mut response = Vec::new();
+        response.extend_from_slice(&STUN_BINDING_SUCCESS.to_be_bytes());
+        response.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        response.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        response.extend_from_slice(id);
+        response.extend_from_slice(&body);
+        response
+    }
+
+    /// (XOR-)MAPPED-ADDRESS value, XORed with the cookie and `id` when set
+    fn address_value(address: SocketAddr, id: Option<&[u8; 12]>) -> Vec<u8> {
+        let mut mask = [0u8; 16];
+        if let Some(id) = id {
+            mask[..4].copy_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+            mask[4..].copy_from_slice(id);
+        }
+        let (family, octets) = match address.ip() {
+            IpAddr::V4(ip) => (STUN_FAMILY_IPV4, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (STUN_FAMILY_IPV6, ip.octets().to_vec()),
+        };
+
+        let mut value = vec![0, family];
+        value.extend_from_slice(
+            &(address.port() ^ u16::from_be_bytes([mask[0], mask[1]])).to_be_bytes(),
+        );
+        value.extend(octets.iter().zip(mask).map(|(octet, mask)| octet ^ mask));
+        value
+    }
+
+    #[test]
+    fn xor_mapped_ipv4_address() {
+        let address: SocketAddr = "192.0.2.1:32853".parse().unwrap();
+        let response = binding_response(
+            &ID,
+            &[
+                (0x8022, b"test vector".to_vec()),
+                (
+                    STUN_ATTR_XOR_MAPPED_ADDRESS,
+                    address_value(address, Some(&ID)),
+                ),
+            ],
+        );
+
+        assert_eq!(
+            parse_binding_response(&response, &ID).unwrap(),
+            address.ip()
+        );
+    }
+
+    #[test]
+    fn xor_mapped_ipv6_address() {
+        let address: SocketAddr = "[2001:db8:1234:5678:11:2233:4455:6677]:32853"
+            .parse()
+            .unwrap();
+        let response = binding_response(
+            &ID,
+            &[(
+                STUN_ATTR_XOR_MAPPED_ADDRESS,
+                address_value(address, Some(&ID)),
+            )],
+        );
+
+        assert_eq!(
+            parse_binding_response(&response, &ID).unwrap(),
+            address.ip()
+        );
+    }
+
+    #[test]
+    fn xor_mapped_address_is_preferred() {
+        let mapped: SocketAddr = "198.51.100.1:3478".parse().unwrap();
+        let xor_mapped: SocketAddr = "192.0.2.1:3478".parse().unwrap();
+        let response = binding_response(
+            &ID,
+            &[
+                (STUN_ATTR_MAPPED_ADDRESS, address_value(mapped, None)),
+                (
+                    STUN_ATTR_XOR_MAPPED_ADDRESS,
+                    address_value(xor_mapped, Some(&ID)),
+                ),
+            ],
+        );
+
+        assert_eq!(
+            parse_binding_response(&response, &ID).unwrap(),
+            xor_mapped.ip()
+        );
+    }
+
+    #[test]
+    fn wrong_transaction_id_is_rejected() {
+        let address: SocketAddr = "192.0.2.1:32853".parse().unwrap();
+        let mut other_id = ID;
+        other_id[11] ^= 0xff;
+        let response = binding_response(
+            &other_id,
+            &[(
+                STUN_ATTR_XOR_MAPPED_ADDRESS,
+                address_value(address, Some(&other_id)),
+            )],
+        );
+
+        assert!(matches!(
+            parse_binding_response(&response, &ID),
+            Err(SourceError::ApiError(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_attribute_is_rejected() {
+        let address: SocketAddr = "192.0.2.1:32853".parse().unwrap();
+        let mut response = binding_response(
+            &ID,
+            &[(
+                STUN_ATTR_XOR_MAPPED_ADDRESS,
+                address_value(address, Some(&ID)),
+            )],
+        );
+        // the attribute announces 8 bytes but only 4 are in the message
+        response.truncate(response.len() - 4);
+        response[2..4].copy_from_slice(&8u16.to_be_bytes());
+
+        assert!(matches!(
+            parse_binding_response(&response, &ID),
+            Err(SourceError::ApiError(_))
+        ));
+    }
+
+    /// STUN server answering the sender address, or with another transaction
+    /// ID when `mismatch` is set
+    async fn start_responder(mismatch: bool) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = socket.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            while let Ok((size, peer)) = socket.recv_from(&mut buffer).await {
+                let Some(Ok(mut id)) = buffer[..size].get(8..20).map(<[u8; 12]>::try_from) else {
+                    continue;
+                };
+                if mismatch {
+                    id[0] ^= 0xff;
+                }
+                let response = binding_response(
+                    &id,
+                    &[(STUN_ATTR_XOR_MAPPED_ADDRESS, address_value(peer, Some(&id)))],
+                );
+                let _ = socket.send_to(&response, peer).await;
+            }
+        });
+        server
+    }
+
+    #[tokio::test]
+    async fn next_server_is_tried_on_invalid_response() {
+        let servers = vec![start_responder(true).await, start_responder(false).await];
+        let mut source = StunSource::new(&Stun {
+            servers,
+            timeout_in_seconds: std::time::Duration::from_secs(2),
+        })
+        .unwrap();
+
+        let ip = source.get_ip(IpVersion::IPv4).await.unwrap();
+
+        assert_eq!(ip.address, "127.0.0.1");
+    }
+}
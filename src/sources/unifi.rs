@@ -0,0 +1,241 @@
+use super::router::{RouterClient, RouterClientBuilder};
+use super::{IpAddress, IpSource, IpVersion, SourceError, validate_ip_address};
+use crate::config::Unifi;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::json;
+use std::net::Ipv6Addr;
+use std::time::SystemTime;
+use tracing::debug;
+
+// device types of UniFi gateways (USG, Dream Machines, Gateway Max/Ultra...)
+const UNIFI_GATEWAY_TYPES: [&str; 3] = ["ugw", "udm", "uxg"];
+
+#[derive(Debug, Deserialize)]
+struct UnifiResponse<T> {
+    meta: Option<UnifiMeta>,
+    #[serde(default = "Vec::new")]
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnifiMeta {
+    rc: String,
+    msg: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnifiHealth {
+    subsystem: String,
+    status: Option<String>,
+    wan_ip: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnifiDevice {
+    #[serde(rename = "type")]
+    device_type: Option<String>,
+    wan1: Option<UnifiWan>,
+    wan2: Option<UnifiWan>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnifiWan {
+    #[serde(default)]
+    up: bool,
+    #[serde(default)]
+    ipv6: Vec<String>,
+}
+
+/// UniFi gateway WAN addresses, read from the UniFi Network controller
+pub struct UnifiSource {
+    config: Unifi,
+    client: RouterClient,
+    name: String,
+    logged_in: bool,
+}
+
+impl UnifiSource {
+    pub fn new(config: &Unifi) -> Result<Self, SourceError> {
+        Ok(Self {
+            config: config.clone(),
+            // the session is kept in the cookie set by the login
+            client: RouterClientBuilder::new(config.timeout_in_seconds, config.retries)
+                .ca_file(config.ca_file.as_deref())
+                .cookies()
+                .build()?,
+            name: format!("unifi ({})", config.url),
+            logged_in: false,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        // UniFi OS consoles serve the Network application behind a proxy
+        format!(
+            "{}{}{}",
+            self.config.url.trim_end_matches('/'),
+            if self.config.unifi_os {
+                "/proxy/network"
+            } else {
+                ""
+            },
+            path
+        )
+    }
+
+    async fn login(&mut self) -> Result<(), SourceError> {
+        debug!("Logging in to the UniFi controller {}", self.config.url);
+        let path = if self.config.unifi_os {
+            "/api/auth/login"
+        } else {
+            "/api/login"
+        };
+        let request = self
+            .client
+            .post(format!("{}{}", self.config.url.trim_end_matches('/'), path))
+            .json(&json!({
+                "username": self.config.username,
+                "password": self.config.password,
+            }));
+        let response = self.client.send(request).await?;
+
+        if !response.status().is_success() {
+            return Err(SourceError::AuthenticationError(format!(
+                "UniFi controller login failed ({})",
+                response.status()
+            )));
+        }
+
+        self.logged_in = true;
+        Ok(())
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, endpoint: &str) -> Result<Vec<T>, SourceError> {
+        let response = self
+            .client
+            .send(
+                self.client
+                    .get(self.url(&format!("/api/s/{}/{}", self.config.site, endpoint))),
+            )
+            .await?;
+        let status = response.status();
+
+        // {"meta":{"rc":"error","msg":"api.err.LoginRequired"},"data":[]}
+        let response: Option<UnifiResponse<T>> = response.json().await.ok();
+        let error = response
+            .as_ref()
+            .and_then(|response| response.meta.as_ref())
+            .filter(|meta| meta.rc != "ok")
+            .map(|meta| meta.msg.clone().unwrap_or_else(|| meta.rc.clone()));
+
+        if status == StatusCode::UNAUTHORIZED
+            || error
+                .as_deref()
+                .is_some_and(|error| error == "api.err.LoginRequired")
+        {
+            return Err(SourceError::AuthenticationError(format!(
+                "UniFi controller rejected {} ({})",
+                endpoint, status
+            )));
+        }
+        if !status.is_success() || error.is_some() {
+            return Err(SourceError::ApiError(format!(
+                "UniFi {} failed ({}): {}",
+                endpoint,
+                status,
+                error.unwrap_or_default()
+            )));
+        }
+
+        response
+            .map(|response| response.data)
+            .ok_or_else(|| SourceError::ApiError(format!("Invalid UniFi {} response", endpoint)))
+    }
+
+    /// Call a site endpoint, logging in first and again once when the
+    /// session is rejected
+    async fn get<T: DeserializeOwned>(&mut self, endpoint: &str) -> Result<Vec<T>, SourceError> {
+        if !self.logged_in {
+            self.login().await?;
+        }
+
+        match self.fetch(endpoint).await {
+            Err(SourceError::AuthenticationError(e)) => {
+                debug!("UniFi session rejected, logging in again: {}", e);
+                self.login().await?;
+                self.fetch(endpoint).await
+            }
+            result => result,
+        }
+    }
+
+    async fn get_ipv4(&mut self) -> Result<Option<String>, SourceError> {
+        let health: Vec<UnifiHealth> = self.get("stat/health").await?;
+        let wan = health
+            .into_iter()
+            .find(|health| health.subsystem == "wan")
+            .ok_or_else(|| {
+                SourceError::ApiError("No WAN in UniFi controller health".to_string())
+            })?;
+
+        if wan.status.as_deref() != Some("ok") {
+            return Err(SourceError::ConnectionError(format!(
+                "UniFi WAN is not healthy (status: {})",
+                wan.status.as_deref().unwrap_or("unknown")
+            )));
+        }
+        Ok(wan.wan_ip.filter(|ip| !ip.is_empty()))
+    }
+
+    async fn get_ipv6(&mut self) -> Result<Option<String>, SourceError> {
+        let devices: Vec<UnifiDevice> = self.get("stat/device").await?;
+        let gateway = devices
+            .into_iter()
+            .find(|device| {
+                device
+                    .device_type
+                    .as_deref()
+                    .is_some_and(|device_type| UNIFI_GATEWAY_TYPES.contains(&device_type))
+            })
+            .ok_or_else(|| {
+                SourceError::ApiError("No gateway adopted by the UniFi controller".to_string())
+            })?;
+
+        // first global address of a WAN that is up, skipping link-local ones
+        Ok([gateway.wan1, gateway.wan2]
+            .into_iter()
+            .flatten()
+            .filter(|wan| wan.up)
+            .flat_map(|wan| wan.ipv6)
+            .find(|ip| {
+                ip.parse::<Ipv6Addr>()
+                    .is_ok_and(|ip| !ip.is_unicast_link_local())
+            }))
+    }
+}
+
+#[async_trait]
+impl IpSource for UnifiSource {
+    async fn get_ip(&mut self, version: IpVersion) -> Result<IpAddress, SourceError> {
+        let ip = match version {
+            IpVersion::IPv4 => self.get_ipv4().await?,
+            IpVersion::IPv6 => self.get_ipv6().await?,
+        }
+        .ok_or_else(|| {
+            SourceError::IpNotFoundError(format!("No {:?} address on the UniFi WAN", version))
+        })?;
+
+        validate_ip_address(&ip, &version)?;
+
+        Ok(IpAddress {
+            version,
+            address: ip,
+            last_updated: Some(SystemTime::now()),
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
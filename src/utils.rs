@@ -1,4 +1,9 @@
-use crate::{config::RecordType, dns::DnsError, sources::IpVersion};
+use crate::{
+    config::{Ipv6Host, RecordType},
+    dns::DnsError,
+    sources::{IpVersion, SourceError},
+};
+use std::net::Ipv6Addr;
 
 pub fn get_ip_version(record_type: &RecordType) -> Result<IpVersion, DnsError> {
     match record_type {
@@ -9,3 +14,197 @@ pub fn get_ip_version(record_type: &RecordType) -> Result<IpVersion, DnsError> {
         )),
     }
 }
+
+/// Get the text of the first XML element with the given local name, ignoring namespaces
+pub fn xml_element_text(xml: &str, name: &str) -> Option<String> {
+    let document = roxmltree::Document::parse(xml).ok()?;
+    document
+        .descendants()
+        .find(|node| node.tag_name().name() == name)
+        .and_then(|node| node.text())
+        .map(|text| text.trim().to_string())
+}
+
+/// Escape text for an XML element or attribute value
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Interface identifier configured for a host, only the low 64 bits of a
+/// MAC-derived identifier are set
+pub fn ipv6_interface_id(host: &Ipv6Host) -> Result<Ipv6Addr, SourceError> {
+    match (&host.interface_id, &host.mac) {
+        (Some(interface_id), None) => interface_id.parse().map_err(|_| {
+            SourceError::ValidationError(format!("Invalid interface id: {}", interface_id))
+        }),
+        (None, Some(mac)) => eui64_interface_id(mac),
+        _ => Err(SourceError::ValidationError(
+            "Exactly one of interface_id or mac is required for ipv6_host".to_string(),
+        )),
+    }
+}
+
+/// Modified EUI-64 interface identifier (RFC 4291) of a MAC address
+pub fn eui64_interface_id(mac: &str) -> Result<Ipv6Addr, SourceError> {
+    let octets = mac
+        .split([':', '-'])
+        .map(|octet| u8::from_str_radix(octet, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()
+        .filter(|octets| octets.len() == 6)
+        .ok_or_else(|| SourceError::ValidationError(format!("Invalid MAC address: {}", mac)))?;
+
+    let mut address = [0u8; 16];
+    address[8..11].copy_from_slice(&octets[..3]);
+    address[11] = 0xff;
+    address[12] = 0xfe;
+    address[13..].copy_from_slice(&octets[3..]);
+    // flip the universal/local bit
+    address[8] ^= 0x02;
+
+    Ok(Ipv6Addr::from(address))
+}
+
+/// Turn a device name into a DNS label: lowercase letters, digits and dashes
+pub fn dns_label(name: &str) -> Option<String> {
+    let label = name
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    match label.len() {
+        1..=63 => Some(label),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::Ipv6Prefix;
+
+    fn host(interface_id: Option<&str>, mac: Option<&str>) -> Ipv6Host {
+        Ipv6Host {
+            interface_id: interface_id.map(str::to_string),
+            mac: mac.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn mac_gives_modified_eui64_interface_id() {
+        // universally administered: the U/L bit gets set
+        assert_eq!(
+            eui64_interface_id("00:1a:2b:3c:4d:5e").unwrap(),
+            "::21a:2bff:fe3c:4d5e".parse::<Ipv6Addr>().unwrap()
+        );
+        // locally administered: the U/L bit gets cleared
+        assert_eq!(
+            eui64_interface_id("02-1A-2B-3C-4D-5E").unwrap(),
+            "::1a:2bff:fe3c:4d5e".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_mac_is_rejected() {
+        for mac in [
+            "00:1a:2b:3c:4d",
+            "00:1a:2b:3c:4d:5e:6f",
+            "00:1a:2b:3c:4d:zz",
+            "",
+        ] {
+            assert!(
+                matches!(
+                    eui64_interface_id(mac),
+                    Err(SourceError::ValidationError(_))
+                ),
+                "{}",
+                mac
+            );
+        }
+    }
+
+    #[test]
+    fn host_needs_exactly_one_identifier() {
+        assert_eq!(
+            ipv6_interface_id(&host(Some("::1234:5678:9abc:def0"), None)).unwrap(),
+            "::1234:5678:9abc:def0".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            ipv6_interface_id(&host(None, Some("00:1a:2b:3c:4d:5e"))).unwrap(),
+            "::21a:2bff:fe3c:4d5e".parse::<Ipv6Addr>().unwrap()
+        );
+        assert!(ipv6_interface_id(&host(Some("not an address"), None)).is_err());
+        assert!(ipv6_interface_id(&host(None, None)).is_err());
+        assert!(ipv6_interface_id(&host(Some("::1"), Some("00:1a:2b:3c:4d:5e"))).is_err());
+    }
+
+    #[test]
+    fn interface_id_is_appended_to_the_prefix() {
+        let interface_id = ipv6_interface_id(&host(None, Some("00:1a:2b:3c:4d:5e"))).unwrap();
+        let address = |prefix: &str| {
+            prefix
+                .parse::<Ipv6Prefix>()
+                .unwrap()
+                .host_address(interface_id)
+                .to_string()
+        };
+
+        assert_eq!(
+            address("2001:db8:1:2::/64"),
+            "2001:db8:1:2:21a:2bff:fe3c:4d5e"
+        );
+        // the first subnet of a shorter delegated prefix
+        assert_eq!(
+            address("2001:db8:1:200::/56"),
+            "2001:db8:1:200:21a:2bff:fe3c:4d5e"
+        );
+        assert_eq!(
+            address("2001:db8:1::/48"),
+            "2001:db8:1:0:21a:2bff:fe3c:4d5e"
+        );
+        // a longer prefix keeps its own bits over the interface id
+        assert_eq!(
+            address("2001:db8:1:2:ffff::/80"),
+            "2001:db8:1:2:ffff:2bff:fe3c:4d5e"
+        );
+        assert_eq!(address("2001:db8::1/128"), "2001:db8::1");
+    }
+
+    #[test]
+    fn fixed_suffix_fills_the_host_bits() {
+        let interface_id = ipv6_interface_id(&host(Some("::5:0:0:1"), None)).unwrap();
+        let address = |prefix: &str| {
+            prefix
+                .parse::<Ipv6Prefix>()
+                .unwrap()
+                .host_address(interface_id)
+                .to_string()
+        };
+
+        assert_eq!(address("2001:db8:1:2::/64"), "2001:db8:1:2:5::1");
+        // bits of the suffix inside the prefix are dropped
+        assert_eq!(address("2001:db8:1:2::/96"), "2001:db8:1:2::1");
+        // host bits of the prefix itself are ignored
+        assert_eq!(address("2001:db8:1:2:ffff::/64"), "2001:db8:1:2:5::1");
+    }
+
+    #[test]
+    fn device_name_is_turned_into_a_label() {
+        assert_eq!(dns_label("NAS Synology").as_deref(), Some("nas-synology"));
+        assert_eq!(
+            dns_label("  iPhone de Léa ").as_deref(),
+            Some("iphone-de-l-a")
+        );
+        assert_eq!(dns_label("--host__01--").as_deref(), Some("host-01"));
+        assert_eq!(dns_label("@@@"), None);
+        assert_eq!(dns_label(""), None);
+        assert_eq!(dns_label(&"a".repeat(63)), Some("a".repeat(63)));
+        assert_eq!(dns_label(&"a".repeat(64)), None);
+    }
+}